@@ -0,0 +1,94 @@
+import json
+import os
+import statistics
+import subprocess
+import time
+
+# Runs a local subnet once without and once with `--batch_changes` and compares the two modes.
+# Build the replica first with `cargo build --release`.
+
+BINARY = "./target/release/consensus_on_demand"
+N = 4
+F = 1
+P = 0
+T = 60
+D = 500
+FICC = True
+BASE_PORT = 40000   # replica i listens on BASE_PORT + 10 * i and serves HTTP on the next port
+
+def replica_port(number):
+    return BASE_PORT + 10 * number
+
+def run_subnet(batch, folder):
+    os.makedirs(folder)
+    ids = {}
+    for number in range(1, N + 1):
+        os.makedirs(f"{folder}/replica_{number}/keys")
+        key_file = f"{folder}/replica_{number}/keys/node.key"
+        output = subprocess.run([BINARY, "--r", str(number), "--key_file", key_file, "--print_peer_id"], capture_output=True, text=True, check=True)
+        ids[number] = output.stdout.strip().splitlines()[-1]
+    for number in range(1, N + 1):
+        with open(f"{folder}/replica_{number}/peers.txt", "w") as file:
+            for other in range(1, N + 1):
+                if other != number:
+                    file.write(f"/ip4/127.0.0.1/tcp/{replica_port(other)}/p2p/{ids[other]}\n")
+
+    processes = []
+    for number in range(1, N + 1):
+        replica_folder = f"{folder}/replica_{number}"
+        command = [
+            BINARY, "--r", str(number), "--n", str(N), "--f", str(F), "--p", str(P), "--t", str(T), "--d", str(D),
+            "--port", str(replica_port(number)), "--key_file", f"{replica_folder}/keys/node.key",
+            "--peers_file", f"{replica_folder}/peers.txt", "--output", f"{replica_folder}/benchmark_results.json",
+        ]
+        if FICC:
+            command.append("--cod")
+        if batch:
+            command.append("--batch_changes")
+        with open(f"{replica_folder}/out.log", "w") as log:
+            processes.append(subprocess.Popen(command, stdout=log, stderr=subprocess.STDOUT))
+    for process in processes:
+        process.wait()
+
+    results = []
+    for number in range(1, N + 1):
+        with open(f"{folder}/replica_{number}/benchmark_results.json") as file:
+            results.append(json.load(file))
+    return results
+
+def seconds(duration):
+    return duration["secs"] + duration["nanos"] / 1000000000
+
+def summarize(results):
+    latencies = sorted(
+        seconds(metrics["latency"])
+        for result in results
+        for metrics in result["finalization_times"].values()
+        if metrics is not None
+    )
+    calls = [sum(subcomponent["calls"] for subcomponent in result["scheduler_metrics"].values()) for result in results]
+    busy_time = [sum(seconds(subcomponent["total_time"]) for subcomponent in result["scheduler_metrics"].values()) for result in results]
+    return {
+        "finalized heights": statistics.mean(len(result["finalization_times"]) for result in results),
+        "mean latency (s)": statistics.mean(latencies) if latencies else float("nan"),
+        "median latency (s)": statistics.median(latencies) if latencies else float("nan"),
+        "p95 latency (s)": latencies[int(0.95 * (len(latencies) - 1))] if latencies else float("nan"),
+        "subcomponent calls": statistics.mean(calls),
+        "subcomponent time (s)": statistics.mean(busy_time),
+        "max incoming queue depth": max(result["queue_metrics"]["max_incoming_depth"] for result in results),
+    }
+
+now = int(time.time())
+summaries = {}
+for batch in [False, True]:
+    mode = "batched" if batch else "unbatched"
+    folder = f'benchmark/batching_{("FICC" if FICC else "ICC")}_{mode}_{N}_{F}_{P}_{D}_{T}_{now}'
+    print(f"\nRunning {mode} subnet with n={N}, f={F} and p={P} for {T} seconds, results in folder {folder}")
+    summaries[mode] = summarize(run_subnet(batch, folder))
+
+print(f'\n{"":<28}{"unbatched":>12}{"batched":>12}{"change":>10}')
+for metric in summaries["unbatched"]:
+    unbatched = summaries["unbatched"][metric]
+    batched = summaries["batched"][metric]
+    change = f"{(batched - unbatched) / unbatched * 100:+.1f}%" if unbatched else ""
+    print(f"{metric:<28}{unbatched:>12.3f}{batched:>12.3f}{change:>10}")
@@ -0,0 +1,28 @@
+use std::process::Command;
+
+// Embed the git revision the replica is built from, so that it can be recorded in the benchmark results.
+// GIT_REVISION can be set when building outside of the git repository.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_REVISION");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+
+    let revision = std::env::var("GIT_REVISION").ok().or_else(|| {
+        let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let mut revision = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        // mark builds containing uncommitted changes
+        let dirty = Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=no"])
+            .output()
+            .map(|output| !output.stdout.is_empty())
+            .unwrap_or(false);
+        if dirty {
+            revision.push_str("-dirty");
+        }
+        Some(revision)
+    });
+    println!("cargo:rustc-env=GIT_REVISION={}", revision.unwrap_or_else(|| "unknown".to_string()));
+}
@@ -1,23 +1,94 @@
-use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
 use std::thread::{Builder as ThreadBuilder, JoinHandle};
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, Mutex, RwLock},
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
 use crate::HeightMetrics;
 use crate::{
+    crypto::Signer,
     consensus_layer::{
         artifacts::{ConsensusMessage, UnvalidatedArtifact},
         height_index::Height,
-        ConsensusProcessor,
+        pool::ConsensusPoolImpl,
+        consensus_subcomponents::stall_detector::StalledRounds,
+        scheduler::SchedulerMetrics,
+        ConsensusProcessor, PoolState,
     },
-    time_source::SysTimeSource,
+    time_source::TimeSource,
+    transport::{Transport, TransportClosed},
     SubnetParams,
 };
 
 struct ProcessRequest;
 
+/// Depth and drops of the queues between the network layer and the consensus layer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QueueMetrics {
+    // max number of incoming artifacts waiting to be inserted in the consensus pool
+    pub max_incoming_depth: usize,
+    // incoming artifacts dropped because the queue of their sender was full, by sender
+    pub incoming_drops: BTreeMap<String, u64>,
+    // max number of locally generated artifacts waiting to be broadcasted
+    pub max_outgoing_depth: usize,
+    // number of times the processing thread had to wait for the network layer because the outgoing queue was full
+    pub outgoing_full: u64,
+    // outgoing artifacts dropped because the queue of the receiving peer was full, by receiver (only by transports which do not wait)
+    pub outgoing_drops: BTreeMap<String, u64>,
+}
+
+// Incoming artifacts waiting to be inserted in the consensus pool. Each peer has its own bounded
+// queue, so that a peer sending too many artifacts only causes its own artifacts to be dropped.
+struct PendingArtifacts {
+    queues: BTreeMap<String, VecDeque<UnvalidatedArtifact<ConsensusMessage>>>,
+    capacity_per_peer: usize,
+}
+
+impl PendingArtifacts {
+    fn new(capacity_per_peer: usize) -> Self {
+        Self {
+            queues: BTreeMap::new(),
+            capacity_per_peer,
+        }
+    }
+
+    // Return false if the artifact is dropped because the queue of the peer is full.
+    fn push(&mut self, peer: String, artifact: UnvalidatedArtifact<ConsensusMessage>) -> bool {
+        let queue = self.queues.entry(peer).or_default();
+        if queue.len() >= self.capacity_per_peer {
+            return false;
+        }
+        queue.push_back(artifact);
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.queues.values().map(|queue| queue.len()).sum()
+    }
+
+    // Take up to `max` artifacts, taking one artifact from each peer in turn.
+    fn take(&mut self, max: usize) -> Vec<UnvalidatedArtifact<ConsensusMessage>> {
+        let mut artifacts = Vec::new();
+        while artifacts.len() < max && !self.queues.is_empty() {
+            for queue in self.queues.values_mut() {
+                if artifacts.len() == max {
+                    break;
+                }
+                if let Some(artifact) = queue.pop_front() {
+                    artifacts.push(artifact);
+                }
+            }
+            self.queues.retain(|_, queue| !queue.is_empty());
+        }
+        artifacts
+    }
+}
+
 // The result of a single 'process_changes' call can result in either:
 // - new changes applied to the state. So 'process_changes' should be
 //   immediately called again.
@@ -29,83 +100,150 @@ pub enum ProcessingResult {
 }
 
 // Manages the life cycle of the client specific artifact processor thread
+// and of the thread receiving the artifacts from the transport
 pub struct ArtifactProcessorManager {
-    // The list of unvalidated artifacts
-    pending_artifacts: Arc<Mutex<Vec<UnvalidatedArtifact<ConsensusMessage>>>>,
     // To send the process requests
     sender_incoming_request: Sender<ProcessRequest>,
-    // Handle for the processing thread
-    handle: Option<JoinHandle<()>>,
+    // Set to stop the processing and receiving threads
+    shutdown: Arc<AtomicBool>,
+    // Handle for the processing thread, which returns the state of the consensus pool when it stops
+    handle: Option<JoinHandle<PoolState>>,
+    // Handle for the thread receiving artifacts from the transport
+    receiving_handle: Option<JoinHandle<()>>,
 }
 
 impl ArtifactProcessorManager {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        replica_number: u8,
+        signer: Signer,
         subnet_params: SubnetParams,
-        time_source: Arc<SysTimeSource>,
-        sender_outgoing_artifact: Sender<ConsensusMessage>,
+        time_source: Arc<dyn TimeSource>,
+        transport: Arc<dyn Transport>,
         finalization_times: Arc<RwLock<BTreeMap<Height, Option<HeightMetrics>>>>,
+        scheduler_metrics: Arc<RwLock<SchedulerMetrics>>,
+        stalled_rounds: Arc<RwLock<StalledRounds>>,
+        queue_metrics: Arc<Mutex<QueueMetrics>>,
+        consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
     ) -> Self {
-        let pending_artifacts = Arc::new(Mutex::new(Vec::new()));
+        let pending_artifacts = Arc::new(Mutex::new(PendingArtifacts::new(subnet_params.queue_capacity)));
+        // a request only wakes up the processing thread, so at most one has to be pending
         let (sender_incoming_request, receiver_incoming_request) =
-            crossbeam_channel::unbounded::<ProcessRequest>();
+            crossbeam_channel::bounded::<ProcessRequest>(1);
 
         let client = Box::new(ConsensusProcessor::new(
-            replica_number,
+            signer,
             subnet_params.clone(),
-            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&time_source),
+            scheduler_metrics,
+            stalled_rounds,
+            consensus_pool,
         ));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let polling_interval = std::time::Duration::from_millis(subnet_params.artifact_manager_polling_interval);
+
+        // Spawn the receiving thread
+        let receiving_handle = {
+            let transport = Arc::clone(&transport);
+            let time_source = Arc::clone(&time_source);
+            let pending_artifacts = pending_artifacts.clone();
+            let sender_incoming_request = sender_incoming_request.clone();
+            let shutdown = shutdown.clone();
+            ThreadBuilder::new()
+                .spawn(move || {
+                    while !shutdown.load(Ordering::SeqCst) {
+                        match transport.receive(polling_interval) {
+                            Ok(Some((peer, artifact))) => {
+                                let artifact = UnvalidatedArtifact::new(artifact, time_source.get_relative_time());
+                                queue_artifact(&pending_artifacts, &queue_metrics, peer, artifact);
+                                request_processing(&sender_incoming_request);
+                            }
+                            Ok(None) => (),
+                            Err(TransportClosed) => return,
+                        }
+                    }
+                })
+                .unwrap()
+        };
 
         // Spawn the processor thread
         let sender_incoming_request_cl = sender_incoming_request.clone();
-        let pending_artifacts_cl = pending_artifacts.clone();
+        let shutdown_cl = shutdown.clone();
 
         let handle = ThreadBuilder::new()
             .spawn(move || {
                 Self::process_messages(
-                    pending_artifacts_cl,
+                    pending_artifacts,
                     time_source,
                     client,
                     sender_incoming_request_cl,
                     receiver_incoming_request,
-                    sender_outgoing_artifact,
+                    transport,
                     finalization_times,
+                    shutdown_cl,
                     subnet_params,
-                );
+                )
             })
             .unwrap();
 
         Self {
-            pending_artifacts,
             sender_incoming_request,
+            shutdown,
             handle: Some(handle),
+            receiving_handle: Some(receiving_handle),
+        }
+    }
+
+    /// Stop the processing and receiving threads and wait for the processing thread to return the state
+    /// of the consensus pool. The artifacts which are still pending are not processed.
+    /// Return None if the threads have already been stopped.
+    pub fn stop(&mut self) -> Option<PoolState> {
+        let handle = self.handle.take()?;
+        self.shutdown.store(true, Ordering::SeqCst);
+        // the thread might have already returned if the transport has been closed
+        self.sender_incoming_request.try_send(ProcessRequest).ok();
+        if let Some(receiving_handle) = self.receiving_handle.take() {
+            // returns within the polling interval
+            receiving_handle.join().ok();
+        }
+        match handle.join() {
+            Ok(pool_state) => Some(pool_state),
+            Err(_) => {
+                println!("\nArtifact processing thread panicked");
+                None
+            }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_messages(
-        pending_artifacts: Arc<Mutex<Vec<UnvalidatedArtifact<ConsensusMessage>>>>,
-        time_source: Arc<SysTimeSource>,
+        pending_artifacts: Arc<Mutex<PendingArtifacts>>,
+        time_source: Arc<dyn TimeSource>,
         client: Box<ConsensusProcessor>,
         sender_incoming_request: Sender<ProcessRequest>,
         receiver_incoming_request: Receiver<ProcessRequest>,
-        sender_outgoing_artifact: Sender<ConsensusMessage>,
+        transport: Arc<dyn Transport>,
         finalization_times: Arc<RwLock<BTreeMap<Height, Option<HeightMetrics>>>>,
+        shutdown: Arc<AtomicBool>,
         subnet_params: SubnetParams,
-    ) {
+    ) -> PoolState {
         // println!("Incoming artifacts thread loop started");
         let recv_timeout = std::time::Duration::from_millis(subnet_params.artifact_manager_polling_interval);
         loop {
             let ret = receiver_incoming_request.recv_timeout(recv_timeout);
+            if shutdown.load(Ordering::SeqCst) {
+                return client.pool_state();
+            }
 
             match ret {
                 Ok(_) | Err(RecvTimeoutError::Timeout) => {
                     time_source.update_time().ok();
 
-                    let artifacts = {
-                        let mut artifacts = Vec::new();
+                    // at most `queue_capacity` artifacts are inserted at each call, so that the processing of
+                    // the artifacts already in the pool is not delayed by a burst of incoming artifacts
+                    let (artifacts, remaining_artifacts) = {
                         let mut received_artifacts = pending_artifacts.lock().unwrap();
-                        std::mem::swap(&mut artifacts, &mut received_artifacts);
-                        artifacts
+                        let artifacts = received_artifacts.take(subnet_params.queue_capacity);
+                        (artifacts, received_artifacts.len())
                     };
 
                     let (adverts, result) = client.process_changes(
@@ -114,28 +252,52 @@ impl ArtifactProcessorManager {
                         Arc::clone(&finalization_times),
                     );
 
-                    if let ProcessingResult::StateChanged = result {
-                        sender_incoming_request
-                            .send(ProcessRequest)
-                            .unwrap_or_else(|err| panic!("Failed to send request: {:?}", err));
+                    if matches!(result, ProcessingResult::StateChanged) || remaining_artifacts > 0 {
+                        request_processing(&sender_incoming_request);
+                    }
+                    for adv in adverts {
+                        // the transport might block until the previous artifacts are sent
+                        if transport.broadcast(adv).is_err() {
+                            // the transport is closed when the replica is stopped
+                            return client.pool_state();
+                        }
                     }
-                    adverts.into_iter().for_each(|adv| {
-                        // use channel to send locally generated artifacts to network layer so that it can broadcast them
-                        sender_outgoing_artifact
-                            .send(adv)
-                            .unwrap_or_else(|err| panic!("Failed to send artifact: {:?}", err));
-                    });
                 }
-                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Disconnected) => return client.pool_state(),
             }
         }
     }
 
-    pub fn on_artifact(&self, artifact: UnvalidatedArtifact<ConsensusMessage>) {
-        let mut pending_artifacts = self.pending_artifacts.lock().unwrap();
-        pending_artifacts.push(artifact);
-        self.sender_incoming_request
-            .send(ProcessRequest)
-            .unwrap_or_else(|err| panic!("Failed to send request: {:?}", err));
+}
+
+// Queue an artifact received from `peer` for processing. The artifact is dropped
+// if too many artifacts received from the same peer are still waiting to be processed.
+fn queue_artifact(
+    pending_artifacts: &Mutex<PendingArtifacts>,
+    queue_metrics: &Mutex<QueueMetrics>,
+    peer: String,
+    artifact: UnvalidatedArtifact<ConsensusMessage>,
+) {
+    let mut pending_artifacts = pending_artifacts.lock().unwrap();
+    let mut queue_metrics = queue_metrics.lock().unwrap();
+    if pending_artifacts.push(peer.clone(), artifact) {
+        queue_metrics.max_incoming_depth = queue_metrics.max_incoming_depth.max(pending_artifacts.len());
+    } else {
+        *queue_metrics.incoming_drops.entry(peer).or_insert(0) += 1;
+    }
+}
+
+impl Drop for ArtifactProcessorManager {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Wake up the processing thread, unless a request is already pending.
+fn request_processing(sender_incoming_request: &Sender<ProcessRequest>) {
+    match sender_incoming_request.try_send(ProcessRequest) {
+        Ok(()) | Err(TrySendError::Full(_)) => (),
+        // the processing thread has stopped, the artifacts are no longer processed
+        Err(TrySendError::Disconnected(_)) => (),
     }
 }
@@ -0,0 +1,137 @@
+//! Replicas taking part in consensus.
+//!
+//! Each replica is identified by a [NodeId] and the [Committee] maps it to the public key used to verify
+//! its signatures and to its identity in the network (ex. the [PeerId](libp2p::PeerId) of its libp2p peer).
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, num::ParseIntError, path::Path, str::FromStr};
+
+use crate::crypto::{CanonicalEncode, PublicKey};
+
+/// Identifier of a replica within the committee (ex. the replica number given with `--r`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(NodeId)
+    }
+}
+
+impl CanonicalEncode for NodeId {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.0.encode_canonical(bytes);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Member {
+    pub public_key: PublicKey,
+    // identity of the replica in the network, None if artifacts are accepted from any peer on its behalf
+    pub network_id: Option<String>,
+}
+
+impl CanonicalEncode for Member {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.public_key.encode_canonical(bytes);
+        self.network_id.encode_canonical(bytes);
+    }
+}
+
+/// Replicas of the subnet, ordered by node id. The rank of the block maker of each height
+/// depends on the position of the replica in the committee.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Committee {
+    members: BTreeMap<NodeId, Member>,
+}
+
+impl Committee {
+    pub fn new(members: BTreeMap<NodeId, Member>) -> Self {
+        Self { members }
+    }
+
+    /// Committee of the replicas with node ids from 1 to `n`, without network identities.
+    /// They sign with the keypairs derived from their node ids, which anyone can derive (see [PublicKey::derived]).
+    pub fn with_size(n: u32) -> Self {
+        Self::new(
+            (1..=n)
+                .map(|id| {
+                    let node_id = NodeId(id);
+                    (
+                        node_id,
+                        Member {
+                            public_key: PublicKey::derived(node_id),
+                            network_id: None,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Load the committee from a JSON file mapping each node id to its public key (hex encoded)
+    /// and optionally to its network identity, ex.
+    /// `{"members": {"1": {"public_key": "ab01...", "network_id": "12D3KooW..."}}}`.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let content = fs::read_to_string(path).expect("can read committee file");
+        serde_json::from_str(&content).expect("valid committee file")
+    }
+
+    pub fn size(&self) -> u32 {
+        self.members.len() as u32
+    }
+
+    pub fn contains(&self, node_id: NodeId) -> bool {
+        self.members.contains_key(&node_id)
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.members.keys().copied()
+    }
+
+    /// Position of the replica in the committee, from 0 to the size of the committee - 1.
+    pub fn position(&self, node_id: NodeId) -> Option<u32> {
+        self.members.keys().position(|id| *id == node_id).map(|position| position as u32)
+    }
+
+    pub fn member(&self, node_id: NodeId) -> Option<&Member> {
+        self.members.get(&node_id)
+    }
+
+    pub fn public_key(&self, node_id: NodeId) -> Option<&PublicKey> {
+        self.members.get(&node_id).map(|member| &member.public_key)
+    }
+
+    pub fn network_id(&self, node_id: NodeId) -> Option<&str> {
+        self.members.get(&node_id).and_then(|member| member.network_id.as_deref())
+    }
+
+    /// Node id of the replica with the given network identity, if it is a member of the committee.
+    pub fn node_id_of(&self, network_id: &str) -> Option<NodeId> {
+        self.members
+            .iter()
+            .find(|(_, member)| member.network_id.as_deref() == Some(network_id))
+            .map(|(node_id, _)| *node_id)
+    }
+
+    /// True if the network identities of the members are known, in which case
+    /// artifacts sent by other peers are not accepted.
+    pub fn has_network_ids(&self) -> bool {
+        self.members.values().any(|member| member.network_id.is_some())
+    }
+}
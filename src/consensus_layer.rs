@@ -1,5 +1,5 @@
 use crate::artifact_manager::ProcessingResult;
-use crate::{SubnetParams, HeightMetrics};
+use crate::{crypto::Signer, SubnetParams, HeightMetrics};
 
 pub mod pool;
 use crate::consensus_layer::pool::ConsensusPoolImpl;
@@ -15,30 +15,72 @@ pub mod pool_reader;
 
 pub mod height_index;
 
+pub mod consensus_mode;
+
+pub mod adaptive_delay;
+
+pub mod finality_proof;
+
+pub mod fast_path_report;
+
+pub mod registry;
+
+pub mod scheduler;
+
 pub mod consensus_subcomponents;
 
 use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 
+use serde::{Deserialize, Serialize};
+
+use self::consensus_subcomponents::{equivocation::EquivocationEvidence, stall_detector::{StalledRound, StalledRounds}};
 use self::height_index::Height;
+use self::scheduler::SchedulerMetrics;
+
+// upper bound on the number of change sets applied by a single call to `process_changes` in batched mode,
+// so that incoming artifacts are still inserted in the pool regularly
+const MAX_BATCHED_CHANGE_SETS: usize = 32;
+
+/// Summary of the consensus pool when the replica is stopped.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PoolState {
+    pub finalized_height: Height,
+    pub notarized_height: Height,
+    // number of artifacts in the validated section of the pool
+    pub validated_artifacts: usize,
+    // number of artifacts received but not validated yet
+    pub unvalidated_artifacts: usize,
+    // replicas proven to have signed conflicting artifacts
+    pub equivocations: Vec<EquivocationEvidence>,
+    // rounds which did not progress within the stall timeout
+    pub stalled_rounds: Vec<StalledRound>,
+}
 
 pub struct ConsensusProcessor {
     consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
     client: Box<ConsensusImpl>,
+    batch_changes: bool,
 }
 
 impl ConsensusProcessor {
     pub fn new(
-        replica_number: u8,
+        signer: Signer,
         subnet_params: SubnetParams,
         time_source: Arc<dyn TimeSource>,
+        scheduler_metrics: Arc<RwLock<SchedulerMetrics>>,
+        stalled_rounds: Arc<RwLock<StalledRounds>>,
+        consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
     ) -> Self {
         Self {
-            consensus_pool: Arc::new(RwLock::new(ConsensusPoolImpl::new())),
+            consensus_pool,
+            batch_changes: subnet_params.batch_changes,
             client: Box::new(ConsensusImpl::new(
-                replica_number,
+                signer,
                 subnet_params,
                 Arc::clone(&time_source) as Arc<_>,
+                scheduler_metrics,
+                stalled_rounds,
             )),
         }
     }
@@ -55,43 +97,73 @@ impl ConsensusProcessor {
                 consensus_pool.insert(artifact)
             }
         }
+        // Each change set is applied to the pool before calling the subcomponents again, as they expect to see
+        // the full state. In batched mode, the subcomponents are called again within the same call as long as
+        // they return changes, instead of requesting a new call through the artifact manager for each change set.
+        let max_change_sets = if self.batch_changes { MAX_BATCHED_CHANGE_SETS } else { 1 };
         let mut adverts = Vec::new();
-        let (change_set, to_broadcast) = {
-            let consensus_pool = self.consensus_pool.read().unwrap();
-            self.client
-                .on_state_change(&*consensus_pool, finalization_times)
-        };
-        let changed = if !change_set.is_empty() {
-            ProcessingResult::StateChanged
-        } else {
-            ProcessingResult::StateUnchanged
-        };
-
-        if to_broadcast == true {
-            for change_action in change_set.iter() {
-                match change_action {
-                    ChangeAction::AddToValidated(to_add) => {
-                        // println!("Broadcasting consensus message to be added: {:?}", to_add);
-                        adverts.push(to_add.to_owned());
-                    }
-                    ChangeAction::MoveToValidated(to_move) => {
-                        // println!("Broadcasting consensus message to be moved: {:?}", to_move);
-                        adverts.push(to_move.to_owned());
+        let mut changed = ProcessingResult::StateUnchanged;
+        for _ in 0..max_change_sets {
+            let (change_set, to_broadcast) = {
+                let consensus_pool = self.consensus_pool.read().unwrap();
+                self.client
+                    .on_state_change(&*consensus_pool, Arc::clone(&finalization_times))
+            };
+            if change_set.is_empty() {
+                changed = ProcessingResult::StateUnchanged;
+                break;
+            }
+            changed = ProcessingResult::StateChanged;
+
+            if to_broadcast == true {
+                for change_action in change_set.iter() {
+                    match change_action {
+                        ChangeAction::AddToValidated(to_add) => {
+                            // println!("Broadcasting consensus message to be added: {:?}", to_add);
+                            adverts.push(to_add.to_owned());
+                        }
+                        ChangeAction::MoveToValidated(to_move) => {
+                            // println!("Broadcasting consensus message to be moved: {:?}", to_move);
+                            adverts.push(to_move.to_owned());
+                        }
+                        // invalid artifacts are never broadcasted
+                        ChangeAction::RemoveFromUnvalidated(_) => (),
                     }
                 }
             }
-        }
 
-        // if !change_set.is_empty() {
-        //     println!("\n########## Processor ##########");
-        //     println!("Applying change set: {:?}", change_set);
-        // }
+            // println!("\n########## Processor ##########");
+            // println!("Applying change set: {:?}", change_set);
 
-        self.consensus_pool
-            .write()
-            .unwrap()
-            .apply_changes(time_source, change_set);
+            self.consensus_pool
+                .write()
+                .unwrap()
+                .apply_changes(time_source, change_set);
+        }
 
         (adverts, changed)
     }
+
+    pub fn pool_state(&self) -> PoolState {
+        let consensus_pool = self.consensus_pool.read().unwrap();
+        PoolState {
+            finalized_height: consensus_pool.finalized_block().map_or(0, |block| block.height),
+            notarized_height: consensus_pool.validated().notarization().max_height().unwrap_or(0),
+            validated_artifacts: consensus_pool.validated().artifacts.len(),
+            unvalidated_artifacts: consensus_pool.unvalidated().artifacts.len(),
+            equivocations: consensus_pool
+                .validated()
+                .equivocation_proof()
+                .height_range()
+                .map_or(vec![], |range| {
+                    consensus_pool
+                        .validated()
+                        .equivocation_proof()
+                        .get_by_height_range(range)
+                        .map(|proof| proof.evidence())
+                        .collect()
+                }),
+            stalled_rounds: self.client.stalled_rounds(),
+        }
+    }
 }
@@ -0,0 +1,65 @@
+//! Delay of the block makers and notaries adapted to the observed network latency.
+//!
+//! The block maker and the notary of rank r wait r times the artifact delay from the start of the round before
+//! proposing or notarizing a block. By default the artifact delay is the one given with `--d`. In adaptive mode,
+//! it is the median duration of the last rounds observed locally, from the first notarization of the previous height
+//! to the first one of the height: in a round whose block of rank 0 is notarized, this is the time for the block
+//! to reach the replicas and for their notarization shares to come back, that is a network round trip. Rounds in which
+//! a block of a higher rank is notarized are not sampled, as they last at least the delay of that rank. The median
+//! is bounded by the minimum and maximum delays given with `--adaptive_delay_min` and `--adaptive_delay_max`.
+
+use crate::SubnetParams;
+
+use super::pool_reader::PoolReader;
+
+// number of recent rounds from which the round trip is estimated
+const ADAPTIVE_DELAY_WINDOW: u64 = 20;
+
+/// Return the artifact delay (in milliseconds) to be used by the block makers and notaries. In adaptive mode,
+/// the static delay is used until a round in which a block of rank 0 is notarized has been observed.
+pub fn get_artifact_delay(pool: &PoolReader<'_>, subnet_params: &SubnetParams) -> u64 {
+    if !subnet_params.adaptive_delay {
+        return subnet_params.artifact_delay;
+    }
+    let notarized_height = pool.get_notarized_height();
+    // the round start of the first height is not known
+    let first_height = std::cmp::max(2, (notarized_height + 1).saturating_sub(ADAPTIVE_DELAY_WINDOW));
+    let round_durations: Vec<u64> = (first_height..=notarized_height)
+        .filter(|h| is_rank_zero_round(pool, *h))
+        .filter_map(|h| pool.get_round_duration(h))
+        .map(|round_duration| round_duration.as_millis() as u64)
+        .collect();
+    bounded_median(round_durations, subnet_params.adaptive_delay_min, subnet_params.adaptive_delay_max)
+        .unwrap_or(subnet_params.artifact_delay)
+}
+
+// true if only blocks of rank 0 are notarized at the height
+fn is_rank_zero_round(pool: &PoolReader<'_>, h: u64) -> bool {
+    let mut notarized_blocks = pool.get_notarized_blocks(h).peekable();
+    notarized_blocks.peek().is_some() && notarized_blocks.all(|block| block.rank == 0)
+}
+
+// median of the round durations bounded by `min` and `max`, None if no round has been observed
+fn bounded_median(mut round_durations: Vec<u64>, min: u64, max: u64) -> Option<u64> {
+    if round_durations.is_empty() {
+        return None;
+    }
+    round_durations.sort_unstable();
+    let median = round_durations[round_durations.len() / 2];
+    Some(median.clamp(min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_is_bounded() {
+        assert_eq!(bounded_median(vec![], 50, 2000), None);
+        assert_eq!(bounded_median(vec![300, 100, 200], 50, 2000), Some(200));
+        // the upper median is taken with an even number of rounds
+        assert_eq!(bounded_median(vec![400, 100, 300, 200], 50, 2000), Some(300));
+        assert_eq!(bounded_median(vec![10, 20, 30], 50, 2000), Some(50));
+        assert_eq!(bounded_median(vec![3000, 5000, 100], 50, 2000), Some(2000));
+    }
+}
@@ -1,13 +1,15 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    crypto::{ConsensusMessageHash, Hashed},
+    committee::NodeId,
+    crypto::{ConsensusMessageHash, CryptoHashDomain, Hashed},
     time_source::Time,
 };
 
 use super::consensus_subcomponents::{
     aggregator::{Finalization, Notarization},
     block_maker::BlockProposal,
+    equivocation::EquivocationProof,
     finalizer::FinalizationShare,
     goodifier::GoodnessArtifact,
     notary::{NotarizationShare, NotarizationShareContent},
@@ -19,6 +21,7 @@ pub type ChangeSet = Vec<ChangeAction>;
 pub enum ChangeAction {
     AddToValidated(ConsensusMessage),
     MoveToValidated(ConsensusMessage),
+    RemoveFromUnvalidated(ConsensusMessage),
 }
 
 impl From<ChangeAction> for ChangeSet {
@@ -46,7 +49,7 @@ pub trait HasTimestamp {
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UnvalidatedArtifact<T> {
     pub message: T,
-    pub peer_id: u8,
+    pub peer_id: Option<NodeId>, // replica which sent the artifact, if known
     pub timestamp: Time,
 }
 
@@ -54,7 +57,7 @@ impl<T> UnvalidatedArtifact<T> {
     pub fn new(artifact: T, timestamp: Time) -> Self {
         Self {
             message: artifact,
-            peer_id: 0,
+            peer_id: None,
             timestamp,
         }
     }
@@ -110,7 +113,36 @@ pub enum ConsensusMessage {
     Notarization(Notarization),
     FinalizationShare(FinalizationShare),
     Finalization(Finalization),
-    GoodnessArtifact(GoodnessArtifact), // does not require to be signed as it is never broadcasted
+    GoodnessArtifact(GoodnessArtifact), // does not require to be signed as it carries the signed acks from which it is derived
+    EquivocationProof(Box<EquivocationProof>), // carries two artifacts signed by the offender, boxed as it is much larger than the others
+}
+
+impl ConsensusMessage {
+    /// Name of the variant, used to group metrics by type of artifact.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConsensusMessage::BlockProposal(_) => "BlockProposal",
+            ConsensusMessage::NotarizationShare(_) => "NotarizationShare",
+            ConsensusMessage::Notarization(_) => "Notarization",
+            ConsensusMessage::FinalizationShare(_) => "FinalizationShare",
+            ConsensusMessage::Finalization(_) => "Finalization",
+            ConsensusMessage::GoodnessArtifact(_) => "GoodnessArtifact",
+            ConsensusMessage::EquivocationProof(_) => "EquivocationProof",
+        }
+    }
+
+    /// Height of the artifact, which determines the committee it is validated against.
+    pub fn height(&self) -> u64 {
+        match self {
+            ConsensusMessage::BlockProposal(value) => value.get_id().height,
+            ConsensusMessage::NotarizationShare(value) => value.get_id().height,
+            ConsensusMessage::Notarization(value) => value.get_id().height,
+            ConsensusMessage::FinalizationShare(value) => value.get_id().height,
+            ConsensusMessage::Finalization(value) => value.get_id().height,
+            ConsensusMessage::GoodnessArtifact(value) => value.get_id().height,
+            ConsensusMessage::EquivocationProof(value) => value.get_id().height,
+        }
+    }
 }
 
 impl ConsensusMessageHashable for ConsensusMessage {
@@ -129,6 +161,7 @@ impl ConsensusMessageHashable for ConsensusMessage {
             ConsensusMessage::FinalizationShare(value) => value.get_cm_hash(),
             ConsensusMessage::Finalization(value) => value.get_cm_hash(),
             ConsensusMessage::GoodnessArtifact(value) => value.get_cm_hash(),
+            ConsensusMessage::EquivocationProof(value) => value.get_cm_hash(),
         }
     }
 
@@ -145,6 +178,36 @@ pub struct ConsensusMessageId {
     pub height: u64,
 }
 
+// Each type of consensus message is hashed with a different domain, so that the
+// hash of a message also identifies its [ConsensusMessageHash] variant.
+impl CryptoHashDomain for BlockProposal {
+    const DOMAIN: &'static str = "ficc-block-proposal";
+}
+
+impl CryptoHashDomain for NotarizationShare {
+    const DOMAIN: &'static str = "ficc-notarization-share";
+}
+
+impl CryptoHashDomain for Notarization {
+    const DOMAIN: &'static str = "ficc-notarization";
+}
+
+impl CryptoHashDomain for FinalizationShare {
+    const DOMAIN: &'static str = "ficc-finalization-share";
+}
+
+impl CryptoHashDomain for Finalization {
+    const DOMAIN: &'static str = "ficc-finalization";
+}
+
+impl CryptoHashDomain for GoodnessArtifact {
+    const DOMAIN: &'static str = "ficc-goodness-artifact";
+}
+
+impl CryptoHashDomain for EquivocationProof {
+    const DOMAIN: &'static str = "ficc-equivocation-proof";
+}
+
 impl ConsensusMessageHashable for BlockProposal {
     fn get_id(&self) -> ConsensusMessageId {
         ConsensusMessageId {
@@ -276,3 +339,24 @@ impl ConsensusMessageHashable for GoodnessArtifact {
         }
     }
 }
+
+impl ConsensusMessageHashable for EquivocationProof {
+    fn get_id(&self) -> ConsensusMessageId {
+        ConsensusMessageId {
+            hash: self.get_cm_hash(),
+            height: self.height(),
+        }
+    }
+
+    fn get_cm_hash(&self) -> ConsensusMessageHash {
+        ConsensusMessageHash::EquivocationProof(Hashed::crypto_hash(self))
+    }
+
+    fn assert(msg: &ConsensusMessage) -> Option<&Self> {
+        if let ConsensusMessage::EquivocationProof(value) = msg {
+            Some(value.as_ref())
+        } else {
+            None
+        }
+    }
+}
@@ -1,49 +1,25 @@
 use std::{
     collections::BTreeMap,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
-use crate::{time_source::TimeSource, SubnetParams, HeightMetrics};
+use crate::{crypto::Signer, time_source::TimeSource, SubnetParams, HeightMetrics};
 
 use super::{
     artifacts::{ChangeAction, ChangeSet, ConsensusMessage},
+    consensus_mode::fast_path_enabled,
     consensus_subcomponents::{
         acknowledger::Acknowledger, aggregator::ShareAggregator, block_maker::BlockMaker,
-        finalizer::Finalizer, goodifier::Goodifier, notary::Notary, validator::Validator,
+        equivocation::EquivocationDetector, finalizer::Finalizer, goodifier::Goodifier, notary::Notary,
+        stall_detector::{StallDetector, StalledRound, StalledRounds}, validator::Validator,
     },
     height_index::Height,
     pool::ConsensusPoolImpl,
     pool_reader::PoolReader,
+    scheduler::{PriorityScheduler, SchedulerMetrics, Subcomponent},
 };
 
-// Rotate on_state_change calls with a round robin schedule to ensure fairness.
-#[derive(Default)]
-pub struct RoundRobin {
-    index: std::cell::RefCell<usize>,
-}
-
-impl RoundRobin {
-    // Call the next function in the given list of calls according to a round
-    // robin schedule. Return as soon as a call returns a non-empty ChangeSet.
-    // Otherwise try calling the next one, and return empty ChangeSet if all
-    // calls from the given list have been tried.
-    pub fn call_next<'a, T>(&self, calls: &[&'a dyn Fn() -> (Vec<T>, bool)]) -> (Vec<T>, bool) {
-        let mut result;
-        let mut to_broadcast;
-        let mut index = self.index.borrow_mut();
-        let mut next = *index;
-        loop {
-            (result, to_broadcast) = calls[next]();
-            next = (next + 1) % calls.len();
-            if !result.is_empty() || *index == next {
-                return (result, to_broadcast);
-            };
-        }
-        *index = next;
-        (result, to_broadcast)
-    }
-}
-
 pub struct ConsensusImpl {
     goodifier: Goodifier,
     acknowledger: Acknowledger,
@@ -52,39 +28,53 @@ pub struct ConsensusImpl {
     notary: Notary,
     aggregator: ShareAggregator,
     validator: Validator,
+    equivocation_detector: EquivocationDetector,
+    stall_detector: StallDetector,
     time_source: Arc<dyn TimeSource>,
-    schedule: RoundRobin,
+    schedule: PriorityScheduler,
     subnet_params: SubnetParams,
 }
 
 impl ConsensusImpl {
     pub fn new(
-        replica_number: u8,
+        signer: Signer,
         subnet_params: SubnetParams,
         time_source: Arc<dyn TimeSource>,
+        scheduler_metrics: Arc<RwLock<SchedulerMetrics>>,
+        stalled_rounds: Arc<RwLock<StalledRounds>>,
     ) -> Self {
+        let replica_number = signer.node_id();
         Self {
             goodifier: Goodifier::new(
                 replica_number,
-                subnet_params.clone(),
                 Arc::clone(&time_source) as Arc<_>,
             ),
-            acknowledger: Acknowledger::new(replica_number, subnet_params.clone()),
-            finalizer: Finalizer::new(replica_number, subnet_params.clone()),
+            acknowledger: Acknowledger::new(replica_number),
+            finalizer: Finalizer::new(signer.clone()),
             block_maker: BlockMaker::new(
-                replica_number,
+                signer.clone(),
                 subnet_params.clone(),
                 Arc::clone(&time_source) as Arc<_>,
             ),
             notary: Notary::new(
+                signer,
+                subnet_params.clone(),
+                Arc::clone(&time_source) as Arc<_>,
+            ),
+            aggregator: ShareAggregator::new(replica_number),
+            validator: Validator::new(
                 replica_number,
                 subnet_params.clone(),
                 Arc::clone(&time_source) as Arc<_>,
             ),
-            aggregator: ShareAggregator::new(replica_number, subnet_params.clone()),
-            validator: Validator::new(Arc::clone(&time_source)),
+            equivocation_detector: EquivocationDetector::new(replica_number),
+            stall_detector: StallDetector::new(
+                replica_number,
+                Arc::clone(&time_source) as Arc<_>,
+                stalled_rounds,
+            ),
             time_source,
-            schedule: RoundRobin::default(),
+            schedule: PriorityScheduler::new(subnet_params.max_starvation, scheduler_metrics),
             subnet_params,
         }
     }
@@ -111,13 +101,16 @@ impl ConsensusImpl {
         // moment it is important to call finalizer first, because otherwise
         // we'll just keep producing notarized blocks indefintely without
         // finalizing anything, due to the above decision of having to return
-        // early. The order of the rest subcomponents decides whom is given
-        // a priority, but it should not affect liveness or correctness.
+        // early. Therefore subcomponents are called in order of priority:
+        // finalization (fast path first), aggregation, notarization, block
+        // making, validation, equivocation detection, stall detection and goodness. The scheduler boosts subcomponents
+        // which are starved for too long, so that none of them is blocked
+        // forever by the ones with higher priority.
 
         let pool_reader = PoolReader::new(pool);
 
         let acknowledge = || {
-            if self.subnet_params.consensus_on_demand == true {
+            if fast_path_enabled(&self.subnet_params) {
                 let change_set = add_all_to_validated(
                     self.acknowledger
                         .on_state_change(&pool_reader, Arc::clone(&finalization_times)),
@@ -159,33 +152,55 @@ impl ConsensusImpl {
 
         let validate = || self.validator.on_state_change(&pool_reader, Arc::clone(&finalization_times));
 
+        let detect_equivocations = || {
+            let change_set = add_all_to_validated(self.equivocation_detector.on_state_change(&pool_reader));
+            // proofs are broadcasted so that all replicas exclude the offender
+            let to_broadcast = true;
+            (change_set, to_broadcast)
+        };
+
+        let detect_stalls = || {
+            let change_set = add_all_to_validated(self.stall_detector.on_state_change(&pool_reader));
+            // the artifacts of a stalled round are gossiped again for the peers which missed them
+            let to_broadcast = true;
+            (change_set, to_broadcast)
+        };
+
         // must be the last component called as it can return the same artifact in multiple iterations
         // running it before the other components might starve them as we break out of the loop
         // as soon as a component returns an artifact
         let goodify = || {
-            if self.subnet_params.consensus_on_demand == true {
+            if fast_path_enabled(&self.subnet_params) {
                 let change_set = add_all_to_validated(self.goodifier.on_state_change(&pool_reader));
-                let to_broadcast = false;
+                // goodness artifacts are broadcasted so that peers can verify them and catch up with acks they missed
+                let to_broadcast = true;
                 return (change_set, to_broadcast);
             } else {
                 return (vec![], false);
             }
         };
 
-        let calls: [&'_ dyn Fn() -> (ChangeSet, bool); 7] = [
-            &acknowledge,
-            &finalize,
-            &aggregate,
-            &notarize,
-            &make_block,
-            &validate,
-            &goodify,
+        let time_budget = Duration::from_millis(self.subnet_params.subcomponent_time_budget);
+        let subcomponents: [Subcomponent<'_, ChangeAction>; 9] = [
+            Subcomponent { name: "acknowledger", time_budget, call: &acknowledge },
+            Subcomponent { name: "finalizer", time_budget, call: &finalize },
+            Subcomponent { name: "aggregator", time_budget, call: &aggregate },
+            Subcomponent { name: "notary", time_budget, call: &notarize },
+            Subcomponent { name: "block_maker", time_budget, call: &make_block },
+            Subcomponent { name: "validator", time_budget, call: &validate },
+            Subcomponent { name: "equivocation_detector", time_budget, call: &detect_equivocations },
+            Subcomponent { name: "stall_detector", time_budget, call: &detect_stalls },
+            Subcomponent { name: "goodifier", time_budget, call: &goodify },
         ];
 
-        let (changeset, to_broadcast) = self.schedule.call_next(&calls);
+        let (changeset, to_broadcast) = self.schedule.call_next(&subcomponents);
 
         (changeset, to_broadcast)
     }
+
+    pub fn stalled_rounds(&self) -> Vec<StalledRound> {
+        self.stall_detector.stalled_rounds()
+    }
 }
 
 fn add_all_to_validated(messages: Vec<ConsensusMessage>) -> ChangeSet {
@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    consensus_layer::{
+        consensus_subcomponents::{
+            block_maker::{genesis_block, Block},
+            notary::NotarizationShareContent,
+        },
+        height_index::Height,
+        pool_reader::PoolReader,
+    },
+    committee::NodeId,
+    crypto::{CanonicalEncode, CryptoHash, CryptoHashOf},
+    SubnetParams,
+};
+
+/// The consensus protocol used to notarize and finalize a block.
+///
+/// The mode of a block is chosen by the proposer of its parent and carried in the payload of the parent.
+/// As the parent is identified by the block itself, all replicas agree on the mode of each block and
+/// all the children of a parent (the only blocks that compete for the fast path) run the same protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ConsensusMode {
+    // original Internet Computer Consensus
+    ICC,
+    // Fast Internet Computer Consensus: acknowledgements allow finalizing a block in the fast path
+    FICC,
+}
+
+impl CanonicalEncode for ConsensusMode {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ConsensusMode::ICC => 0u8.encode_canonical(bytes),
+            ConsensusMode::FICC => 1u8.encode_canonical(bytes),
+        }
+    }
+}
+
+/// Mode used by every height when the mode is not switched at runtime.
+pub fn static_mode(subnet_params: &SubnetParams) -> ConsensusMode {
+    match subnet_params.consensus_on_demand {
+        true => ConsensusMode::FICC,
+        false => ConsensusMode::ICC,
+    }
+}
+
+/// Return true if blocks might be finalized in the fast path, therefore acknowledgements and
+/// "goodness" artifacts have to be processed.
+pub fn fast_path_enabled(subnet_params: &SubnetParams) -> bool {
+    subnet_params.consensus_on_demand || subnet_params.adaptive_consensus
+}
+
+/// Return the mode used to notarize and finalize `block`.
+/// Return None if the mode is switched at runtime and the parent of the block is not in the pool yet.
+pub fn get_block_mode(
+    pool: &PoolReader<'_>,
+    subnet_params: &SubnetParams,
+    block: &Block,
+) -> Option<ConsensusMode> {
+    if !subnet_params.adaptive_consensus {
+        return Some(static_mode(subnet_params));
+    }
+    match block.height {
+        0 | 1 => Some(genesis_block().payload.next_mode),
+        h => pool
+            .get_block(&CryptoHashOf::from(block.parent), h - 1)
+            .ok()
+            .map(|parent| parent.payload.next_mode),
+    }
+}
+
+/// Choose the mode of the children of the block proposed at height `height`, whose own mode is `current_mode`.
+///
+/// The fast path is considered failed at a height if no block received acknowledgements from n-p replicas
+/// (or, for heights running ICC, if no block received notarization shares from n-p replicas, which is what the
+/// acknowledgements would have been). The last height is not considered as its shares might still be in flight.
+/// FICC is abandoned after `mode_switch_threshold` failures within the last `mode_switch_window` heights and
+/// resumed only once the fast path would have succeeded in all of them.
+pub fn choose_next_mode(
+    pool: &PoolReader<'_>,
+    subnet_params: &SubnetParams,
+    height: Height,
+    current_mode: ConsensusMode,
+) -> ConsensusMode {
+    if !subnet_params.adaptive_consensus {
+        return static_mode(subnet_params);
+    }
+    let last_height = height.saturating_sub(2);
+    if last_height < subnet_params.mode_switch_window {
+        // not enough history to decide
+        return current_mode;
+    }
+    let failures = (last_height - subnet_params.mode_switch_window + 1..=last_height)
+        .filter(|h| !fast_path_would_succeed(pool, *h))
+        .count() as u64;
+    match current_mode {
+        ConsensusMode::FICC if failures >= subnet_params.mode_switch_threshold => {
+            println!("\nSwitching to ICC from height: {} as the fast path failed at {} of the last {} heights", height + 1, failures, subnet_params.mode_switch_window);
+            ConsensusMode::ICC
+        }
+        ConsensusMode::ICC if failures == 0 => {
+            println!("\nSwitching to FICC from height: {} as the fast path would have succeeded at the last {} heights", height + 1, subnet_params.mode_switch_window);
+            ConsensusMode::FICC
+        }
+        mode => mode,
+    }
+}
+
+fn fast_path_would_succeed(pool: &PoolReader<'_>, h: Height) -> bool {
+    let subnet_params = pool.get_subnet_params(h);
+    let supporters_by_block = pool.get_notarization_shares(h).fold(
+        BTreeMap::<CryptoHash, BTreeSet<NodeId>>::new(),
+        |mut supporters_by_block, share| {
+            let block = match share.content {
+                NotarizationShareContent::COD(content) if content.is_ack => Some(content.block),
+                NotarizationShareContent::COD(_) => None,
+                NotarizationShareContent::ICC(content) => Some(content.block),
+            };
+            if let Some(block) = block {
+                supporters_by_block
+                    .entry(*block.get_ref())
+                    .or_default()
+                    .insert(share.signature.signer);
+            }
+            supporters_by_block
+        },
+    );
+    supporters_by_block.values().any(|supporters| {
+        supporters.len()
+            >= (subnet_params.total_nodes_number - subnet_params.disagreeing_nodes_number) as usize
+    })
+}
@@ -4,4 +4,6 @@ pub mod finalizer;
 pub mod validator;
 pub mod aggregator;
 pub mod acknowledger;
-pub mod goodifier;
\ No newline at end of file
+pub mod goodifier;
+pub mod equivocation;
+pub mod stall_detector;
\ No newline at end of file
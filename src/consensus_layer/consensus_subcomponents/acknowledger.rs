@@ -9,33 +9,32 @@ use crate::{
         artifacts::ConsensusMessage,
         consensus_subcomponents::{
             aggregator::{
-                aggregate, Finalization, FinalizationContent, Notarization, NotarizationContent,
+                aggregate, AggregatedSignature, Finalization, FinalizationContent, Notarization, NotarizationContent,
             },
             notary::NotarizationShareContent,
         },
         height_index::Height,
         pool_reader::PoolReader,
     },
-    crypto::Signed,
-    SubnetParams, HeightMetrics, FinalizationType,
+    committee::NodeId,
+    crypto::{Signature, Signed},
+    HeightMetrics, FinalizationType,
 };
 
 /// A finalization share is a multi-signature share on a finalization content.
 /// If sufficiently many replicas create finalization shares, the shares can be
 /// aggregated into a full finalization.
-pub type FinalizationShare = Signed<FinalizationShareContent, u8>;
+pub type FinalizationShare = Signed<FinalizationShareContent, Signature>;
 
 pub struct Acknowledger {
-    node_id: u8,
-    subnet_params: SubnetParams,
+    node_id: NodeId,
 }
 
 impl Acknowledger {
     #[allow(clippy::too_many_arguments)]
-    pub fn new(node_id: u8, subnet_params: SubnetParams) -> Self {
+    pub fn new(node_id: NodeId) -> Self {
         Self {
             node_id,
-            subnet_params,
         }
     }
 
@@ -51,6 +50,7 @@ impl Acknowledger {
         // check heights in which it is still possible for a block to be FP-finalized
         // even if it was already notarized (happens if F > P) 
         for height in finalized_height+1..=notarized_height+1 {
+            let subnet_params = pool.get_subnet_params(height);
             let notarization_shares = pool.get_notarization_shares(height);
             let grouped_shares = aggregate(notarization_shares);
             let fp_pair_at_height: Vec<ConsensusMessage> = grouped_shares
@@ -60,18 +60,20 @@ impl Acknowledger {
                         // CoD rule 2: acknowledge (FP-finalize) only blocks whose parent is finalized
                         if notarization_content.is_ack == true
                             && committee.len()
-                                >= (self.subnet_params.total_nodes_number
-                                    - self.subnet_params.disagreeing_nodes_number)
+                                >= (subnet_params.total_nodes_number
+                                    - subnet_params.disagreeing_nodes_number)
                                     as usize
                             && is_parent_finalized(pool, &notarization_content)
                         {
-                            println!("\nAcknowledgement of block with hash: {} at height {} by committee: {:?}", notarization_content.block.get_ref(), notarization_content.height, committee);
+                            println!("\nAcknowledgement of block with hash: {} at height {} by committee: {:?}", notarization_content.block.get_ref(), notarization_content.height, committee.iter().map(|ack| ack.signer).collect::<Vec<_>>());
                             if let Some(finalization_time) =
                                 pool.get_finalization_time(notarization_content.height)
                             {
                                 let height_metrics = HeightMetrics {
                                     latency: finalization_time,
                                     fp_finalization: FinalizationType::FP,
+                                    phases: pool.get_phase_timestamps(notarization_content.height, self.node_id, subnet_params),
+                                    end_to_end_latency: pool.get_end_to_end_latency(&notarization_content.block, notarization_content.height),
                                 };
 
                                 finalization_times
@@ -84,29 +86,47 @@ impl Acknowledger {
                             None
                         }
                         .map(|notarization_content| {
+                            let signature = AggregatedSignature::COD {
+                                parent_hash: notarization_content.block_parent_hash,
+                                acks: committee,
+                                non_acks: Default::default(),
+                            };
                             // if a block is acknowledged (>= n-f acks) it must be the only G child
                             // therefore, we can send the notarization even before checking whether it is G or not
                             // as we know it will be as soon as the 'goodifier' component is run
-                            vec![
-                                ConsensusMessage::Notarization(Notarization {
-                                    content: NotarizationContent {
-                                        height: notarization_content.height,
-                                        block: notarization_content.block.clone(),
-                                    },
-
-                                    signature: 0, // committee signature
-                                }),
-                                ConsensusMessage::Finalization(Finalization {
-                                    content: FinalizationContent {
-                                        height: notarization_content.height,
-                                        block: notarization_content.block,
-                                    },
-                                    signature: 10, // committee signature
-                                }),
-                            ]
+                            let notarization_content = NotarizationContent {
+                                height: notarization_content.height,
+                                block: notarization_content.block,
+                            };
+                            // the block might already be notarized by the aggregator, with the signatures of other shares
+                            let is_notarized = pool
+                                .pool()
+                                .validated()
+                                .notarization()
+                                .get_by_height(notarization_content.height)
+                                .any(|notarization| notarization.content == notarization_content);
+                            let finalization = ConsensusMessage::Finalization(Finalization {
+                                content: FinalizationContent {
+                                    height: notarization_content.height,
+                                    block: notarization_content.block.clone(),
+                                },
+                                signature: signature.clone(),
+                            });
+                            if is_notarized {
+                                vec![finalization]
+                            } else {
+                                vec![
+                                    ConsensusMessage::Notarization(Notarization {
+                                        content: notarization_content,
+                                        signature,
+                                    }),
+                                    finalization,
+                                ]
+                            }
                         })
                     } else {
-                        panic!("acknowledger called while running original IC consensus");
+                        // blocks whose mode is ICC can only be finalized by the finalizer
+                        None
                     }
                 })
                 .flatten()
@@ -123,7 +143,7 @@ fn is_parent_finalized(
     pool: &PoolReader<'_>,
     notarization_content: &NotarizationShareContentCOD,
 ) -> bool {
-    let parent_hash = notarization_content.block_parent_hash.clone();
+    let parent_hash = notarization_content.block_parent_hash;
     let parent_height = notarization_content.height - 1;
     if parent_height == 0 {
         return true; // genesis block is finalized
@@ -12,11 +12,13 @@ use crate::consensus_layer::consensus_subcomponents::goodifier::{
 };
 use crate::consensus_layer::height_index::Height;
 use crate::consensus_layer::{artifacts::ConsensusMessage, pool_reader::PoolReader};
-use crate::crypto::{CryptoHashOf, Signed};
-use crate::{SubnetParams, HeightMetrics, FinalizationType};
+use crate::committee::{Committee, NodeId};
+use crate::crypto::{verify_signature, CanonicalEncode, CryptoHash, CryptoHashOf, Signature, SignatureError, Signed};
+use crate::{HeightMetrics, FinalizationType, SubnetParams};
 
 use super::block_maker::Block;
-use super::notary::{NotarizationShareContent, NotarizationShareContentCOD};
+use super::finalizer::FinalizationShareContent;
+use super::notary::{NotarizationShareContent, NotarizationShareContentCOD, NotarizationShareContentICC};
 
 // NotarizationContent holds the values that are signed in a notarization
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -34,7 +36,16 @@ impl NotarizationContent {
     }
 }
 
-pub type Notarization = Signed<NotarizationContent, u8>;
+impl CanonicalEncode for NotarizationContent {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.height.encode_canonical(bytes);
+        self.block.encode_canonical(bytes);
+    }
+}
+
+/// A notarization carries the signatures of n - f notarization shares on a NotarizationContent
+/// (or of n - p acknowledgements if it is created by the acknowledger).
+pub type Notarization = Signed<NotarizationContent, AggregatedSignature>;
 
 /// FinalizationContent holds the values that are signed in a finalization
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -49,21 +60,165 @@ impl FinalizationContent {
     }
 }
 
-/// A finalization is a multi-signature on a FinalizationContent. A finalization
+impl CanonicalEncode for FinalizationContent {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.height.encode_canonical(bytes);
+        self.block.encode_canonical(bytes);
+    }
+}
+
+/// A finalization carries the signatures of n - f finalization shares on a FinalizationContent
+/// (or of n - p acknowledgements if it is created by the acknowledger). A finalization
 /// proves that the block identified by the block hash in the finalization
 /// content (and the block chain it implies) is agreed upon.
-pub type Finalization = Signed<FinalizationContent, u8>;
+pub type Finalization = Signed<FinalizationContent, AggregatedSignature>;
+
+/// Signatures of the shares aggregated into a notarization or a finalization. Ed25519 signatures cannot be
+/// aggregated into a multi-signature, therefore the signatures of the individual replicas are kept, from which
+/// the verifier rebuilds the signed contents of the shares.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum AggregatedSignature {
+    // signatures of notarization shares when only IC Consensus is used
+    ICC(BTreeSet<Signature>),
+    // signatures of notarization shares on a block whose parent is `parent_hash` when Consensus on Demand is used,
+    // a replica which sent both an acknowledgement and a share for the block only being counted among the acknowledgements
+    COD {
+        parent_hash: CryptoHash,
+        acks: BTreeSet<Signature>,
+        non_acks: BTreeSet<Signature>,
+    },
+    // signatures of finalization shares
+    Finalization(BTreeSet<Signature>),
+}
+
+impl CanonicalEncode for AggregatedSignature {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        match self {
+            AggregatedSignature::ICC(signatures) => {
+                0u8.encode_canonical(bytes);
+                signatures.encode_canonical(bytes);
+            }
+            AggregatedSignature::COD { parent_hash, acks, non_acks } => {
+                1u8.encode_canonical(bytes);
+                parent_hash.encode_canonical(bytes);
+                acks.encode_canonical(bytes);
+                non_acks.encode_canonical(bytes);
+            }
+            AggregatedSignature::Finalization(signatures) => {
+                2u8.encode_canonical(bytes);
+                signatures.encode_canonical(bytes);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidAggregate {
+    InvalidSignature(SignatureError),
+    DuplicateSigner(NodeId),
+    // the signatures are not of shares which can be aggregated into the artifact (ex. finalization shares in a notarization)
+    UnexpectedShares,
+    NotEnoughSignatures { signatures: usize, threshold: usize },
+}
+
+impl AggregatedSignature {
+    /// Replicas whose signatures are aggregated.
+    pub fn signers(&self) -> BTreeSet<NodeId> {
+        match self {
+            AggregatedSignature::ICC(signatures) | AggregatedSignature::Finalization(signatures) => {
+                signatures.iter().map(|signature| signature.signer).collect()
+            }
+            AggregatedSignature::COD { acks, non_acks, .. } => acks.iter().chain(non_acks).map(|signature| signature.signer).collect(),
+        }
+    }
+
+    // Check that each signature is created by a different member of the committee on the share of its kind on the
+    // block `block` at `height`, and return the number of signers.
+    fn verify(&self, committee: &Committee, height: Height, block: &CryptoHashOf<Block>) -> Result<usize, InvalidAggregate> {
+        let mut signers = BTreeSet::new();
+        let mut check = |signature: &Signature, result: Result<(), SignatureError>| {
+            if !signers.insert(signature.signer) {
+                return Err(InvalidAggregate::DuplicateSigner(signature.signer));
+            }
+            result.map_err(InvalidAggregate::InvalidSignature)
+        };
+        match self {
+            AggregatedSignature::ICC(signatures) => {
+                let content = NotarizationShareContent::ICC(NotarizationShareContentICC::new(height, block.clone(), None));
+                for signature in signatures {
+                    check(signature, verify_signature(committee, &content, signature))?;
+                }
+            }
+            AggregatedSignature::COD { parent_hash, acks, non_acks } => {
+                for (signatures, is_ack) in [(acks, true), (non_acks, false)] {
+                    let content = NotarizationShareContent::COD(NotarizationShareContentCOD::new(
+                        height,
+                        block.clone(),
+                        *parent_hash,
+                        Some(is_ack),
+                    ));
+                    for signature in signatures {
+                        check(signature, verify_signature(committee, &content, signature))?;
+                    }
+                }
+            }
+            AggregatedSignature::Finalization(signatures) => {
+                let content = FinalizationShareContent::new(height, block.clone());
+                for signature in signatures {
+                    check(signature, verify_signature(committee, &content, signature))?;
+                }
+            }
+        }
+        Ok(signers.len())
+    }
+}
+
+fn check_threshold(signatures: usize, threshold: usize) -> Result<(), InvalidAggregate> {
+    if signatures < threshold {
+        return Err(InvalidAggregate::NotEnoughSignatures { signatures, threshold });
+    }
+    Ok(())
+}
+
+/// Check that a notarization carries the signatures of n - f notarization shares on its block, or of n - p
+/// acknowledgements if it is created by the acknowledger, each one by a different member of the committee.
+pub fn verify_notarization(subnet_params: &SubnetParams, notarization: &Notarization) -> Result<(), InvalidAggregate> {
+    let total_nodes_number = subnet_params.total_nodes_number as usize;
+    let notarization_threshold = total_nodes_number - subnet_params.byzantine_nodes_number as usize;
+    let acks_threshold = total_nodes_number - subnet_params.disagreeing_nodes_number as usize;
+    let threshold = match &notarization.signature {
+        AggregatedSignature::ICC(_) => notarization_threshold,
+        AggregatedSignature::COD { acks, .. } if acks.len() >= acks_threshold => std::cmp::min(notarization_threshold, acks_threshold),
+        AggregatedSignature::COD { .. } => notarization_threshold,
+        AggregatedSignature::Finalization(_) => return Err(InvalidAggregate::UnexpectedShares),
+    };
+    let signers = notarization.signature.verify(&subnet_params.committee, notarization.content.height, &notarization.content.block)?;
+    check_threshold(signers, threshold)
+}
+
+/// Check that a finalization carries the signatures of n - f finalization shares on its block, or of n - p
+/// acknowledgements (CoD rule 2) if it is created by the acknowledger, each one by a different member of the committee.
+pub fn verify_finalization(subnet_params: &SubnetParams, finalization: &Finalization) -> Result<(), InvalidAggregate> {
+    let total_nodes_number = subnet_params.total_nodes_number as usize;
+    let threshold = match &finalization.signature {
+        AggregatedSignature::Finalization(_) => total_nodes_number - subnet_params.byzantine_nodes_number as usize,
+        AggregatedSignature::COD { non_acks, .. } if non_acks.is_empty() => {
+            total_nodes_number - subnet_params.disagreeing_nodes_number as usize
+        }
+        _ => return Err(InvalidAggregate::UnexpectedShares),
+    };
+    let signers = finalization.signature.verify(&subnet_params.committee, finalization.content.height, &finalization.content.block)?;
+    check_threshold(signers, threshold)
+}
 
 pub struct ShareAggregator {
-    node_id: u8,
-    subnet_params: SubnetParams,
+    node_id: NodeId,
 }
 
 impl ShareAggregator {
-    pub fn new(node_id: u8, subnet_params: SubnetParams) -> Self {
+    pub fn new(node_id: NodeId) -> Self {
         Self {
             node_id,
-            subnet_params,
         }
     }
 
@@ -84,6 +239,7 @@ impl ShareAggregator {
     /// Attempt to construct `Notarization`s at `notarized_height + 1`
     fn aggregate_notarization_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
         let height = pool.get_notarized_height() + 1;
+        let subnet_params = pool.get_subnet_params(height);
         let notarization_shares = pool.get_notarization_shares(height);
         let grouped_shares_separated_from_acks = aggregate(notarization_shares); // in case CoD is used, shares and acks for the same proposal are in two separate entries
         // println!("Grouped shares separated from acks {:?}", grouped_shares_separated_from_acks);
@@ -91,7 +247,7 @@ impl ShareAggregator {
         // println!("Grouped shares: {:?}", grouped_shares);
         let notarizations = grouped_shares
             .into_iter()
-            .filter_map(|(notary_content, shares)| {
+            .filter_map(|(notary_content, signature)| {
                 let notary_content = match notary_content {
                     NotarizationShareContent::COD(notary_content) => NotarizationContent {
                         height: notary_content.height,
@@ -102,28 +258,25 @@ impl ShareAggregator {
                         block: notary_content.block,
                     },
                 };
-                if shares.len()
-                    >= (self.subnet_params.total_nodes_number
-                        - self.subnet_params.byzantine_nodes_number) as usize
+                if signature.signers().len()
+                    >= (subnet_params.total_nodes_number
+                        - subnet_params.byzantine_nodes_number) as usize
                 {
-                    if self.subnet_params.consensus_on_demand {
-                        // println!("\nBlock with hash: {} received at least n-f notarization shares", notary_content.block.get_ref());
-                        let block = get_block_by_hash_and_height(
-                            pool,
-                            &notary_content.block,
-                            notary_content.height,
-                        );
-                        // CoD rule 3c: notarize only 'good' blocks
-                        match block_is_good(pool, &block.expect("block must be in pool")) {
-                            true => {
-                                // println!("\nNotarization of block with hash: {} at height {} by committee: {:?}", notary_content.block.get_ref(), notary_content.height, shares);
-                                Some(notary_content.clone())
-                            }
-                            false => None,
+                    // println!("\nBlock with hash: {} received at least n-f notarization shares", notary_content.block.get_ref());
+                    let block = get_block_by_hash_and_height(
+                        pool,
+                        &notary_content.block,
+                        notary_content.height,
+                    );
+                    // CoD rule 3c: notarize only 'good' blocks
+                    // the block might not be validated yet (ex. if its shares were received before the block),
+                    // in which case the shares are aggregated once it is validated
+                    match block {
+                        Some(block) if block_is_good(pool, subnet_params, &block) => {
+                            // println!("\nNotarization of block with hash: {} at height {} by committee: {:?}", notary_content.block.get_ref(), notary_content.height, signature.signers());
+                            Some(notary_content.clone())
                         }
-                    } else {
-                        // println!("\nNotarization of block with hash: {} at height {} by committee: {:?}", notary_content.block.get_ref(), notary_content.height, shares);
-                        Some(notary_content)
+                        _ => None,
                     }
                 } else {
                     None
@@ -134,7 +287,7 @@ impl ShareAggregator {
                             height: notary_content.height,
                             block: notary_content.block,
                         },
-                        signature: 0, // committee signature
+                        signature,
                     })
                 })
             })
@@ -155,15 +308,16 @@ impl ShareAggregator {
         grouped_shares
             .into_iter()
             .filter_map(|(finalization_content, shares)| {
+                let subnet_params = pool.get_subnet_params(finalization_content.height);
                 if shares.len()
-                    >= (self.subnet_params.total_nodes_number
-                        - self.subnet_params.byzantine_nodes_number) as usize
+                    >= (subnet_params.total_nodes_number
+                        - subnet_params.byzantine_nodes_number) as usize
                 {
                     println!(
                         "\nFinalization of block with hash: {} at height {} by committee: {:?}",
                         finalization_content.block.get_ref(),
                         finalization_content.height,
-                        shares
+                        shares.iter().map(|share| share.signer).collect::<BTreeSet<_>>()
                     );
                     if let Some(finalization_time) =
                         pool.get_finalization_time(finalization_content.height)
@@ -171,6 +325,8 @@ impl ShareAggregator {
                         let height_metrics = HeightMetrics {
                             latency: finalization_time,
                             fp_finalization: FinalizationType::IC,
+                            phases: pool.get_phase_timestamps(finalization_content.height, self.node_id, subnet_params),
+                            end_to_end_latency: pool.get_end_to_end_latency(&finalization_content.block, finalization_content.height),
                         };
 
                         finalization_times
@@ -178,17 +334,17 @@ impl ShareAggregator {
                             .unwrap()
                             .insert(finalization_content.height, Some(height_metrics));
                     }
-                    Some(finalization_content)
+                    Some((finalization_content, shares))
                 } else {
                     None
                 }
-                .map(|finalization_content| {
+                .map(|(finalization_content, shares)| {
                     ConsensusMessage::Finalization(Finalization {
                         content: FinalizationContent {
                             height: finalization_content.height,
                             block: finalization_content.block,
                         },
-                        signature: 10, // committee signature
+                        signature: AggregatedSignature::Finalization(shares),
                     })
                 })
             })
@@ -197,37 +353,28 @@ impl ShareAggregator {
 }
 
 pub fn aggregate<T: Ord>(
-    shares: Box<dyn Iterator<Item = Signed<T, u8>>>,
-) -> BTreeMap<T, BTreeSet<u8>> {
+    shares: Box<dyn Iterator<Item = Signed<T, Signature>>>,
+) -> BTreeMap<T, BTreeSet<Signature>> {
     shares.fold(
-        BTreeMap::<T, BTreeSet<u8>>::new(),
+        BTreeMap::<T, BTreeSet<Signature>>::new(),
         |mut grouped_shares, share| {
-            match grouped_shares.get_mut(&share.content) {
-                Some(existing) => {
-                    existing.insert(share.signature);
-                }
-                None => {
-                    let mut new_set = BTreeSet::<u8>::new();
-                    new_set.insert(share.signature);
-                    grouped_shares.insert(share.content, new_set);
-                }
-            };
+            grouped_shares.entry(share.content).or_default().insert(share.signature);
             grouped_shares
         },
     )
 }
 
 fn group_shares_and_acks(
-    grouped_shares_separated_from_acks: BTreeMap<NotarizationShareContent, BTreeSet<u8>>,
-) -> BTreeMap<NotarizationShareContent, BTreeSet<u8>> {
+    grouped_shares_separated_from_acks: BTreeMap<NotarizationShareContent, BTreeSet<Signature>>,
+) -> BTreeMap<NotarizationShareContent, AggregatedSignature> {
     // println!("\nGrouped shares separated from acks {:?}", grouped_shares_separated_from_acks);
     // we need to aggregate shares and acks for the same block proposal
     // if there are only acks for a proposal, we might still need to aggregate them into a notarization as
     // the acknowledger might not be able to create an FP-finalization even if it received n-p acks
     // this happens due to rule 2 of CoD which requires the parent of a block to be finalized in order for the block to be FP-finalized
-    let grouped_shares_and_acks = grouped_shares_separated_from_acks.iter().fold(
-        BTreeMap::<NotarizationShareContent, BTreeSet<u8>>::new(),
-        |mut grouped_shares_and_acks, (notary_content, committee)| {
+    let mut grouped_shares_and_acks = grouped_shares_separated_from_acks.into_iter().fold(
+        BTreeMap::<NotarizationShareContent, AggregatedSignature>::new(),
+        |mut grouped_shares_and_acks, (notary_content, signatures)| {
             match notary_content {
                 NotarizationShareContent::COD(notary_content) => {
                     // here we only try to notarize blocks, therefore it is not important whether a notarization share is an acknowledgement or not
@@ -237,15 +384,18 @@ fn group_shares_and_acks(
                             is_ack: false, // set "is_ack" to false fopr each entry so that the acks are grouped with the shares for the same proposal
                             ..notary_content.clone()
                         });
-                    match grouped_shares_and_acks.get_mut(&generic_notary_content) {
-                        Some(grouped_by_proposal) => {
-                            for share in committee {
-                                grouped_by_proposal.insert(share.to_owned());
-                            }
-                        }
-                        None => {
-                            grouped_shares_and_acks
-                                .insert(generic_notary_content.clone(), committee.clone());
+                    let grouped_by_proposal = grouped_shares_and_acks
+                        .entry(generic_notary_content)
+                        .or_insert_with(|| AggregatedSignature::COD {
+                            parent_hash: notary_content.block_parent_hash,
+                            acks: BTreeSet::new(),
+                            non_acks: BTreeSet::new(),
+                        });
+                    if let AggregatedSignature::COD { acks, non_acks, .. } = grouped_by_proposal {
+                        if notary_content.is_ack {
+                            acks.extend(signatures);
+                        } else {
+                            non_acks.extend(signatures);
                         }
                     }
                 }
@@ -253,14 +403,132 @@ fn group_shares_and_acks(
                 // shares for the same proposal are already aggregated by the "aggregate" function
                 NotarizationShareContent::ICC(notary_content) => {
                     grouped_shares_and_acks.insert(
-                        NotarizationShareContent::ICC(notary_content.clone()),
-                        committee.clone(),
+                        NotarizationShareContent::ICC(notary_content),
+                        AggregatedSignature::ICC(signatures),
                     );
                 }
             }
             grouped_shares_and_acks
         },
     );
+    // a replica which both acknowledged a proposal and sent a share for it is only counted once
+    for grouped_by_proposal in grouped_shares_and_acks.values_mut() {
+        if let AggregatedSignature::COD { acks, non_acks, .. } = grouped_by_proposal {
+            non_acks.retain(|share| !acks.iter().any(|ack| ack.signer == share.signer));
+        }
+    }
     // println!("Grouped shares and acks {:?}", grouped_shares_and_acks);
     grouped_shares_and_acks
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        committee::Committee,
+        consensus_layer::{consensus_mode::ConsensusMode, consensus_subcomponents::block_maker::{genesis_block, Payload}},
+        crypto::{Hashed, Signer},
+    };
+
+    // n = 4, f = 1, p = 0
+    fn subnet_params() -> SubnetParams {
+        SubnetParams::builder(Committee::with_size(4))
+            .byzantine_nodes_number(1)
+            .disagreeing_nodes_number(0)
+            .build()
+            .expect("valid subnet parameters")
+    }
+
+    fn block() -> Block {
+        Block::new(Hashed::crypto_hash(&genesis_block()), Payload::new(ConsensusMode::FICC, None), 1, 0, None)
+    }
+
+    fn block_hash() -> CryptoHashOf<Block> {
+        CryptoHashOf::from(Hashed::crypto_hash(&block()))
+    }
+
+    fn share_signatures(signers: &[u32], is_ack: bool) -> BTreeSet<Signature> {
+        signers
+            .iter()
+            .map(|id| {
+                let content = NotarizationShareContentCOD::new(1, block_hash(), block().parent, Some(is_ack));
+                Signer::with_derived_key(NodeId(*id)).sign(NotarizationShareContent::COD(content)).signature
+            })
+            .collect()
+    }
+
+    fn notarization(acks: &[u32], non_acks: &[u32]) -> Notarization {
+        Notarization {
+            content: NotarizationContent::new(1, block_hash()),
+            signature: AggregatedSignature::COD {
+                parent_hash: block().parent,
+                acks: share_signatures(acks, true),
+                non_acks: share_signatures(non_acks, false),
+            },
+        }
+    }
+
+    fn finalization(signers: &[u32]) -> Finalization {
+        Finalization {
+            content: FinalizationContent::new(1, block_hash()),
+            signature: AggregatedSignature::Finalization(
+                signers
+                    .iter()
+                    .map(|id| Signer::with_derived_key(NodeId(*id)).sign(FinalizationShareContent::new(1, block_hash())).signature)
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn notarization_needs_shares_of_n_minus_f_members() {
+        assert_eq!(verify_notarization(&subnet_params(), &notarization(&[1], &[2, 3])), Ok(()));
+        assert_eq!(
+            verify_notarization(&subnet_params(), &notarization(&[1], &[2])),
+            Err(InvalidAggregate::NotEnoughSignatures { signatures: 2, threshold: 3 })
+        );
+        assert_eq!(
+            verify_notarization(&subnet_params(), &notarization(&[1], &[2, 5])),
+            Err(InvalidAggregate::InvalidSignature(SignatureError::UnknownSigner(NodeId(5))))
+        );
+        // a replica is either counted among the acknowledgements or among the other shares
+        assert_eq!(
+            verify_notarization(&subnet_params(), &notarization(&[1, 2], &[2])),
+            Err(InvalidAggregate::DuplicateSigner(NodeId(2)))
+        );
+    }
+
+    #[test]
+    fn signatures_of_shares_on_another_block_are_rejected() {
+        let mut notarization = notarization(&[1], &[2, 3]);
+        notarization.content = NotarizationContent::new(1, CryptoHashOf::from(CryptoHash::default()));
+        assert!(matches!(
+            verify_notarization(&subnet_params(), &notarization),
+            Err(InvalidAggregate::InvalidSignature(SignatureError::InvalidSignature { signer: NodeId(1), .. }))
+        ));
+    }
+
+    #[test]
+    fn finalization_needs_finalization_shares_or_n_minus_p_acks() {
+        assert_eq!(verify_finalization(&subnet_params(), &finalization(&[1, 2, 3])), Ok(()));
+        assert_eq!(
+            verify_finalization(&subnet_params(), &finalization(&[1, 2])),
+            Err(InvalidAggregate::NotEnoughSignatures { signatures: 2, threshold: 3 })
+        );
+        // with p = 0, the fast path needs the acknowledgements of all the replicas
+        let acks = |acks: &[u32]| Finalization {
+            content: FinalizationContent::new(1, block_hash()),
+            signature: notarization(acks, &[]).signature,
+        };
+        assert_eq!(verify_finalization(&subnet_params(), &acks(&[1, 2, 3, 4])), Ok(()));
+        assert_eq!(
+            verify_finalization(&subnet_params(), &acks(&[1, 2, 3])),
+            Err(InvalidAggregate::NotEnoughSignatures { signatures: 3, threshold: 4 })
+        );
+        let mixed = Finalization {
+            content: FinalizationContent::new(1, block_hash()),
+            signature: notarization(&[1, 2, 3], &[4]).signature,
+        };
+        assert_eq!(verify_finalization(&subnet_params(), &mixed), Err(InvalidAggregate::UnexpectedShares));
+    }
+}
@@ -3,20 +3,40 @@ use std::{sync::Arc, time::Duration};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
-    crypto::{Hashed, Signed},
-    time_source::TimeSource,
+    consensus_layer::{
+        adaptive_delay::get_artifact_delay,
+        artifacts::ConsensusMessage,
+        consensus_mode::{choose_next_mode, get_block_mode, ConsensusMode},
+        height_index::Height,
+        pool_reader::PoolReader,
+        registry::{check_authorized_update, is_update_pending, AuthorizedRegistryUpdate},
+    },
+    committee::NodeId,
+    crypto::{CanonicalEncode, CryptoHash, CryptoHashDomain, Hashed, Signable, Signature, Signed, Signer, SigningDomain},
+    time_source::{system_time_now, Time, TimeSource},
     SubnetParams,
 };
 
-use super::goodifier::block_is_good;
+use super::{goodifier::block_is_good, stall_detector::is_stalled};
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
-pub struct Payload {}
+pub struct Payload {
+    // mode used to notarize and finalize the children of the block
+    pub next_mode: ConsensusMode,
+    // change of the subnet parameters, applied once the block is finalized (boxed as most blocks do not carry any)
+    pub registry_update: Option<Box<AuthorizedRegistryUpdate>>,
+}
 
 impl Payload {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(next_mode: ConsensusMode, registry_update: Option<AuthorizedRegistryUpdate>) -> Self {
+        Self { next_mode, registry_update: registry_update.map(Box::new) }
+    }
+}
+
+impl CanonicalEncode for Payload {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.next_mode.encode_canonical(bytes);
+        self.registry_update.encode_canonical(bytes);
     }
 }
 
@@ -25,46 +45,84 @@ impl Payload {
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct Block {
     // the parent block that this block extends, forming a block chain
-    pub parent: String,
+    pub parent: CryptoHash,
     // the payload of the block
     pub payload: Payload,
     // the height of the block, which is the height of the parent + 1
     pub height: u64,
     // rank indicates the rank of the block maker that created this block
-    pub rank: u8,
+    pub rank: u32,
+    // system time of the block maker when it created this block, only set if replicas compare their timestamps
+    pub proposer_timestamp: Option<Time>,
 }
 
 impl Block {
     // Create a new block
-    pub fn new(parent: String, payload: Payload, height: u64, rank: u8) -> Self {
+    pub fn new(
+        parent: CryptoHash,
+        payload: Payload,
+        height: u64,
+        rank: u32,
+        proposer_timestamp: Option<Time>,
+    ) -> Self {
         Block {
             parent,
             payload,
             height,
             rank,
+            proposer_timestamp,
         }
     }
 }
 
+impl CanonicalEncode for Block {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.parent.encode_canonical(bytes);
+        self.payload.encode_canonical(bytes);
+        self.height.encode_canonical(bytes);
+        self.rank.encode_canonical(bytes);
+        self.proposer_timestamp.encode_canonical(bytes);
+    }
+}
+
+impl CryptoHashDomain for Block {
+    const DOMAIN: &'static str = "ficc-block";
+}
+
+/// The genesis block, which is the parent of the blocks at height 1.
+/// It has no parent, therefore its parent hash is all zeros.
+/// When the mode is switched at runtime, the blocks at height 1 start with the fast path.
+pub fn genesis_block() -> Block {
+    Block::new(CryptoHash::default(), Payload::new(ConsensusMode::FICC, None), 0, 0, None)
+}
+
 /// HashedBlock contains a Block together with its hash
 pub type HashedBlock = Hashed<Block>;
 
-pub type BlockProposal = Signed<HashedBlock, u8>;
+impl Signable for HashedBlock {
+    fn signing_domain(&self) -> SigningDomain {
+        SigningDomain::BlockProposal
+    }
+}
+
+pub type BlockProposal = Signed<HashedBlock, Signature>;
 
 pub struct RandomBeacon {}
 
 pub struct BlockMaker {
-    node_id: u8,
+    node_id: NodeId,
     subnet_params: SubnetParams,
     time_source: Arc<dyn TimeSource>,
+    signer: Signer,
 }
 
 impl BlockMaker {
-    pub fn new(node_id: u8, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
+    pub fn new(signer: Signer, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
         Self {
-            node_id,
+            node_id: signer.node_id(),
             subnet_params,
             time_source,
+            signer,
         }
     }
 
@@ -72,19 +130,26 @@ impl BlockMaker {
         // println!("\n########## Block maker ##########");
         let my_node_id = self.node_id;
         let (beacon, parent) =
-            get_dependencies(pool, self.subnet_params.consensus_on_demand).unwrap();
+            get_dependencies(pool).unwrap();
         let height: u64 = parent.height + 1;
-        match self.get_block_maker_rank(height, &beacon, my_node_id) {
-            rank => {
+        // the committee of the height might still change until the blocks of the previous epochs are finalized
+        if !pool.is_registry_known(height) {
+            return None;
+        }
+        let subnet_params = pool.get_subnet_params(height);
+        match self.get_block_maker_rank(height, &beacon, my_node_id, subnet_params) {
+            Some(rank) => {
+                // once the round stalls, a proposal is made even if a lower ranked one is available as the latter might never be notarized
                 if !already_proposed(pool, height, my_node_id)
-                    && !self.is_better_block_proposal_available(pool, height, rank)
+                    && (!self.is_better_block_proposal_available(pool, height, rank)
+                        || is_stalled(pool, height, subnet_params, self.time_source.get_relative_time()))
                     && is_time_to_make_block(
                         pool,
                         height,
                         rank,
                         self.time_source.as_ref(),
                         my_node_id,
-                        self.subnet_params.artifact_delay,
+                        subnet_params,
                     )
                 {
                     let block_proposal = self
@@ -96,14 +161,17 @@ impl BlockMaker {
                     None
                 }
             }
+            // the local replica is not a member of the committee at this height
+            None => None,
         }
     }
 
-    fn get_block_maker_rank(&self, height: u64, beacon: &RandomBeacon, my_node_id: u8) -> u8 {
+    fn get_block_maker_rank(&self, height: u64, beacon: &RandomBeacon, my_node_id: NodeId, subnet_params: &SubnetParams) -> Option<u32> {
+        let position = subnet_params.committee.position(my_node_id)?;
         let rank =
-            ((height + my_node_id as u64 - 2) % self.subnet_params.total_nodes_number as u64) as u8;
+            ((height + position as u64 - 1) % subnet_params.total_nodes_number as u64) as u32;
         // println!("Local rank for height {} is: {}", height, rank);
-        rank
+        Some(rank)
     }
 
     /// Return true if the validated pool contains a better (lower ranked) block
@@ -112,7 +180,7 @@ impl BlockMaker {
         &self,
         pool: &PoolReader<'_>,
         height: Height,
-        rank: u8,
+        rank: u32,
     ) -> bool {
         if let Some(block) = find_lowest_ranked_proposals(pool, height).first() {
             return block.content.value.rank < rank;
@@ -124,7 +192,7 @@ impl BlockMaker {
     fn propose_block(
         &self,
         pool: &PoolReader<'_>,
-        rank: u8,
+        rank: u32,
         parent: Block,
     ) -> Option<BlockProposal> {
         let parent_hash = Hashed::crypto_hash(&parent);
@@ -140,16 +208,26 @@ impl BlockMaker {
         &self,
         pool: &PoolReader<'_>,
         parent: Block,
-        parent_hash: String,
+        parent_hash: CryptoHash,
         height: u64,
-        rank: u8,
+        rank: u32,
     ) -> Option<BlockProposal> {
-        let payload = Payload::new();
-        let block = Block::new(parent_hash, payload, height, rank);
-        Some(BlockProposal {
-            signature: self.node_id,
-            content: Hashed::new(block),
-        })
+        let subnet_params = pool.get_subnet_params(height);
+        let current_mode = get_block_mode(pool, pool.get_subnet_params(parent.height), &parent)?;
+        // propose the first update of the local replica which can still take effect and is not carried by an ancestor
+        let registry_update = self
+            .subnet_params
+            .registry_updates
+            .iter()
+            .find(|update| {
+                check_authorized_update(update, height, subnet_params).is_ok()
+                    && is_update_pending(pool, &parent, &update.content)
+            })
+            .cloned();
+        let payload = Payload::new(choose_next_mode(pool, subnet_params, height, current_mode), registry_update);
+        let proposer_timestamp = self.subnet_params.max_clock_skew.map(|_| system_time_now());
+        let block = Block::new(parent_hash, payload, height, rank, proposer_timestamp);
+        Some(self.signer.sign(Hashed::new(block)))
     }
 }
 
@@ -158,7 +236,6 @@ impl BlockMaker {
 // Return None otherwise.
 fn get_dependencies(
     pool: &PoolReader<'_>,
-    is_consensus_on_demand: bool,
 ) -> Option<(RandomBeacon, Block)> {
     let notarized_height = pool.get_notarized_height();
     // println!("Last block notarized at height: {}", notarized_height);
@@ -168,14 +245,10 @@ fn get_dependencies(
     let parent = pool
         .get_notarized_blocks(notarized_height)
         .filter(|block| {
-            if is_consensus_on_demand {
-                // CoD rule 3a: extend only "good" blocks
-                let is_good = block_is_good(pool, &block);
-                // println!("Notarized block {:?} is good: {}", block, is_good);
-                is_good
-            } else {
-                true
-            }
+            // CoD rule 3a: extend only "good" blocks
+            let is_good = block_is_good(pool, pool.get_subnet_params(notarized_height), &block);
+            // println!("Notarized block {:?} is good: {}", block, is_good);
+            is_good
         })
         .min_by(|block1, block2| block1.rank.cmp(&block2.rank));
     match parent {
@@ -183,25 +256,17 @@ fn get_dependencies(
             // println!("Parent block: {:?}", parent);
             Some((RandomBeacon {}, parent))
         }
-        None => Some((
-            RandomBeacon {},
-            Block {
-                parent: String::from("Genesis has no parent"),
-                payload: Payload::new(),
-                height: 0,
-                rank: 0,
-            },
-        )),
+        None => Some((RandomBeacon {}, genesis_block())),
     }
 }
 
 // Return true if this node has already made a proposal at the given height.
-fn already_proposed(pool: &PoolReader<'_>, h: u64, this_node: u8) -> bool {
+fn already_proposed(pool: &PoolReader<'_>, h: u64, this_node: NodeId) -> bool {
     pool.pool()
         .validated()
         .block_proposal()
         .get_by_height(h)
-        .any(|p| p.signature == this_node)
+        .any(|p| p.signature.signer == this_node)
 }
 
 // Return true if the time since round start is greater than the required block
@@ -209,12 +274,12 @@ fn already_proposed(pool: &PoolReader<'_>, h: u64, this_node: u8) -> bool {
 fn is_time_to_make_block(
     pool: &PoolReader<'_>,
     height: u64,
-    rank: u8,
+    rank: u32,
     time_source: &dyn TimeSource,
-    node_id: u8,
-    proposer_delay: u64,
+    node_id: NodeId,
+    subnet_params: &SubnetParams,
 ) -> bool {
-    let block_maker_delay = match get_block_maker_delay(rank, proposer_delay) {
+    let block_maker_delay = match get_block_maker_delay(rank, get_artifact_delay(pool, subnet_params)) {
         Some(delay) => delay,
         _ => return false,
     };
@@ -227,8 +292,8 @@ fn is_time_to_make_block(
             false
         }
         None => {
-            // if there is no previous notarization, node 1 proposes the first block (has rank 0 in the first round)
-            if node_id == 1 && rank == 0 {
+            // if there is no previous notarization, the first replica of the committee proposes the first block (has rank 0 in the first round)
+            if subnet_params.committee.position(node_id) == Some(0) && rank == 0 {
                 return true;
             }
             false
@@ -238,8 +303,8 @@ fn is_time_to_make_block(
 
 /// Calculate the required delay for block making based on the block maker's
 /// rank.
-fn get_block_maker_delay(rank: u8, proposer_delay: u64) -> Option<Duration> {
-    Some(Duration::from_millis(proposer_delay) * rank as u32)
+fn get_block_maker_delay(rank: u32, proposer_delay: u64) -> Option<Duration> {
+    Some(Duration::from_millis(proposer_delay) * rank)
 }
 
 /// Return the validated block proposals with the lowest rank at height `h`, if
@@ -0,0 +1,309 @@
+//! Detection of replicas which sign conflicting artifacts.
+//!
+//! A block maker equivocates if it proposes two different blocks at the same height and a replica
+//! equivocates if it acknowledges two different children of the same parent, or if it sends both an
+//! acknowledgement and a notarization share which is not an acknowledgement for the same block. Honest
+//! replicas never do so, therefore the two conflicting artifacts are a proof of misbehavior which any
+//! replica can verify. Proofs are gossiped to the other replicas, which then ignore the shares of the
+//! offender for `equivocation_exclusion` heights starting from the height of the equivocation.
+//!
+//! A proof can only be forged by a replica which knows the secret key of the offender, therefore exclusion
+//! is only as safe as the keys of the committee: with the keypairs derived from the node ids of the default
+//! committee (see [Committee::with_size]), a malicious replica could exclude an honest one.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    committee::{Committee, NodeId},
+    consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
+    crypto::{verify, CanonicalEncode, CryptoHash, Hashed, SignatureError},
+};
+
+use super::{
+    block_maker::BlockProposal,
+    notary::{NotarizationShare, NotarizationShareContent},
+};
+
+/// Two conflicting artifacts signed by the same replica, ordered by hash so that
+/// replicas detecting the same equivocation create the same proof.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EquivocationProof {
+    // two different blocks proposed by the same block maker at the same height
+    BlockProposals(BlockProposal, BlockProposal),
+    // conflicting notarization shares of the Consensus on Demand variant
+    NotarizationShares(NotarizationShare, NotarizationShare),
+}
+
+impl CanonicalEncode for EquivocationProof {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        match self {
+            EquivocationProof::BlockProposals(first, second) => {
+                0u8.encode_canonical(bytes);
+                first.encode_canonical(bytes);
+                second.encode_canonical(bytes);
+            }
+            EquivocationProof::NotarizationShares(first, second) => {
+                1u8.encode_canonical(bytes);
+                first.encode_canonical(bytes);
+                second.encode_canonical(bytes);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidEquivocationProof {
+    InvalidSignature(SignatureError),
+    BlockHashMismatch,
+    NotConflicting,
+}
+
+/// Summary of an equivocation proof, written to the benchmark results.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EquivocationEvidence {
+    pub offender: NodeId,
+    pub height: Height,
+    pub kind: EquivocationKind,
+    // hashes of the conflicting artifacts
+    pub artifacts: (CryptoHash, CryptoHash),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EquivocationKind {
+    BlockProposal,
+    NotarizationShare,
+}
+
+impl EquivocationProof {
+    pub fn block_proposals(first: BlockProposal, second: BlockProposal) -> Self {
+        if Hashed::crypto_hash(&first) <= Hashed::crypto_hash(&second) {
+            EquivocationProof::BlockProposals(first, second)
+        } else {
+            EquivocationProof::BlockProposals(second, first)
+        }
+    }
+
+    pub fn notarization_shares(first: NotarizationShare, second: NotarizationShare) -> Self {
+        if Hashed::crypto_hash(&first) <= Hashed::crypto_hash(&second) {
+            EquivocationProof::NotarizationShares(first, second)
+        } else {
+            EquivocationProof::NotarizationShares(second, first)
+        }
+    }
+
+    /// Replica which signed both artifacts.
+    pub fn offender(&self) -> NodeId {
+        match self {
+            EquivocationProof::BlockProposals(first, _) => first.signature.signer,
+            EquivocationProof::NotarizationShares(first, _) => first.signature.signer,
+        }
+    }
+
+    /// Height at which the offender equivocated.
+    pub fn height(&self) -> Height {
+        match self {
+            EquivocationProof::BlockProposals(first, _) => first.content.value.height,
+            EquivocationProof::NotarizationShares(first, _) => share_height(first),
+        }
+    }
+
+    pub fn evidence(&self) -> EquivocationEvidence {
+        let (kind, artifacts) = match self {
+            EquivocationProof::BlockProposals(first, second) => (
+                EquivocationKind::BlockProposal,
+                (Hashed::crypto_hash(first), Hashed::crypto_hash(second)),
+            ),
+            EquivocationProof::NotarizationShares(first, second) => (
+                EquivocationKind::NotarizationShare,
+                (Hashed::crypto_hash(first), Hashed::crypto_hash(second)),
+            ),
+        };
+        EquivocationEvidence {
+            offender: self.offender(),
+            height: self.height(),
+            kind,
+            artifacts,
+        }
+    }
+
+    /// Check that both artifacts are signed by the same member of the committee and that they conflict.
+    pub fn verify(&self, committee: &Committee) -> Result<(), InvalidEquivocationProof> {
+        match self {
+            EquivocationProof::BlockProposals(first, second) => {
+                for proposal in [first, second] {
+                    if proposal.content.get_hash() != &Hashed::crypto_hash(&proposal.content.value) {
+                        return Err(InvalidEquivocationProof::BlockHashMismatch);
+                    }
+                    verify(committee, proposal).map_err(InvalidEquivocationProof::InvalidSignature)?;
+                }
+                if !proposals_conflict(first, second) {
+                    return Err(InvalidEquivocationProof::NotConflicting);
+                }
+            }
+            EquivocationProof::NotarizationShares(first, second) => {
+                for share in [first, second] {
+                    verify(committee, share).map_err(InvalidEquivocationProof::InvalidSignature)?;
+                }
+                if !notarization_shares_conflict(first, second) {
+                    return Err(InvalidEquivocationProof::NotConflicting);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn share_height(share: &NotarizationShare) -> Height {
+    match &share.content {
+        NotarizationShareContent::COD(content) => content.height,
+        NotarizationShareContent::ICC(content) => content.height,
+    }
+}
+
+/// Return true if both proposals are signed by the same block maker for different blocks at the same height.
+pub fn proposals_conflict(first: &BlockProposal, second: &BlockProposal) -> bool {
+    first.signature.signer == second.signature.signer
+        && first.content.value.height == second.content.value.height
+        && first.content.get_hash() != second.content.get_hash()
+}
+
+/// Return true if both shares are signed by the same replica and either acknowledge two different children
+/// of the same parent, or are an acknowledgement and a share which is not an acknowledgement for the same block.
+/// Shares of the IC Consensus variant never conflict, as a replica can notarize several blocks at the same height.
+pub fn notarization_shares_conflict(first: &NotarizationShare, second: &NotarizationShare) -> bool {
+    if first.signature.signer != second.signature.signer {
+        return false;
+    }
+    match (&first.content, &second.content) {
+        (NotarizationShareContent::COD(first), NotarizationShareContent::COD(second)) => {
+            first.height == second.height
+                && ((first.is_ack
+                    && second.is_ack
+                    && first.block_parent_hash == second.block_parent_hash
+                    && first.block != second.block)
+                    || (first.block == second.block && first.is_ack != second.is_ack))
+        }
+        _ => false,
+    }
+}
+
+pub struct EquivocationDetector {
+    node_id: NodeId,
+}
+
+impl EquivocationDetector {
+    pub fn new(node_id: NodeId) -> Self {
+        Self { node_id }
+    }
+
+    /// Create a proof for each replica which equivocated at a height which might not be finalized yet,
+    /// unless the pool already contains a proof of its misbehavior at the same height.
+    pub fn on_state_change(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
+        // println!("\n########## Equivocation detector ##########");
+        let finalized_height = pool.get_finalized_height();
+        let notarized_height = pool.get_notarized_height();
+        let mut proofs: Vec<EquivocationProof> = Vec::new();
+        for h in finalized_height..=notarized_height + 1 {
+            for proof in pool.find_equivocations(h) {
+                let already_proven = pool.get_equivocation_proofs(h).any(|existing| existing.offender() == proof.offender())
+                    || proofs.iter().any(|new| new.offender() == proof.offender() && new.height() == h);
+                if !already_proven {
+                    println!(
+                        "\nReplica: {} detected equivocation of replica: {} at height: {}",
+                        self.node_id,
+                        proof.offender(),
+                        h
+                    );
+                    proofs.push(proof);
+                }
+            }
+        }
+        proofs.into_iter().map(|proof| ConsensusMessage::EquivocationProof(Box::new(proof))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus_layer::{
+            consensus_mode::ConsensusMode,
+            consensus_subcomponents::{
+                block_maker::{genesis_block, Block, Payload},
+                notary::{NotarizationShareContentCOD, NotarizationShareContentICC},
+            },
+        },
+        crypto::{CryptoHashOf, Signer},
+    };
+
+    fn block(mode: ConsensusMode) -> Block {
+        Block::new(Hashed::crypto_hash(&genesis_block()), Payload::new(mode, None), 1, 0, None)
+    }
+
+    fn proposal(signer: u32, block: Block) -> BlockProposal {
+        Signer::with_derived_key(NodeId(signer)).sign(Hashed::new(block))
+    }
+
+    // share of `signer` on the child of the genesis block whose children use `mode`
+    fn share(signer: u32, mode: ConsensusMode, is_ack: bool) -> NotarizationShare {
+        let block = block(mode);
+        let content = NotarizationShareContentCOD::new(1, CryptoHashOf::from(Hashed::crypto_hash(&block)), block.parent, Some(is_ack));
+        Signer::with_derived_key(NodeId(signer)).sign(NotarizationShareContent::COD(content))
+    }
+
+    #[test]
+    fn proposals_of_different_blocks_by_the_same_block_maker_conflict() {
+        let first = proposal(1, block(ConsensusMode::FICC));
+        assert!(proposals_conflict(&first, &proposal(1, block(ConsensusMode::ICC))));
+        assert!(!proposals_conflict(&first, &proposal(1, block(ConsensusMode::FICC))));
+        assert!(!proposals_conflict(&first, &proposal(2, block(ConsensusMode::ICC))));
+        let mut higher = block(ConsensusMode::ICC);
+        higher.height = 2;
+        assert!(!proposals_conflict(&first, &proposal(1, higher)));
+    }
+
+    #[test]
+    fn acks_of_different_children_or_ack_and_share_of_the_same_block_conflict() {
+        let ack = share(1, ConsensusMode::FICC, true);
+        // acknowledgements of two children of the same parent
+        assert!(notarization_shares_conflict(&ack, &share(1, ConsensusMode::ICC, true)));
+        // only is_ack differs
+        assert!(notarization_shares_conflict(&ack, &share(1, ConsensusMode::FICC, false)));
+        // a replica can notarize several children
+        assert!(!notarization_shares_conflict(&share(1, ConsensusMode::FICC, false), &share(1, ConsensusMode::ICC, false)));
+        assert!(!notarization_shares_conflict(&ack, &share(2, ConsensusMode::ICC, true)));
+        let icc_share = |mode| {
+            let content = NotarizationShareContentICC::new(1, CryptoHashOf::from(Hashed::crypto_hash(&block(mode))), None);
+            Signer::with_derived_key(NodeId(1)).sign(NotarizationShareContent::ICC(content))
+        };
+        assert!(!notarization_shares_conflict(&icc_share(ConsensusMode::FICC), &icc_share(ConsensusMode::ICC)));
+    }
+
+    #[test]
+    fn proof_is_verified_against_the_committee() {
+        let committee = Committee::with_size(4);
+        let proof = EquivocationProof::notarization_shares(share(1, ConsensusMode::FICC, true), share(1, ConsensusMode::FICC, false));
+        assert_eq!(proof.verify(&committee), Ok(()));
+        assert_eq!(proof.offender(), NodeId(1));
+
+        let proof = EquivocationProof::block_proposals(proposal(2, block(ConsensusMode::FICC)), proposal(2, block(ConsensusMode::ICC)));
+        assert_eq!(proof.verify(&committee), Ok(()));
+
+        let not_conflicting = EquivocationProof::notarization_shares(share(1, ConsensusMode::FICC, false), share(1, ConsensusMode::ICC, false));
+        assert_eq!(not_conflicting.verify(&committee), Err(InvalidEquivocationProof::NotConflicting));
+
+        // replica 2 frames replica 1 by signing a share on its behalf
+        let mut forged = share(2, ConsensusMode::FICC, false);
+        forged.signature.signer = NodeId(1);
+        let framing = EquivocationProof::notarization_shares(share(1, ConsensusMode::FICC, true), forged);
+        assert!(matches!(
+            framing.verify(&committee),
+            Err(InvalidEquivocationProof::InvalidSignature(SignatureError::InvalidSignature { signer: NodeId(1), .. }))
+        ));
+
+        let mut tampered = proposal(2, block(ConsensusMode::ICC));
+        tampered.content.value.rank = 1;
+        let proof = EquivocationProof::block_proposals(proposal(2, block(ConsensusMode::FICC)), tampered);
+        assert_eq!(proof.verify(&committee), Err(InvalidEquivocationProof::BlockHashMismatch));
+    }
+}
@@ -4,8 +4,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
-    crypto::{CryptoHashOf, Hashed, Signed},
-    SubnetParams,
+    committee::NodeId,
+    crypto::{CanonicalEncode, CryptoHashOf, Hashed, Signable, Signature, Signed, Signer, SigningDomain},
 };
 
 use super::{block_maker::Block, goodifier::block_is_good, notary::NotarizationShareContent};
@@ -23,24 +23,37 @@ impl FinalizationShareContent {
     }
 }
 
+impl CanonicalEncode for FinalizationShareContent {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.height.encode_canonical(bytes);
+        self.block.encode_canonical(bytes);
+    }
+}
+
+impl Signable for FinalizationShareContent {
+    fn signing_domain(&self) -> SigningDomain {
+        SigningDomain::FinalizationShare
+    }
+}
+
 /// A finalization share is a multi-signature share on a finalization content.
 /// If sufficiently many replicas create finalization shares, the shares can be
 /// aggregated into a full finalization.
-pub type FinalizationShare = Signed<FinalizationShareContent, u8>;
+pub type FinalizationShare = Signed<FinalizationShareContent, Signature>;
 
 pub struct Finalizer {
-    node_id: u8,
-    subnet_params: SubnetParams,
+    node_id: NodeId,
     prev_finalized_height: RefCell<Height>,
+    signer: Signer,
 }
 
 impl Finalizer {
     #[allow(clippy::too_many_arguments)]
-    pub fn new(node_id: u8, subnet_params: SubnetParams) -> Self {
+    pub fn new(signer: Signer) -> Self {
         Self {
-            node_id,
-            subnet_params,
+            node_id: signer.node_id(),
             prev_finalized_height: RefCell::new(0),
+            signer,
         }
     }
 
@@ -80,10 +93,9 @@ impl Finalizer {
                 &self.pick_block_to_finality_sign(pool, height)?,
             )),
         );
-        // add 10 to make the hash of the finalization share different from the one of the notarization share
-        // needed in order not to overwrite the notarization share for the same block in the artifact pool (indexed by hash)
-        let signature = 10 + self.node_id;
-        Some(FinalizationShare { content, signature })
+        // the hash of the finalization share is different from the one of the notarization share for the same block
+        // as they are computed in different domains, therefore they do not overwrite each other in the artifact pool (indexed by hash)
+        Some(self.signer.sign(content))
     }
 
     /// Attempt to find a notarized block at the given height that this node
@@ -96,11 +108,16 @@ impl Finalizer {
     /// In this case, the the single notarized block is returned. Otherwise,
     /// return `None`
     fn pick_block_to_finality_sign(&self, pool: &PoolReader<'_>, h: Height) -> Option<Block> {
+        // only the members of the committee of height `h` finality sign its blocks
+        if !pool.get_subnet_params(h).committee.contains(self.node_id) {
+            return None;
+        }
+
         // if this replica already created a finalization share for height `h`, we do
         // not need to finality sign a block anymore
         if pool
             .get_finalization_shares(h, h)
-            .any(|share| share.signature == 10 + self.node_id)
+            .any(|share| share.signature.signer == self.node_id)
         {
             return None;
         }
@@ -110,14 +127,9 @@ impl Finalizer {
 
         // Check if we have exactly one notarized block, and if so, determine that block
         let notarized_block = match notarized_blocks.len() {
-            0 => {
-                // If there are no notarized blocks at height `h`, we panic, as we should only
-                // try to finalize heights that are notarized.
-                panic!(
-                    "Trying to finalize height {:?} but no notarized block found",
-                    h
-                );
-            }
+            // the notarization of height `h` might have been received before its block, which is
+            // finality signed once it is validated
+            0 => return None,
             1 => notarized_blocks.remove(0),
             _ => {
                 // if there are multiple fully notarized blocks, there is no chance we reach
@@ -126,11 +138,9 @@ impl Finalizer {
             }
         };
 
-        if self.subnet_params.consensus_on_demand {
-            // CoD rule 3b: send finalization share only for "good" block
-            if !block_is_good(pool, &notarized_block) {
-                return None;
-            }
+        // CoD rule 3b: send finalization share only for "good" block
+        if !block_is_good(pool, pool.get_subnet_params(h), &notarized_block) {
+            return None;
         }
 
         // If notarization shares exists created by this replica at height `h`
@@ -138,12 +148,12 @@ impl Finalizer {
         let other_notarized_shares_exists =
             pool.get_notarization_shares(h).any(|x| match x.content {
                 NotarizationShareContent::COD(share_content) => {
-                    x.signature == self.node_id
+                    x.signature.signer == self.node_id
                         && share_content.block
                             != CryptoHashOf::new(Hashed::crypto_hash(&notarized_block))
                 }
                 NotarizationShareContent::ICC(share_content) => {
-                    x.signature == self.node_id
+                    x.signature.signer == self.node_id
                         && share_content.block
                             != CryptoHashOf::new(Hashed::crypto_hash(&notarized_block))
                 }
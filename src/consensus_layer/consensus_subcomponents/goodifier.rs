@@ -6,39 +6,189 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
-    crypto::{CryptoHashOf, Hashed},
+    consensus_layer::{
+        artifacts::ConsensusMessage,
+        consensus_mode::{get_block_mode, ConsensusMode},
+        height_index::Height,
+        pool_reader::PoolReader,
+    },
+    committee::{Committee, NodeId},
+    crypto::{verify, CanonicalEncode, CryptoHash, CryptoHashOf, Hashed, Signature, SignatureError, Signed},
     time_source::{Time, TimeSource},
     SubnetParams,
 };
 
 use super::{
     block_maker::{Block, BlockProposal},
-    notary::NotarizationShareContent,
+    notary::{NotarizationShare, NotarizationShareContent, NotarizationShareContentCOD},
 };
 
+/// Acknowledgements of the children of a parent, grouped by child. Any replica can verify the signatures
+/// and derive from them which children are good, so that goodness decisions can be checked by peers.
+///
+/// The certificate is gossiped in a goodness artifact rather than attached to each notarization share: it grows
+/// with the number of acknowledgements of the children, so attaching it would make each share up to n signatures
+/// larger, while a goodness artifact is only gossiped when the goodness of the children of a parent changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct GoodnessCertificate {
+    pub children_height: Height,
+    pub parent_hash: CryptoHash,
+    // signatures of the acknowledgements of each child, by hash of the child
+    pub acks: BTreeMap<CryptoHash, BTreeSet<Signature>>,
+}
+
+impl CanonicalEncode for GoodnessCertificate {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.children_height.encode_canonical(bytes);
+        self.parent_hash.encode_canonical(bytes);
+        self.acks.encode_canonical(bytes);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidGoodnessCertificate {
+    InvalidSignature(SignatureError),
+    // the same replica acknowledged several children of the parent
+    DuplicateSigner(NodeId),
+    // the goodness artifact does not match the one derived from its certificate
+    DerivationMismatch,
+}
+
+impl GoodnessCertificate {
+    pub fn new(children_height: Height, parent_hash: CryptoHash) -> Self {
+        Self {
+            children_height,
+            parent_hash,
+            acks: BTreeMap::new(),
+        }
+    }
+
+    /// Add the acknowledgement of `block` signed with `signature`, unless its signer already acknowledged a child.
+    pub fn add(&mut self, block: CryptoHash, signature: Signature) {
+        let signer = signature.signer;
+        if !self.acks.values().flatten().any(|signature| signature.signer == signer) {
+            self.acks.entry(block).or_default().insert(signature);
+        }
+    }
+
+    /// Acknowledgement of `block` by the signer of `signature`, as sent by the notary.
+    fn ack(&self, block: &CryptoHash, signature: &Signature) -> NotarizationShare {
+        Signed {
+            content: NotarizationShareContent::COD(NotarizationShareContentCOD::new(
+                self.children_height,
+                CryptoHashOf::from(*block),
+                self.parent_hash,
+                Some(true),
+            )),
+            signature: signature.clone(),
+        }
+    }
+
+    /// Check that each acknowledgement is signed by a different member of the committee.
+    pub fn verify(&self, committee: &Committee) -> Result<(), InvalidGoodnessCertificate> {
+        let mut signers = BTreeSet::new();
+        for (block, signatures) in &self.acks {
+            for signature in signatures {
+                if !signers.insert(signature.signer) {
+                    return Err(InvalidGoodnessCertificate::DuplicateSigner(signature.signer));
+                }
+                verify(committee, &self.ack(block, signature)).map_err(InvalidGoodnessCertificate::InvalidSignature)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct GoodnessArtifact {
     pub children_height: Height,
-    pub parent_hash: String,
-    pub most_acks_child: String,
+    pub parent_hash: CryptoHash,
+    pub most_acks_child: CryptoHash,
     pub most_acks_child_count: usize,
     total_acks_for_children: usize,
     pub all_children_good: bool,
     pub timestamp: Time,
+    // acknowledgements from which the other fields are derived
+    pub certificate: GoodnessCertificate,
+}
+
+impl CanonicalEncode for GoodnessArtifact {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.children_height.encode_canonical(bytes);
+        self.parent_hash.encode_canonical(bytes);
+        self.most_acks_child.encode_canonical(bytes);
+        self.most_acks_child_count.encode_canonical(bytes);
+        self.total_acks_for_children.encode_canonical(bytes);
+        self.all_children_good.encode_canonical(bytes);
+        self.timestamp.encode_canonical(bytes);
+        self.certificate.encode_canonical(bytes);
+    }
+}
+
+impl GoodnessArtifact {
+    /// Derive which children of the parent are good from the acknowledgements of the certificate:
+    /// all of them if more than f + p acknowledgements are for children other than the one with the most
+    /// acknowledgements, otherwise only the latter once the children received at least n - f acknowledgements.
+    /// Return None if no child is known to be good yet.
+    pub fn from_certificate(certificate: GoodnessCertificate, subnet_params: &SubnetParams, timestamp: Time) -> Option<Self> {
+        let mut goodness_artifact = GoodnessArtifact {
+            children_height: certificate.children_height,
+            parent_hash: certificate.parent_hash,
+            most_acks_child: CryptoHash::default(),
+            most_acks_child_count: 0,
+            total_acks_for_children: 0,
+            all_children_good: false,
+            timestamp,
+            certificate: GoodnessCertificate::default(),
+        };
+        // count total number of acks on children and determine which child is the one with the most acks
+        for (block_hash, acks_for_block) in &certificate.acks {
+            if acks_for_block.len() > goodness_artifact.most_acks_child_count {
+                goodness_artifact.most_acks_child = *block_hash;
+                goodness_artifact.most_acks_child_count = acks_for_block.len();
+            }
+            goodness_artifact.total_acks_for_children += acks_for_block.len();
+        }
+        goodness_artifact.certificate = certificate;
+        if goodness_artifact.total_acks_for_children - goodness_artifact.most_acks_child_count
+            > (subnet_params.byzantine_nodes_number + subnet_params.disagreeing_nodes_number) as usize
+        {
+            goodness_artifact.all_children_good = true;
+            Some(goodness_artifact)
+        } else if goodness_artifact.total_acks_for_children
+            >= (subnet_params.total_nodes_number - subnet_params.byzantine_nodes_number) as usize
+        {
+            Some(goodness_artifact)
+        } else {
+            None
+        }
+    }
+
+    /// Return true if the artifact changes the goodness of the children with respect to `previous`, the latest
+    /// artifact for the same parent: once all children are good, they remain so and the artifact is never updated,
+    /// otherwise it is updated if all children become good or if another child has more acknowledgements.
+    pub fn supersedes(&self, previous: Option<&GoodnessArtifact>) -> bool {
+        match previous {
+            None => true,
+            Some(previous) => {
+                !previous.all_children_good
+                    && (self.all_children_good
+                        || (previous.most_acks_child != self.most_acks_child
+                            && previous.most_acks_child_count < self.most_acks_child_count))
+            }
+        }
+    }
 }
 
 pub struct Goodifier {
-    node_id: u8,
-    subnet_params: SubnetParams,
+    node_id: NodeId,
     time_source: Arc<dyn TimeSource>,
 }
 
 impl Goodifier {
-    pub fn new(node_id: u8, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
+    pub fn new(node_id: NodeId, time_source: Arc<dyn TimeSource>) -> Self {
         Self {
             node_id,
-            subnet_params,
             time_source,
         }
     }
@@ -64,155 +214,43 @@ impl Goodifier {
     fn goodify_height(&self, pool: &PoolReader<'_>, h: Height) -> Vec<ConsensusMessage> {
         // group acks according to the parent of the block they are acknowledging
         // then for each parent group, group acks according to the block they are acknowledging
-        let grouped_acks = pool.get_notarization_shares(h).fold(
-            BTreeMap::<String, BTreeMap<String, BTreeSet<u8>>>::new(),
-            |mut grouped_acks_by_parent, signed_share| {
-                if let NotarizationShareContent::COD(notarization_share) = signed_share.content {
-                    if notarization_share.is_ack {
-                        let ack = notarization_share;
-                        let signature = signed_share.signature;
-                        match grouped_acks_by_parent.get_mut(&ack.block_parent_hash) {
-                            Some(existing_parent_map) => {
-                                match existing_parent_map.get_mut(ack.block.get_ref()) {
-                                    Some(existing_block_set) => {
-                                        existing_block_set.insert(signature);
-                                    }
-                                    None => {
-                                        let mut block_set = BTreeSet::<u8>::new();
-                                        let block_hash = ack.block.get_ref().clone();
-                                        block_set.insert(signature);
-                                        existing_parent_map.insert(block_hash, block_set);
-                                    }
-                                }
-                            }
-                            None => {
-                                let mut grouped_acks_by_block =
-                                    BTreeMap::<String, BTreeSet<u8>>::new();
-                                let mut block_set = BTreeSet::<u8>::new();
-                                let block_hash = ack.block.get_ref().clone();
-                                let block_parent_hash = ack.block_parent_hash.clone();
-                                block_set.insert(signature);
-                                grouped_acks_by_block.insert(block_hash, block_set);
-                                grouped_acks_by_parent
-                                    .insert(block_parent_hash, grouped_acks_by_block);
-                            }
-                        };
-                    }
-                } else {
-                    panic!("goodifier called while running original IC consensus");
-                }
-                grouped_acks_by_parent
-            },
-        );
-        // println!("Grouped acks {:?}", grouped_acks);
-
-        grouped_acks.into_iter().fold(
-            Vec::new(),
-            |mut goodness_consensus_messages_at_height, (parent_hash, grouped_acks_by_block)| {
-                // initialize "goodness" artifact for a particular parent
-                let mut children_goodness_artifact = GoodnessArtifact {
-                    children_height: h,
-                    parent_hash,
-                    most_acks_child: String::from(""),
-                    most_acks_child_count: 0,
-                    total_acks_for_children: 0,
-                    all_children_good: false,
-                    timestamp: self.time_source.get_relative_time(),
-                };
-
-                // count total number of acks on children and determine which child is the one with the most acks
-                for (block_hash, acks_for_block) in grouped_acks_by_block {
-                    let acks_for_current_block_count = acks_for_block.len();
-                    if acks_for_current_block_count
-                        > children_goodness_artifact.most_acks_child_count
-                    {
-                        children_goodness_artifact.most_acks_child = block_hash.clone();
-                        children_goodness_artifact.most_acks_child_count =
-                            acks_for_current_block_count;
-                    }
-                    children_goodness_artifact.total_acks_for_children +=
-                        acks_for_current_block_count;
+        let mut certificates = BTreeMap::<CryptoHash, GoodnessCertificate>::new();
+        for signed_share in pool.get_notarization_shares(h) {
+            if let NotarizationShareContent::COD(notarization_share) = signed_share.content {
+                if notarization_share.is_ack {
+                    certificates
+                        .entry(notarization_share.block_parent_hash)
+                        .or_insert_with(|| GoodnessCertificate::new(h, notarization_share.block_parent_hash))
+                        .add(*notarization_share.block.get_ref(), signed_share.signature);
                 }
+            }
+            // shares of ICC variant are sent for children of parents whose mode is ICC, which are never acknowledged
+        }
+        // println!("Grouped acks {:?}", certificates);
 
+        let subnet_params = pool.get_subnet_params(h);
+        certificates
+            .into_values()
+            .filter_map(|certificate| {
                 // for each parent, check conditions to determine which children are "good"
-                match pool.get_latest_goodness_artifact_for_parent(
+                let children_goodness_artifact = GoodnessArtifact::from_certificate(
+                    certificate,
+                    subnet_params,
+                    self.time_source.get_relative_time(),
+                )?;
+                // if the "goodness" artifact already exists, we must check whether it should be updated
+                let previous_goodness_artifact = pool.get_latest_goodness_artifact_for_parent(
                     &children_goodness_artifact.parent_hash,
                     h,
-                ) {
-                    // if "goodness" artifact does not exist, we check whether it can be created according to currently received acks
-                    None => {
-                        if children_goodness_artifact.total_acks_for_children
-                            - children_goodness_artifact.most_acks_child_count
-                            > (self.subnet_params.byzantine_nodes_number
-                                + self.subnet_params.disagreeing_nodes_number)
-                                as usize
-                        {
-                            // println!("\nAll children of: {} at height: {} are good", children_goodness_artifact.parent_hash, h);
-                            children_goodness_artifact.all_children_good = true;
-                            goodness_consensus_messages_at_height.push(
-                                ConsensusMessage::GoodnessArtifact(
-                                    children_goodness_artifact.clone(),
-                                ),
-                            );
-                        } else if children_goodness_artifact.total_acks_for_children
-                            >= (self.subnet_params.total_nodes_number
-                                - self.subnet_params.byzantine_nodes_number)
-                                as usize
-                        {
-                            // println!("\nFor parent: {} at height: {}, the good child with most acks is: {} and received: {} acks out of: {}", children_goodness_artifact.parent_hash, children_goodness_artifact.children_height-1, children_goodness_artifact.most_acks_child, children_goodness_artifact.most_acks_child_count, children_goodness_artifact.total_acks_for_children);
-                            goodness_consensus_messages_at_height.push(
-                                ConsensusMessage::GoodnessArtifact(
-                                    children_goodness_artifact.clone(),
-                                ),
-                            );
-                        }
-                    }
-                    // if the "goodness" artifact already exists, we must check whether it should be updated
-                    Some(previous_goodness_artifact) => {
-                        // if all children are "good", the "goodness" artifact for this parent does not have to be updated as all children will remain "good"
-                        // and in this case we do not care about which one is the one with the most acks
-                        if !previous_goodness_artifact.all_children_good {
-                            // if all children become "good" we create an updated "goodness" artifact
-                            if children_goodness_artifact.total_acks_for_children
-                                - children_goodness_artifact.most_acks_child_count
-                                > (self.subnet_params.byzantine_nodes_number
-                                    + self.subnet_params.disagreeing_nodes_number)
-                                    as usize
-                            {
-                                // println!("\nAll children of: {} at height: {} are good", children_goodness_artifact.parent_hash, h);
-                                children_goodness_artifact.all_children_good = true;
-                                goodness_consensus_messages_at_height.push(
-                                    ConsensusMessage::GoodnessArtifact(
-                                        children_goodness_artifact.clone(),
-                                    ),
-                                );
-                            } else if children_goodness_artifact.total_acks_for_children
-                                >= (self.subnet_params.total_nodes_number
-                                    - self.subnet_params.byzantine_nodes_number)
-                                    as usize
-                            {
-                                // if the child with most acks is different from the one stored in the previous "goodness" artifact and has more acks
-                                // we create an updated "goodness" child
-                                if previous_goodness_artifact.most_acks_child
-                                    != children_goodness_artifact.most_acks_child
-                                    && previous_goodness_artifact.most_acks_child_count
-                                        < children_goodness_artifact.most_acks_child_count
-                                {
-                                    // println!("\nUpdating good child with most acks: {} for parent: {} at height: {}", children_goodness_artifact.most_acks_child, children_goodness_artifact.parent_hash, children_goodness_artifact.children_height-1);
-                                    goodness_consensus_messages_at_height.push(
-                                        ConsensusMessage::GoodnessArtifact(
-                                            children_goodness_artifact.clone(),
-                                        ),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                };
-
-                goodness_consensus_messages_at_height
-            },
-        )
+                );
+                if children_goodness_artifact.supersedes(previous_goodness_artifact.as_ref()) {
+                    // println!("\nFor parent: {} at height: {}, the good child with most acks is: {} and all children are good: {}", children_goodness_artifact.parent_hash, h - 1, children_goodness_artifact.most_acks_child, children_goodness_artifact.all_children_good);
+                    Some(ConsensusMessage::GoodnessArtifact(children_goodness_artifact))
+                } else {
+                    None
+                }
+            })
+            .collect()
     }
 }
 
@@ -235,7 +273,13 @@ pub fn get_block_by_hash_and_height(
     }
 }
 
-pub fn block_is_good(pool: &PoolReader<'_>, block: &Block) -> bool {
+pub fn block_is_good(pool: &PoolReader<'_>, subnet_params: &SubnetParams, block: &Block) -> bool {
+    // the CoD rules apply only to blocks running FICC, all the other blocks are considered "good"
+    match get_block_mode(pool, subnet_params, block) {
+        Some(ConsensusMode::FICC) => (),
+        Some(ConsensusMode::ICC) => return true,
+        None => return false,
+    }
     // block is one of the children for the latest "goodness" artifact
     // pool.print_goodness_artifacts_at_height(block.height);
     match pool.get_latest_goodness_artifact_for_parent(&block.parent, block.height) {
@@ -244,7 +288,7 @@ pub fn block_is_good(pool: &PoolReader<'_>, block: &Block) -> bool {
             if goodness_artifact.all_children_good {
                 return true;
             }
-            let block_hash = Hashed::crypto_hash(&block);
+            let block_hash = Hashed::crypto_hash(block);
             // println!("Block to be checked: {}", block_hash);
             goodness_artifact.most_acks_child == block_hash
         }
@@ -256,3 +300,78 @@ pub fn block_is_good(pool: &PoolReader<'_>, block: &Block) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Signer;
+    use std::time::Duration;
+
+    // n = 6, f = 1, p = 1: all children are good once more than f + p = 2 acknowledgements are for children other
+    // than the one with the most acknowledgements, otherwise the latter is good once n - f = 5 acknowledgements are received
+    fn subnet_params() -> SubnetParams {
+        SubnetParams::builder(Committee::with_size(6))
+            .byzantine_nodes_number(1)
+            .disagreeing_nodes_number(1)
+            .build()
+            .expect("valid subnet parameters")
+    }
+
+    fn child(index: u8) -> CryptoHash {
+        CryptoHash([index; 32])
+    }
+
+    // acknowledgement of `block` in `certificate` signed by `signer`
+    fn sign_ack(certificate: &GoodnessCertificate, block: &CryptoHash, signer: &Signer) -> Signature {
+        let unsigned = Signature { signer: signer.node_id(), bytes: vec![] };
+        signer.sign(certificate.ack(block, &unsigned).content).signature
+    }
+
+    // certificate in which the replicas `signers[i]` acknowledged the child `i`
+    fn certificate(signers: &[&[u32]]) -> GoodnessCertificate {
+        let mut certificate = GoodnessCertificate::new(1, CryptoHash::default());
+        for (index, signers) in signers.iter().enumerate() {
+            for id in *signers {
+                let signature = sign_ack(&certificate, &child(index as u8), &Signer::with_derived_key(NodeId(*id)));
+                certificate.add(child(index as u8), signature);
+            }
+        }
+        certificate
+    }
+
+    fn goodness(signers: &[&[u32]]) -> Option<GoodnessArtifact> {
+        let certificate = certificate(signers);
+        assert_eq!(certificate.verify(&subnet_params().committee), Ok(()));
+        GoodnessArtifact::from_certificate(certificate, &subnet_params(), Time::from_duration(Duration::ZERO))
+    }
+
+    #[test]
+    fn all_children_are_good_above_f_plus_p_acks_for_other_children() {
+        // 2 acknowledgements for other children than the first one are not enough to make all of them good
+        assert_eq!(goodness(&[&[1, 2], &[3], &[4]]), None);
+        let goodness = goodness(&[&[1, 2], &[3], &[4, 5]]).expect("all children are good");
+        assert!(goodness.all_children_good);
+    }
+
+    #[test]
+    fn child_is_good_with_n_minus_f_acks() {
+        assert_eq!(goodness(&[&[1, 2, 3, 4]]), None);
+        let goodness = goodness(&[&[1, 2, 3, 4, 5]]).expect("the child is good");
+        assert!(!goodness.all_children_good);
+        assert_eq!((goodness.most_acks_child, goodness.most_acks_child_count), (child(0), 5));
+    }
+
+    #[test]
+    fn forged_acknowledgement_is_rejected() {
+        let mut certificate = certificate(&[&[1, 2, 3, 4, 5]]);
+        // replica 6 signs an acknowledgement on behalf of replica 5
+        let forged = sign_ack(&certificate, &child(0), &Signer::with_derived_key(NodeId(6)));
+        let acks = certificate.acks.get_mut(&child(0)).expect("acknowledged child");
+        acks.retain(|signature| signature.signer != NodeId(5));
+        acks.insert(Signature { signer: NodeId(5), ..forged });
+        assert!(matches!(
+            certificate.verify(&subnet_params().committee),
+            Err(InvalidGoodnessCertificate::InvalidSignature(SignatureError::InvalidSignature { signer: NodeId(5), .. }))
+        ));
+    }
+}
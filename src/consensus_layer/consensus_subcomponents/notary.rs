@@ -3,8 +3,15 @@ use std::{sync::Arc, time::Duration};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
-    crypto::{CryptoHashOf, Hashed, Signed},
+    consensus_layer::{
+        adaptive_delay::get_artifact_delay,
+        artifacts::ConsensusMessage,
+        consensus_mode::{get_block_mode, ConsensusMode},
+        height_index::Height,
+        pool_reader::PoolReader,
+    },
+    committee::NodeId,
+    crypto::{CanonicalEncode, CryptoHash, CryptoHashOf, Signable, Signature, Signed, Signer, SigningDomain},
     time_source::TimeSource,
     SubnetParams,
 };
@@ -42,7 +49,7 @@ impl NotarizationShareContentICC {
 pub struct NotarizationShareContentCOD {
     pub height: u64,
     pub block: CryptoHashOf<Block>,
-    pub block_parent_hash: String,
+    pub block_parent_hash: CryptoHash,
     pub is_ack: bool,
 }
 
@@ -50,7 +57,7 @@ impl NotarizationShareContentCOD {
     pub fn new(
         block_height: Height,
         block_hash: CryptoHashOf<Block>,
-        block_parent_hash: String,
+        block_parent_hash: CryptoHash,
         is_ack: Option<bool>,
     ) -> Self {
         Self {
@@ -62,23 +69,71 @@ impl NotarizationShareContentCOD {
     }
 }
 
+impl CanonicalEncode for NotarizationShareContent {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        match self {
+            NotarizationShareContent::COD(share_content) => {
+                0u8.encode_canonical(bytes);
+                share_content.encode_canonical(bytes);
+            }
+            NotarizationShareContent::ICC(share_content) => {
+                1u8.encode_canonical(bytes);
+                share_content.encode_canonical(bytes);
+            }
+        }
+    }
+}
+
+impl Signable for NotarizationShareContent {
+    fn signing_domain(&self) -> SigningDomain {
+        match self {
+            NotarizationShareContent::COD(share_content) => {
+                if share_content.is_ack {
+                    SigningDomain::NotarizationShareAck
+                } else {
+                    SigningDomain::NotarizationShareNonAck
+                }
+            }
+            NotarizationShareContent::ICC(_) => SigningDomain::NotarizationShareICC,
+        }
+    }
+}
+
+impl CanonicalEncode for NotarizationShareContentICC {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.height.encode_canonical(bytes);
+        self.block.encode_canonical(bytes);
+    }
+}
+
+impl CanonicalEncode for NotarizationShareContentCOD {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.height.encode_canonical(bytes);
+        self.block.encode_canonical(bytes);
+        self.block_parent_hash.encode_canonical(bytes);
+        self.is_ack.encode_canonical(bytes);
+    }
+}
+
 /// A notarization share is a multi-signature share on a notarization content.
 /// If sufficiently many replicas create notarization shares, the shares can be
 /// aggregated into a full notarization.
-pub type NotarizationShare = Signed<NotarizationShareContent, u8>;
+pub type NotarizationShare = Signed<NotarizationShareContent, Signature>;
 
 pub struct Notary {
-    node_id: u8,
+    node_id: NodeId,
     subnet_params: SubnetParams,
     time_source: Arc<dyn TimeSource>,
+    signer: Signer,
 }
 
 impl Notary {
-    pub fn new(node_id: u8, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
+    pub fn new(signer: Signer, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
         Self {
-            node_id,
+            node_id: signer.node_id(),
             subnet_params,
             time_source,
+            signer,
         }
     }
 
@@ -87,6 +142,10 @@ impl Notary {
         let notarized_height = pool.get_notarized_height();
         let mut notarization_shares = Vec::new();
         let height = notarized_height + 1;
+        if !pool.is_registry_known(height) || !pool.get_subnet_params(height).committee.contains(self.node_id) {
+            // only the members of the committee of the height notarize its blocks
+            return notarization_shares;
+        }
         // CoD implementation change: cannot use find_lowest_ranked_proposals.
         // in case there is only one 'good' block, it might not be the one with the lowest rank
         // therefore, we consider all proposals
@@ -109,8 +168,14 @@ impl Notary {
 
     /// Return the time since round start, if it is greater than required
     /// notarization delay for the given block rank, or None otherwise.
-    fn time_to_notarize(&self, pool: &PoolReader<'_>, height: Height, rank: u8) -> bool {
-        let adjusted_notary_delay = get_adjusted_notary_delay(pool, height, rank, self.subnet_params.artifact_delay);
+    fn time_to_notarize(&self, pool: &PoolReader<'_>, height: Height, rank: u32) -> bool {
+        let adjusted_notary_delay = get_adjusted_notary_delay(
+            pool,
+            height,
+            rank,
+            get_artifact_delay(pool, &self.subnet_params),
+            self.subnet_params.delay_backoff_base,
+        );
         if let Some(start_time) = pool.get_round_start_time(height) {
             let now = self.time_source.get_relative_time();
             return now >= start_time + adjusted_notary_delay;
@@ -127,7 +192,7 @@ impl Notary {
     ) -> bool {
         let height = proposal.content.value.height;
         pool.get_notarization_shares(height)
-            .filter(|s| s.signature == self.node_id)
+            .filter(|s| s.signature.signer == self.node_id)
             .any(|s| match s.content {
                 NotarizationShareContent::COD(share_content) => {
                     proposal.content.hash.eq(share_content.block.get_ref())
@@ -142,39 +207,39 @@ impl Notary {
     fn notarize_block(
         &self,
         pool: &PoolReader<'_>,
-        proposal: Signed<Hashed<Block>, u8>,
+        proposal: BlockProposal,
     ) -> Option<NotarizationShare> {
         let height = proposal.content.value.height;
-        let mut content: NotarizationShareContent;
-        if self.subnet_params.consensus_on_demand == true {
-            // CoD rule 1: first child of each block is acknowledged
-            let is_ack = pool
-                .get_notarization_shares(height)
-                .filter(|s| s.signature == self.node_id)    // filter out shares not sent by local replica
-                .filter(|s| {
-                    if let NotarizationShareContent::COD(notarization_share_content_cod) = &s.content {
-                        notarization_share_content_cod.block_parent_hash == proposal.content.value.parent   // filter out shares for blocks that do not have the same parent of the block being proposed
-                    }
-                    else {
-                        panic!("no notarization shares of ICC variant when consensus_on_demand parameter is true");
-                    }
-                })
-                .count() == 0; // set 'is_ack' to true if 'proposal' is the first child of its parent for which the local replica creates a notarization share, the latter is also an acknowledgement
-            content = NotarizationShareContent::COD(NotarizationShareContentCOD::new(
-                proposal.content.value.height,
-                CryptoHashOf::from(proposal.content.hash),
-                proposal.content.value.parent,
-                Some(is_ack),
-            ));
-        } else {
-            content = NotarizationShareContent::ICC(NotarizationShareContentICC::new(
+        let content = match get_block_mode(pool, pool.get_subnet_params(height), &proposal.content.value)? {
+            ConsensusMode::FICC => {
+                // CoD rule 1: first child of each block is acknowledged
+                let is_ack = pool
+                    .get_notarization_shares(height)
+                    .filter(|s| s.signature.signer == self.node_id)    // filter out shares not sent by local replica
+                    .filter(|s| {
+                        // shares of ICC variant are sent only for children of other parents
+                        if let NotarizationShareContent::COD(notarization_share_content_cod) = &s.content {
+                            notarization_share_content_cod.block_parent_hash == proposal.content.value.parent   // filter out shares for blocks that do not have the same parent of the block being proposed
+                        }
+                        else {
+                            false
+                        }
+                    })
+                    .count() == 0; // set 'is_ack' to true if 'proposal' is the first child of its parent for which the local replica creates a notarization share, the latter is also an acknowledgement
+                NotarizationShareContent::COD(NotarizationShareContentCOD::new(
+                    proposal.content.value.height,
+                    CryptoHashOf::from(proposal.content.hash),
+                    proposal.content.value.parent,
+                    Some(is_ack),
+                ))
+            }
+            ConsensusMode::ICC => NotarizationShareContent::ICC(NotarizationShareContentICC::new(
                 proposal.content.value.height,
                 CryptoHashOf::from(proposal.content.hash),
                 None,
-            ));
-        }
-        let signature = self.node_id;
-        Some(NotarizationShare { content, signature })
+            )),
+        };
+        Some(self.signer.sign(content))
     }
 }
 
@@ -215,7 +280,7 @@ fn find_lowest_ranked_proposals(pool: &PoolReader<'_>, h: Height) -> Vec<BlockPr
 /// notarized heights, by how far the certified height lags behind the finalized
 /// height, and by how far we have advanced beyond a summary block without
 /// creating a CUP.
-pub fn get_adjusted_notary_delay(pool: &PoolReader<'_>, height: Height, rank: u8, notarization_delay: u64) -> Duration {
+pub fn get_adjusted_notary_delay(pool: &PoolReader<'_>, height: Height, rank: u32, notarization_delay: u64, backoff_base: f32) -> Duration {
     // We adjust regular delay based on the gap between finalization and
     // notarization to make it exponentially longer to keep the gap from growing too
     // big. This is because increasing delay leads to higher chance of notarizing
@@ -223,8 +288,9 @@ pub fn get_adjusted_notary_delay(pool: &PoolReader<'_>, height: Height, rank: u8
     // round.  This exponential backoff does not apply to block rank 0.
     let finalized_height = pool.get_finalized_height();
     let ranked_delay = notarization_delay as f32 * rank as f32;
-    let finality_gap = (pool.get_notarized_height() - finalized_height) as i32;
+    // the finalized height can be greater than the notarized one when a block is FP-finalized before being notarized
+    let finality_gap = pool.get_notarized_height().saturating_sub(finalized_height) as i32;
     let finality_adjusted_delay =
-        (ranked_delay * 1.5_f32.powi(finality_gap)) as u64;
+        (ranked_delay * backoff_base.powi(finality_gap)) as u64;
     Duration::from_millis(finality_adjusted_delay)
 }
\ No newline at end of file
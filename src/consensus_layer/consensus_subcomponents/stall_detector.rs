@@ -0,0 +1,134 @@
+//! Detection of rounds which do not progress and recovery of their liveness.
+//!
+//! A round stalls if no block is notarized at the height following the notarized one within `stall_timeout`
+//! milliseconds from its start, for example because too few acknowledgements were received for any child of
+//! the parent to be good. Each time the timeout elapses again without progress, the replica escalates:
+//! - it gossips again its own block proposals and notarization shares of the height and the notarizations of the
+//!   previous height, as peers might have missed them while disconnected (the artifacts of the other replicas are
+//!   gossiped again by them, so that a stalled round does not multiply the traffic by the size of the subnet),
+//! - block makers propose a block even if a proposal with a lower rank is available (see [is_stalled]).
+//!
+//! Goodness is not relaxed: a child of the parent becomes good only once n - f acknowledgements are received
+//! for its siblings, otherwise it could be notarized while a sibling is FP-finalized with n - p acknowledgements.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    consensus_layer::{artifacts::ConsensusMessage, height_index::Height, pool_reader::PoolReader},
+    committee::NodeId,
+    time_source::{Time, TimeSource},
+    SubnetParams,
+};
+
+/// Round which did not progress within the stall timeout, written to the benchmark results.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StalledRound {
+    pub height: Height,
+    // number of times the stall timeout elapsed before a block was notarized
+    pub escalations: u32,
+    // from the start of the round to the first notarization, None if the round was still stalled when the replica stopped
+    pub duration: Option<Duration>,
+}
+
+/// Stalled rounds by height, shared with the status API of the replica.
+pub type StalledRounds = BTreeMap<Height, StalledRound>;
+
+/// Return true if the round of the given height started more than `stall_timeout` milliseconds ago
+/// and no block is notarized at the height yet.
+pub fn is_stalled(pool: &PoolReader<'_>, height: Height, subnet_params: &SubnetParams, now: Time) -> bool {
+    stalled_for(pool, height, subnet_params, now).is_some()
+}
+
+// Time elapsed since the start of the round of the given height, if it stalled.
+fn stalled_for(pool: &PoolReader<'_>, height: Height, subnet_params: &SubnetParams, now: Time) -> Option<Duration> {
+    if subnet_params.stall_timeout == 0 || height != pool.get_notarized_height() + 1 {
+        return None;
+    }
+    let elapsed = now.checked_duration_since(pool.get_round_start_time(height)?)?;
+    if elapsed >= Duration::from_millis(subnet_params.stall_timeout) {
+        Some(elapsed)
+    } else {
+        None
+    }
+}
+
+pub struct StallDetector {
+    node_id: NodeId,
+    time_source: Arc<dyn TimeSource>,
+    stalled_rounds: Arc<RwLock<StalledRounds>>,
+}
+
+impl StallDetector {
+    pub fn new(
+        node_id: NodeId,
+        time_source: Arc<dyn TimeSource>,
+        stalled_rounds: Arc<RwLock<StalledRounds>>,
+    ) -> Self {
+        Self {
+            node_id,
+            time_source,
+            stalled_rounds,
+        }
+    }
+
+    /// Record the stalled rounds and return the artifacts to be gossiped again each time the round
+    /// of the height following the notarized one stalls for another `stall_timeout` milliseconds.
+    pub fn on_state_change(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
+        // println!("\n########## Stall detector ##########");
+        let height = pool.get_notarized_height() + 1;
+        let mut stalled_rounds = self.stalled_rounds.write().unwrap();
+        // the rounds which stalled before the notarized height have recovered
+        for stalled_round in stalled_rounds.values_mut().filter(|stalled_round| stalled_round.height < height) {
+            if stalled_round.duration.is_none() {
+                stalled_round.duration = pool.get_round_duration(stalled_round.height);
+                println!(
+                    "\nReplica: {} recovered the round at height: {} after {:?}",
+                    self.node_id, stalled_round.height, stalled_round.duration
+                );
+            }
+        }
+        let subnet_params = pool.get_subnet_params(height);
+        let elapsed = match stalled_for(pool, height, subnet_params, self.time_source.get_relative_time()) {
+            Some(elapsed) => elapsed,
+            None => return vec![],
+        };
+        let escalations = (elapsed.as_millis() / subnet_params.stall_timeout as u128) as u32;
+        let stalled_round = stalled_rounds.entry(height).or_insert(StalledRound {
+            height,
+            escalations: 0,
+            duration: None,
+        });
+        if stalled_round.escalations >= escalations {
+            return vec![];
+        }
+        stalled_round.escalations = escalations;
+        println!(
+            "\nReplica: {} detected stalled round at height: {} for {:?}, escalation: {}",
+            self.node_id, height, elapsed, escalations
+        );
+        let validated = pool.pool().validated();
+        validated
+            .block_proposal()
+            .get_by_height(height)
+            .filter(|proposal| proposal.signature.signer == self.node_id)
+            .map(ConsensusMessage::BlockProposal)
+            .chain(
+                validated
+                    .notarization_share()
+                    .get_by_height(height)
+                    .filter(|share| share.signature.signer == self.node_id)
+                    .map(ConsensusMessage::NotarizationShare),
+            )
+            .chain(validated.notarization().get_by_height(height - 1).map(ConsensusMessage::Notarization))
+            .collect()
+    }
+
+    pub fn stalled_rounds(&self) -> Vec<StalledRound> {
+        self.stalled_rounds.read().unwrap().values().cloned().collect()
+    }
+}
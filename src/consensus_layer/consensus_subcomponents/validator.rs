@@ -1,20 +1,40 @@
-use std::{sync::{Arc, RwLock}, collections::BTreeMap};
+use std::{sync::{Arc, RwLock}, collections::{BTreeMap, BTreeSet}, time::Duration};
 
 use crate::{consensus_layer::{
     pool_reader::PoolReader,
     artifacts::{ChangeSet, ChangeAction, IntoInner, ConsensusMessage},
-    consensus::RoundRobin, height_index::Height
-}, time_source::TimeSource, HeightMetrics, FinalizationType};
+    height_index::Height,
+    registry::{check_authorized_update, InvalidRegistryUpdate},
+    consensus_subcomponents::{
+        aggregator::{verify_finalization, verify_notarization, InvalidAggregate},
+        equivocation::InvalidEquivocationProof,
+        goodifier::{GoodnessArtifact, InvalidGoodnessCertificate},
+    },
+}, committee::NodeId, crypto::{verify, CryptoHash, Hashed, SignatureError}, time_source::{system_time_now, TimeSource}, HeightMetrics, FinalizationType, SubnetParams};
+
+#[derive(Debug)]
+pub enum InvalidArtifact {
+    InvalidSignature(SignatureError),
+    BlockHashMismatch,
+    MissingProposerTimestamp,
+    InvalidRegistryUpdate(InvalidRegistryUpdate),
+    InvalidEquivocationProof(InvalidEquivocationProof),
+    ExcludedSigner(NodeId),
+    InvalidGoodnessCertificate(InvalidGoodnessCertificate),
+    InvalidAggregate(InvalidAggregate),
+}
 
 pub struct Validator {
-    schedule: RoundRobin,
+    node_id: NodeId,
+    subnet_params: SubnetParams,
     time_source: Arc<dyn TimeSource>,
 }
 
 impl Validator {
-    pub fn new(time_source: Arc<dyn TimeSource>) -> Self {
+    pub fn new(node_id: NodeId, subnet_params: SubnetParams, time_source: Arc<dyn TimeSource>) -> Self {
         Self {
-            schedule: RoundRobin::default(),
+            node_id,
+            subnet_params,
             time_source,
         }
     }
@@ -22,9 +42,74 @@ impl Validator {
     pub fn on_state_change(&self, pool_reader: &PoolReader<'_>, finalization_times: Arc<RwLock<BTreeMap<Height, Option<HeightMetrics>>>>) -> (ChangeSet, bool) {
         // println!("\n########## Validator ##########");
         let mut change_set = Vec::new();
+        // parents for which a goodness artifact has been validated in this pass, as it becomes the latest one only once the change set is applied
+        let mut goodness_parents = BTreeSet::new();
+        // blocks for which a notarization or a finalization has been validated in this pass
+        let mut aggregated_blocks = BTreeSet::new();
         for (_, unvalidated_artifact) in &pool_reader.pool().unvalidated().artifacts {
             // println!("Validating artifact {:?}", unvalidated_artifact);
             let consensus_message = unvalidated_artifact.to_owned().into_inner();
+            let height = consensus_message.height();
+            if !pool_reader.is_registry_known(height) {
+                // the artifact is kept in the unvalidated section until the committee of its height is known
+                continue;
+            }
+            // aggregates of the same block created by different replicas only differ by their signers, so only the first valid one is kept
+            if let Some(block) = aggregated_block(&consensus_message) {
+                if aggregated_blocks.contains(&block) || is_aggregated(pool_reader, &consensus_message) {
+                    change_set.push(ChangeAction::RemoveFromUnvalidated(consensus_message));
+                    continue;
+                }
+            }
+            if let Err(e) = verify_artifact(pool_reader.get_subnet_params(height), &consensus_message) {
+                println!("\nDropping invalid artifact: {:?}, reason: {:?}", consensus_message, e);
+                change_set.push(ChangeAction::RemoveFromUnvalidated(consensus_message));
+                continue;
+            }
+            if let Some(block) = aggregated_block(&consensus_message) {
+                aggregated_blocks.insert(block);
+            }
+            if let Some(signer) = share_signer(&consensus_message) {
+                if pool_reader.is_excluded(signer, height) {
+                    println!("\nDropping invalid artifact: {:?}, reason: {:?}", consensus_message, InvalidArtifact::ExcludedSigner(signer));
+                    change_set.push(ChangeAction::RemoveFromUnvalidated(consensus_message));
+                    continue;
+                }
+            }
+            if let (Some(max_clock_skew), ConsensusMessage::BlockProposal(proposal)) = (self.subnet_params.max_clock_skew, &consensus_message) {
+                match proposal.content.value.proposer_timestamp {
+                    None => {
+                        println!("\nDropping invalid artifact: {:?}, reason: {:?}", consensus_message, InvalidArtifact::MissingProposerTimestamp);
+                        change_set.push(ChangeAction::RemoveFromUnvalidated(consensus_message));
+                        continue;
+                    }
+                    Some(proposer_timestamp) => {
+                        // a proposal from the future is kept in the unvalidated section until the local clock catches up with the one of its proposer
+                        if proposer_timestamp > system_time_now() + Duration::from_millis(max_clock_skew) {
+                            continue;
+                        }
+                    }
+                }
+            }
+            if let ConsensusMessage::GoodnessArtifact(goodness_artifact) = &consensus_message {
+                // a goodness artifact received from a peer is only kept if it changes the goodness of the children of its parent,
+                // in which case it is derived again with the local time so that it is ordered with the ones created locally
+                if !goodness_parents.contains(&goodness_artifact.parent_hash) {
+                    let latest_goodness_artifact = pool_reader.get_latest_goodness_artifact_for_parent(&goodness_artifact.parent_hash, height);
+                    if goodness_artifact.supersedes(latest_goodness_artifact.as_ref()) {
+                        let derived_goodness_artifact = GoodnessArtifact::from_certificate(
+                            goodness_artifact.certificate.clone(),
+                            pool_reader.get_subnet_params(height),
+                            self.time_source.get_relative_time(),
+                        )
+                        .expect("verified goodness artifact");
+                        goodness_parents.insert(goodness_artifact.parent_hash);
+                        change_set.push(ChangeAction::AddToValidated(ConsensusMessage::GoodnessArtifact(derived_goodness_artifact)));
+                    }
+                    change_set.push(ChangeAction::RemoveFromUnvalidated(consensus_message));
+                }
+                continue;
+            }
             if let ConsensusMessage::Finalization(finalization) = &consensus_message {
                 // only insert finalization of type DK if received by peer before it was finalized locally
                 if !finalization_times.read().unwrap().contains_key(&finalization.content.height) {
@@ -34,6 +119,8 @@ impl Validator {
                         let height_metrics = HeightMetrics {
                             latency: finalization_time,
                             fp_finalization: FinalizationType::DK,
+                            phases: pool_reader.get_phase_timestamps(finalization.content.height, self.node_id, pool_reader.get_subnet_params(finalization.content.height)),
+                            end_to_end_latency: pool_reader.get_end_to_end_latency(&finalization.content.block, finalization.content.height),
                         };
                         finalization_times
                             .write()
@@ -47,4 +134,144 @@ impl Validator {
         // the changes due to the validation of a block do not have to be broadcasted as each node performs them locally depending on the state of its consensus pool
         (change_set, false)
     }
-}
\ No newline at end of file
+}
+
+// Check that the artifacts signed by a single replica are signed by a member of the committee of their height, in the signing domain of their content,
+// that the registry update carried by a block proposal can take effect where it says and is signed by n - f members and that a goodness artifact
+// is the one derived from its certificate, and that notarizations and finalizations carry the signatures of enough shares of different members.
+// The mode chosen by a proposer for the children of its block is not checked, as it depends on the shares received by the proposer, which
+// other replicas might not have: all the children of the block use the mode of their parent in any case, see [ConsensusMode].
+fn verify_artifact(
+    subnet_params: &SubnetParams,
+    consensus_message: &ConsensusMessage,
+) -> Result<(), InvalidArtifact> {
+    let committee = &subnet_params.committee;
+    match consensus_message {
+        ConsensusMessage::BlockProposal(proposal) => {
+            if proposal.content.get_hash() != &Hashed::crypto_hash(&proposal.content.value) {
+                return Err(InvalidArtifact::BlockHashMismatch);
+            }
+            if let Some(registry_update) = &proposal.content.value.payload.registry_update {
+                check_authorized_update(registry_update, proposal.content.value.height, subnet_params)
+                    .map_err(InvalidArtifact::InvalidRegistryUpdate)?;
+            }
+            verify(committee, proposal).map_err(InvalidArtifact::InvalidSignature)
+        }
+        ConsensusMessage::NotarizationShare(share) => verify(committee, share).map_err(InvalidArtifact::InvalidSignature),
+        ConsensusMessage::FinalizationShare(share) => verify(committee, share).map_err(InvalidArtifact::InvalidSignature),
+        ConsensusMessage::EquivocationProof(proof) => proof.verify(committee).map_err(InvalidArtifact::InvalidEquivocationProof),
+        ConsensusMessage::GoodnessArtifact(goodness_artifact) => {
+            goodness_artifact.certificate.verify(committee).map_err(InvalidArtifact::InvalidGoodnessCertificate)?;
+            match GoodnessArtifact::from_certificate(goodness_artifact.certificate.clone(), subnet_params, goodness_artifact.timestamp) {
+                Some(derived_goodness_artifact) if derived_goodness_artifact == *goodness_artifact => Ok(()),
+                _ => Err(InvalidArtifact::InvalidGoodnessCertificate(InvalidGoodnessCertificate::DerivationMismatch)),
+            }
+        }
+        ConsensusMessage::Notarization(notarization) => {
+            verify_notarization(subnet_params, notarization).map_err(InvalidArtifact::InvalidAggregate)
+        }
+        ConsensusMessage::Finalization(finalization) => {
+            verify_finalization(subnet_params, finalization).map_err(InvalidArtifact::InvalidAggregate)
+        }
+    }
+}
+
+// Kind and block of a notarization or a finalization.
+fn aggregated_block(consensus_message: &ConsensusMessage) -> Option<(&'static str, Height, CryptoHash)> {
+    match consensus_message {
+        ConsensusMessage::Notarization(notarization) => {
+            Some((consensus_message.type_name(), notarization.content.height, *notarization.content.block.get_ref()))
+        }
+        ConsensusMessage::Finalization(finalization) => {
+            Some((consensus_message.type_name(), finalization.content.height, *finalization.content.block.get_ref()))
+        }
+        _ => None,
+    }
+}
+
+// Return true if a notarization or a finalization of the same block is already validated.
+fn is_aggregated(pool_reader: &PoolReader<'_>, consensus_message: &ConsensusMessage) -> bool {
+    let validated = pool_reader.pool().validated();
+    match consensus_message {
+        ConsensusMessage::Notarization(notarization) => validated
+            .notarization()
+            .get_by_height(notarization.content.height)
+            .any(|validated| validated.content == notarization.content),
+        ConsensusMessage::Finalization(finalization) => validated
+            .finalization()
+            .get_by_height(finalization.content.height)
+            .any(|validated| validated.content == finalization.content),
+        _ => false,
+    }
+}
+
+// Signer of the notarization and finalization shares, which are ignored while their signer is excluded for having equivocated.
+fn share_signer(consensus_message: &ConsensusMessage) -> Option<NodeId> {
+    match consensus_message {
+        ConsensusMessage::NotarizationShare(share) => Some(share.signature.signer),
+        ConsensusMessage::FinalizationShare(share) => Some(share.signature.signer),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        committee::Committee,
+        consensus_layer::{
+            artifacts::UnvalidatedArtifact,
+            consensus_mode::{choose_next_mode, ConsensusMode},
+            consensus_subcomponents::{
+                block_maker::{Block, Payload},
+                notary::{NotarizationShareContent, NotarizationShareContentCOD},
+            },
+            pool::ConsensusPoolImpl,
+        },
+        crypto::{CryptoHash, CryptoHashOf, Signer},
+        time_source::{SysTimeSource, Time},
+    };
+
+    fn subnet_params() -> SubnetParams {
+        SubnetParams::builder(Committee::with_size(4))
+            .byzantine_nodes_number(1)
+            .disagreeing_nodes_number(0)
+            .adaptive_consensus(true)
+            .mode_switch_window(1)
+            .mode_switch_threshold(1)
+            .build()
+            .expect("valid subnet parameters")
+    }
+
+    #[test]
+    fn proposal_is_validated_by_replicas_disagreeing_on_the_mode_switch() {
+        let subnet_params = subnet_params();
+        let time_source = SysTimeSource::new();
+        // the fast path failed at height 1 according to the first pool, which lacks the acknowledgements of the second one
+        let mut missing_acks = ConsensusPoolImpl::new(subnet_params.clone());
+        let mut all_acks = ConsensusPoolImpl::new(subnet_params.clone());
+        let acked_block = CryptoHashOf::new(CryptoHash([1; 32]));
+        let acks = subnet_params.committee.node_ids().map(|node_id| {
+            let content = NotarizationShareContentCOD::new(1, acked_block.clone(), CryptoHash([0; 32]), Some(true));
+            ChangeAction::AddToValidated(ConsensusMessage::NotarizationShare(
+                Signer::with_derived_key(node_id).sign(NotarizationShareContent::COD(content)),
+            ))
+        });
+        all_acks.apply_changes(&time_source, acks.collect());
+        let next_modes: Vec<_> = [&missing_acks, &all_acks]
+            .iter()
+            .map(|pool| choose_next_mode(&PoolReader::new(pool), &subnet_params, 3, ConsensusMode::FICC))
+            .collect();
+        assert_eq!(next_modes, vec![ConsensusMode::ICC, ConsensusMode::FICC]);
+
+        // the proposer switched to ICC, which both replicas accept
+        let block = Block::new(CryptoHash([2; 32]), Payload::new(ConsensusMode::ICC, None), 3, 0, None);
+        let proposal = ConsensusMessage::BlockProposal(Signer::with_derived_key(NodeId(1)).sign(Hashed::new(block)));
+        for pool in [&mut missing_acks, &mut all_acks] {
+            pool.insert(UnvalidatedArtifact::new(proposal.clone(), Time::from_duration(Duration::ZERO)));
+            let validator = Validator::new(NodeId(2), subnet_params.clone(), Arc::new(SysTimeSource::new()));
+            let (change_set, _) = validator.on_state_change(&PoolReader::new(pool), Arc::new(RwLock::new(BTreeMap::new())));
+            assert!(matches!(change_set.as_slice(), [ChangeAction::MoveToValidated(validated)] if *validated == proposal));
+        }
+    }
+}
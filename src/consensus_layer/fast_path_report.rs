@@ -0,0 +1,280 @@
+//! Statistics on the paths through which the blocks of the finalized chain were finalized.
+//!
+//! A block at height h can be finalized through three paths:
+//! - the fast path: n - p replicas acknowledged it and its parent was finalized (rule 2 of Consensus on Demand),
+//! - a finalization: n - f replicas sent a finalization share on it,
+//! - implicitly: a descendant of the block was finalized, which finalizes all its ancestors.
+//!
+//! The path is the one completed first according to the timestamps of the artifacts in the local pool.
+//! If the fast path was not taken, the report tells why: the height ran ICC, too many replicas acknowledged
+//! a sibling of the block, too few acknowledgements were received at all, or the acknowledgements (or the
+//! finalization of the parent) were received only after the block was finalized through another path.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    committee::NodeId,
+    crypto::{CryptoHash, Hashed},
+    time_source::Time,
+};
+
+use super::{
+    artifacts::ConsensusMessageHashable,
+    consensus_mode::{get_block_mode, ConsensusMode},
+    consensus_subcomponents::{block_maker::Block, notary::NotarizationShareContent},
+    height_index::{Height, HeightRange},
+    pool_reader::{quorum_time, PoolReader},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FinalizationPath {
+    FastPath,
+    Finalization,
+    Implicit,
+}
+
+/// Reason for which a block was not finalized in the fast path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FastPathFailure {
+    // the height ran ICC, therefore no acknowledgements were sent
+    IcMode,
+    // more than p replicas acknowledged siblings of the block, so that it could not receive n - p acknowledgements
+    ConflictingChildren,
+    // fewer than n - p acknowledgements were received, for example because some replicas did not send them in time
+    NotEnoughAcks,
+    // the parent of the block was not finalized explicitly (by a finalization or the fast path)
+    ParentNotFinalized,
+    // the n - p acknowledgements were received after the block was finalized through another path
+    AcksLate,
+    // the parent was finalized after the block was finalized through another path
+    ParentFinalizedLate,
+}
+
+/// How the block of the finalized chain at `height` was finalized, and why the fast path was not taken if so.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FastPathReport {
+    pub height: Height,
+    pub block: CryptoHash,
+    pub mode: Option<ConsensusMode>,
+    pub finalization_path: FinalizationPath,
+    pub failure: Option<FastPathFailure>,  // None if the block was finalized in the fast path
+    // number of replicas which acknowledged each child of the parent of the block, including the block itself
+    pub acks_per_child: BTreeMap<CryptoHash, usize>,
+    pub acks_threshold: usize,  // n - p
+    pub missing_acks: BTreeSet<NodeId>,  // members of the committee whose acknowledgement of the block was not received
+    pub finalization_shares: usize,
+    pub finalization_shares_threshold: usize,  // n - f
+    pub missing_finalization_shares: BTreeSet<NodeId>,
+    // time at which the local replica received the n - p acknowledgements of the block
+    pub acks_quorum: Option<Time>,
+    // time at which the parent was finalized explicitly, None for the children of the genesis block
+    pub parent_finalization: Option<Time>,
+    // time at which the local replica received the n - f finalization shares on the block
+    pub finalization_shares_quorum: Option<Time>,
+    // time at which the first descendant of the block was finalized explicitly
+    pub descendant_finalization: Option<Time>,
+}
+
+/// Number of heights finalized through each path and number of fast path failures by reason.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FastPathStatistics {
+    pub finalized_heights: usize,
+    pub paths: BTreeMap<FinalizationPath, usize>,
+    pub failures: BTreeMap<FastPathFailure, usize>,
+}
+
+impl FastPathStatistics {
+    pub fn new<'a>(reports: impl Iterator<Item = &'a FastPathReport>) -> Self {
+        let mut statistics = Self::default();
+        for report in reports {
+            statistics.add(report);
+        }
+        statistics
+    }
+
+    pub fn add(&mut self, report: &FastPathReport) {
+        self.finalized_heights += 1;
+        *self.paths.entry(report.finalization_path).or_default() += 1;
+        if let Some(failure) = report.failure {
+            *self.failures.entry(failure).or_default() += 1;
+        }
+    }
+}
+
+/// Statistics of the finalized chain kept up to date while the replica is running, so that only the reports
+/// of the heights finalized since the previous update are computed. As the report of a height can still change
+/// when late artifacts are received, a height is counted once it is `recent_heights` heights below the finalized
+/// tip, the reports of the last `recent_heights` heights being computed at each update.
+pub struct FastPathTracker {
+    recent_heights: usize,
+    // statistics of the heights from 1 to `counted_height`
+    counted: FastPathStatistics,
+    counted_height: Height,
+}
+
+impl FastPathTracker {
+    pub fn new(recent_heights: usize) -> Self {
+        Self { recent_heights, counted: FastPathStatistics::default(), counted_height: 0 }
+    }
+
+    /// Return the statistics of all the finalized heights and the reports of the last `recent_heights` ones.
+    pub fn update(&mut self, pool: &PoolReader<'_>) -> (FastPathStatistics, Vec<FastPathReport>) {
+        let reports: Vec<FastPathReport> = pool
+            .get_finalized_chain_from(self.counted_height + 1)
+            .unwrap_or_default()
+            .iter()
+            .map(|block| get_block_report(pool, block))
+            .collect();
+        let recent = reports.len().saturating_sub(self.recent_heights);
+        for report in &reports[..recent] {
+            self.counted.add(report);
+            self.counted_height = report.height;
+        }
+        let mut statistics = self.counted.clone();
+        for report in &reports[recent..] {
+            statistics.add(report);
+        }
+        (statistics, reports[recent..].to_vec())
+    }
+}
+
+/// Return the reports of all the heights of the finalized chain.
+/// Return an empty map if an ancestor of the finalized tip has not been received yet.
+pub fn get_fast_path_reports(pool: &PoolReader<'_>) -> BTreeMap<Height, FastPathReport> {
+    pool.get_finalized_chain()
+        .unwrap_or_default()
+        .iter()
+        .map(|block| (block.height, get_block_report(pool, block)))
+        .collect()
+}
+
+/// Return the report of the given height, None if it is not finalized yet.
+pub fn get_fast_path_report(pool: &PoolReader<'_>, height: Height) -> Option<FastPathReport> {
+    if height == 0 || height > pool.get_finalized_height() {
+        return None;
+    }
+    let chain = pool.get_finalized_chain_from(height)?;
+    Some(get_block_report(pool, chain.first()?))
+}
+
+fn get_block_report(pool: &PoolReader<'_>, block: &Block) -> FastPathReport {
+    let height = block.height;
+    let subnet_params = pool.get_subnet_params(height);
+    let validated = pool.pool().validated();
+    let block_hash: CryptoHash = Hashed::crypto_hash(block);
+    let acks_threshold = (subnet_params.total_nodes_number - subnet_params.disagreeing_nodes_number) as usize;
+    let finalization_shares_threshold = (subnet_params.total_nodes_number - subnet_params.byzantine_nodes_number) as usize;
+
+    // acknowledgements of the children of the parent of the block
+    let mut ack_signers = BTreeMap::<CryptoHash, BTreeSet<NodeId>>::new();
+    let mut acks = Vec::new();
+    for share in validated.notarization_share().get_by_height(height) {
+        if let NotarizationShareContent::COD(content) = &share.content {
+            if content.is_ack && content.block_parent_hash == block.parent {
+                ack_signers.entry(*content.block.get_ref()).or_default().insert(share.signature.signer);
+                if content.block.get_ref() == &block_hash {
+                    if let Some(time) = validated.get_timestamp(&share.get_id()) {
+                        acks.push((block_hash, time));
+                    }
+                }
+            }
+        }
+    }
+    let block_ack_signers = ack_signers.get(&block_hash).cloned().unwrap_or_default();
+    let sibling_acks: usize = ack_signers
+        .iter()
+        .filter(|(child, _)| *child != &block_hash)
+        .map(|(_, signers)| signers.len())
+        .sum();
+
+    let mut finalization_share_signers = BTreeSet::new();
+    let mut finalization_shares = Vec::new();
+    for share in validated.finalization_share().get_by_height(height) {
+        if share.content.block.get_ref() == &block_hash {
+            finalization_share_signers.insert(share.signature.signer);
+            if let Some(time) = validated.get_timestamp(&share.get_id()) {
+                finalization_shares.push((block_hash, time));
+            }
+        }
+    }
+
+    let acks_quorum = quorum_time(acks.into_iter(), acks_threshold);
+    let finalization_shares_quorum = quorum_time(finalization_shares.into_iter(), finalization_shares_threshold);
+    let parent_finalization = validated
+        .finalization()
+        .get_by_height(height - 1)
+        .filter(|finalization| finalization.content.block.get_ref() == &block.parent)
+        .flat_map(|finalization| validated.get_timestamp(&finalization.get_id()))
+        .min();
+    let is_parent_finalized = height == 1 || parent_finalization.is_some();
+    let descendant_finalization = validated
+        .finalization()
+        .get_by_height_range(HeightRange::new(height + 1, pool.get_finalized_height()))
+        .flat_map(|finalization| validated.get_timestamp(&finalization.get_id()))
+        .min();
+
+    // time at which the fast path completed: the latest of the acknowledgements and the finalization of the parent
+    let fast_path = match (acks_quorum, parent_finalization) {
+        (Some(acks_quorum), _) if height == 1 => Some(acks_quorum),
+        (Some(acks_quorum), Some(parent_finalization)) => Some(std::cmp::max(acks_quorum, parent_finalization)),
+        _ => None,
+    };
+    // time at which the block was finalized through another path
+    let other_path = [
+        finalization_shares_quorum.map(|time| (time, FinalizationPath::Finalization)),
+        descendant_finalization.map(|time| (time, FinalizationPath::Implicit)),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let finalization_path = match (fast_path, other_path) {
+        (Some(fast_path), Some((time, _))) if fast_path <= time => FinalizationPath::FastPath,
+        (Some(_), None) => FinalizationPath::FastPath,
+        (_, Some((_, path))) => path,
+        // the finalization was received from a peer without its shares
+        (None, None) if pool.get_finalized_block_hash_at_height(height) == Some(block_hash) => {
+            FinalizationPath::Finalization
+        }
+        (None, None) => FinalizationPath::Implicit,
+    };
+
+    let mode = get_block_mode(pool, subnet_params, block);
+    let failure = match finalization_path {
+        FinalizationPath::FastPath => None,
+        _ if mode == Some(ConsensusMode::ICC) => Some(FastPathFailure::IcMode),
+        _ if acks_quorum.is_none() && sibling_acks > subnet_params.disagreeing_nodes_number as usize => {
+            Some(FastPathFailure::ConflictingChildren)
+        }
+        _ if acks_quorum.is_none() => Some(FastPathFailure::NotEnoughAcks),
+        _ if !is_parent_finalized => Some(FastPathFailure::ParentNotFinalized),
+        _ if parent_finalization > acks_quorum => Some(FastPathFailure::ParentFinalizedLate),
+        _ => Some(FastPathFailure::AcksLate),
+    };
+
+    FastPathReport {
+        height,
+        block: block_hash,
+        mode,
+        finalization_path,
+        failure,
+        acks_per_child: ack_signers.iter().map(|(child, signers)| (*child, signers.len())).collect(),
+        acks_threshold,
+        missing_acks: subnet_params
+            .committee
+            .node_ids()
+            .filter(|node_id| !block_ack_signers.contains(node_id))
+            .collect(),
+        finalization_shares: finalization_share_signers.len(),
+        finalization_shares_threshold,
+        missing_finalization_shares: subnet_params
+            .committee
+            .node_ids()
+            .filter(|node_id| !finalization_share_signers.contains(node_id))
+            .collect(),
+        acks_quorum,
+        parent_finalization,
+        finalization_shares_quorum,
+        descendant_finalization,
+    }
+}
@@ -0,0 +1,399 @@
+//! Finality proofs for light clients.
+//!
+//! A light client which only trusts the public keys of the committee can verify that a block is final
+//! with a [FinalityProof]: a hash chain of consecutive blocks of the finalized chain together with the
+//! certificates of some of them. A block is final if it is certified by n - f finalization shares, in
+//! which case all its ancestors are final too, or if it is certified by n - p acknowledgements and its
+//! parent is final (rule 2 of Consensus on Demand), the genesis block being final.
+//!
+//! The signatures of the shares are not aggregated, therefore a certificate contains the signatures of
+//! the individual replicas, from which the signed contents are rebuilt by the verifier.
+//!
+//! A proof is only as secure as the keys of the committee: the keypairs of the default committee are derived
+//! from the node ids (see [Committee::with_size]), so anyone can forge a proof that any block of such a subnet
+//! is final.
+//!
+//! All the blocks of a proof belong to the epoch of its height, so that a single committee verifies it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::{
+    committee::Committee,
+    crypto::{verify, CryptoHash, CryptoHashOf, Hashed, Signature, SignatureError, Signed},
+};
+
+use super::{
+    consensus_subcomponents::{
+        block_maker::{genesis_block, Block},
+        finalizer::FinalizationShareContent,
+        notary::{NotarizationShareContent, NotarizationShareContentCOD},
+    },
+    height_index::Height,
+    pool_reader::PoolReader,
+    registry::{epoch, epoch_start},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinalityCertificate {
+    // signatures of n - f finalization shares on the block
+    Finalization(BTreeSet<Signature>),
+    // signatures of n - p acknowledgements of the block, which finalize it once its parent is final
+    Acks(BTreeSet<Signature>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertifiedBlock {
+    pub block: Block,
+    pub certificate: Option<FinalityCertificate>,
+}
+
+/// Proof that the block at `height` is final.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalityProof {
+    pub height: Height,
+    // consecutive blocks in increasing height, each one being the parent of the next one
+    pub blocks: Vec<CertifiedBlock>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidFinalityProof {
+    MissingBlock(Height),
+    BrokenChain(Height),
+    InvalidSignature(SignatureError),
+    DuplicateSigner(Height),
+    WrongEpoch(Height),
+    NotEnoughSignatures { height: Height, signatures: usize, threshold: usize },
+    NotFinal(Height),
+}
+
+impl FinalityCertificate {
+    fn signatures(&self) -> &BTreeSet<Signature> {
+        match self {
+            FinalityCertificate::Finalization(signatures) => signatures,
+            FinalityCertificate::Acks(signatures) => signatures,
+        }
+    }
+
+    // Check the signatures of the shares on `block`, which must be created by different members of the committee.
+    fn verify(&self, block: &Block, committee: &Committee, threshold: usize) -> Result<(), InvalidFinalityProof> {
+        let block_hash = CryptoHashOf::from(Hashed::crypto_hash(block));
+        let mut signers = BTreeSet::new();
+        for signature in self.signatures() {
+            if !signers.insert(signature.signer) {
+                return Err(InvalidFinalityProof::DuplicateSigner(block.height));
+            }
+            let result = match self {
+                FinalityCertificate::Finalization(_) => verify(
+                    committee,
+                    &Signed {
+                        content: FinalizationShareContent::new(block.height, block_hash.clone()),
+                        signature: signature.clone(),
+                    },
+                ),
+                FinalityCertificate::Acks(_) => verify(
+                    committee,
+                    &Signed {
+                        content: NotarizationShareContent::COD(NotarizationShareContentCOD::new(
+                            block.height,
+                            block_hash.clone(),
+                            block.parent,
+                            Some(true),
+                        )),
+                        signature: signature.clone(),
+                    },
+                ),
+            };
+            result.map_err(InvalidFinalityProof::InvalidSignature)?;
+        }
+        if signers.len() < threshold {
+            return Err(InvalidFinalityProof::NotEnoughSignatures {
+                height: block.height,
+                signatures: signers.len(),
+                threshold,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Verify that the block at the height of the proof is final, given the committee and the number of byzantine (f)
+/// and disagreeing (p) replicas of the heights of its blocks, and return its hash. As the committee can only change
+/// at the first height of an epoch, a proof containing blocks of another epoch than the one of its height is rejected.
+pub fn verify_finality_proof(
+    proof: &FinalityProof,
+    committee: &Committee,
+    byzantine_nodes_number: u32,
+    disagreeing_nodes_number: u32,
+    epoch_length: u64,
+) -> Result<CryptoHashOf<Block>, InvalidFinalityProof> {
+    let total_nodes_number = committee.size() as usize;
+    let finalization_threshold = total_nodes_number.saturating_sub(byzantine_nodes_number as usize);
+    let acks_threshold = total_nodes_number.saturating_sub(disagreeing_nodes_number as usize);
+    let first_height = match proof.blocks.first() {
+        Some(first) => first.block.height,
+        None => return Err(InvalidFinalityProof::MissingBlock(proof.height)),
+    };
+    let index = match proof.height.checked_sub(first_height) {
+        Some(index) if (index as usize) < proof.blocks.len() => index as usize,
+        _ => return Err(InvalidFinalityProof::MissingBlock(proof.height)),
+    };
+    if let Some(certified) =
+        proof.blocks.iter().find(|certified| epoch(certified.block.height, epoch_length) != epoch(proof.height, epoch_length))
+    {
+        return Err(InvalidFinalityProof::WrongEpoch(certified.block.height));
+    }
+    for (parent, child) in proof.blocks.iter().zip(proof.blocks.iter().skip(1)) {
+        if child.block.height != parent.block.height + 1 || child.block.parent != Hashed::crypto_hash(&parent.block) {
+            return Err(InvalidFinalityProof::BrokenChain(child.block.height));
+        }
+    }
+    for certified in &proof.blocks {
+        match &certified.certificate {
+            Some(certificate @ FinalityCertificate::Finalization(_)) => {
+                certificate.verify(&certified.block, committee, finalization_threshold)?
+            }
+            Some(certificate @ FinalityCertificate::Acks(_)) => certificate.verify(&certified.block, committee, acks_threshold)?,
+            None => (),
+        }
+    }
+    // a finalized block finalizes its ancestors, then acknowledged blocks are final as long as their parent is final
+    let mut is_final = match proof
+        .blocks
+        .iter()
+        .rposition(|certified| matches!(certified.certificate, Some(FinalityCertificate::Finalization(_))))
+    {
+        Some(last_finalized) => last_finalized >= index,
+        None => false,
+    };
+    if !is_final {
+        let first = &proof.blocks[0].block;
+        let mut is_parent_final = first.height == 1 && first.parent == Hashed::crypto_hash(&genesis_block());
+        for certified in &proof.blocks[..=index] {
+            is_parent_final = match certified.certificate {
+                Some(FinalityCertificate::Finalization(_)) => true,
+                Some(FinalityCertificate::Acks(_)) => is_parent_final,
+                None => false,
+            };
+        }
+        is_final = is_parent_final;
+    }
+    if is_final {
+        Ok(CryptoHashOf::from(Hashed::crypto_hash(&proof.blocks[index].block)))
+    } else {
+        Err(InvalidFinalityProof::NotFinal(proof.height))
+    }
+}
+
+/// Build the shortest proof that the finalized block at `height` is final, either with the first finalization
+/// at a later height or with the acknowledgements of the blocks between the last finalization at an earlier height
+/// (or the genesis block) and `height`. Both walks stay within the epoch of `height`, as the committee might change
+/// at the start of an epoch. Return None if the height is not finalized yet or if the pool does not contain enough
+/// shares to prove it.
+pub fn build_finality_proof(pool: &PoolReader<'_>, height: Height) -> Option<FinalityProof> {
+    let finalized_height = pool.get_finalized_height();
+    if height == 0 || height > finalized_height {
+        return None;
+    }
+    let chain = pool.get_finalized_chain()?;
+    let block_at = |h: Height| &chain[(h - 1) as usize];
+    let epoch_length = pool.get_subnet_params(height).epoch_length;
+    let first_epoch_height = epoch_start(epoch(height, epoch_length), epoch_length);
+    let last_epoch_height = epoch_start(epoch(height, epoch_length) + 1, epoch_length) - 1;
+
+    // walk up until a block certified by a finalization
+    let mut upwards = Vec::new();
+    for h in height..=std::cmp::min(finalized_height, last_epoch_height) {
+        let finalization = finalization_certificate(pool, block_at(h));
+        let found = finalization.is_some();
+        upwards.push(CertifiedBlock { block: block_at(h).clone(), certificate: finalization });
+        if found {
+            break;
+        }
+    }
+    if upwards.last().and_then(|certified| certified.certificate.as_ref()).is_none() {
+        upwards.clear();
+    }
+
+    // walk down through acknowledged blocks until a block certified by a finalization or the genesis block,
+    // the acknowledgements of the first block of an epoch other than the first one do not prove that its parent is final
+    let mut downwards = Vec::new();
+    let mut h = height;
+    loop {
+        if let Some(finalization) = finalization_certificate(pool, block_at(h)) {
+            downwards.push(CertifiedBlock { block: block_at(h).clone(), certificate: Some(finalization) });
+            break;
+        }
+        match acks_certificate(pool, block_at(h)) {
+            Some(acks) => downwards.push(CertifiedBlock { block: block_at(h).clone(), certificate: Some(acks) }),
+            None => {
+                downwards.clear();
+                break;
+            }
+        }
+        if h == 1 {
+            break;
+        }
+        if h == first_epoch_height {
+            downwards.clear();
+            break;
+        }
+        h -= 1;
+    }
+    downwards.reverse();
+
+    let blocks = match (upwards.is_empty(), downwards.is_empty()) {
+        (true, true) => return None,
+        (false, true) => upwards,
+        (true, false) => downwards,
+        (false, false) if upwards.len() <= downwards.len() => upwards,
+        (false, false) => downwards,
+    };
+    Some(FinalityProof { height, blocks })
+}
+
+// Signatures of n - f finalization shares on the block, if the pool contains enough of them.
+fn finalization_certificate(pool: &PoolReader<'_>, block: &Block) -> Option<FinalityCertificate> {
+    let subnet_params = pool.get_subnet_params(block.height);
+    let threshold = (subnet_params.total_nodes_number - subnet_params.byzantine_nodes_number) as usize;
+    let block_hash: CryptoHash = Hashed::crypto_hash(block);
+    let signatures: BTreeSet<Signature> = pool
+        .get_finalization_shares(block.height, block.height)
+        .filter(|share| share.content.block.get_ref() == &block_hash)
+        .map(|share| share.signature)
+        .take(threshold)
+        .collect();
+    if signatures.len() >= threshold {
+        Some(FinalityCertificate::Finalization(signatures))
+    } else {
+        None
+    }
+}
+
+// Signatures of n - p acknowledgements of the block, if the pool contains enough of them.
+fn acks_certificate(pool: &PoolReader<'_>, block: &Block) -> Option<FinalityCertificate> {
+    let subnet_params = pool.get_subnet_params(block.height);
+    let threshold = (subnet_params.total_nodes_number - subnet_params.disagreeing_nodes_number) as usize;
+    let block_hash: CryptoHash = Hashed::crypto_hash(block);
+    let signatures: BTreeSet<Signature> = pool
+        .get_notarization_shares(block.height)
+        .filter_map(|share| match share.content {
+            NotarizationShareContent::COD(content) if content.is_ack && content.block.get_ref() == &block_hash => {
+                Some(share.signature)
+            }
+            _ => None,
+        })
+        .take(threshold)
+        .collect();
+    if signatures.len() >= threshold {
+        Some(FinalityCertificate::Acks(signatures))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        committee::NodeId,
+        consensus_layer::{consensus_mode::ConsensusMode, consensus_subcomponents::block_maker::Payload},
+        crypto::{SignatureError, Signer, SigningDomain},
+    };
+
+    const EPOCH_LENGTH: u64 = 10;
+
+    // chain of `length` blocks on top of the genesis block
+    fn chain(length: u64) -> Vec<Block> {
+        let mut parent = Hashed::crypto_hash(&genesis_block());
+        (1..=length)
+            .map(|height| {
+                let block = Block::new(parent, Payload::new(ConsensusMode::FICC, None), height, 0, None);
+                parent = Hashed::crypto_hash(&block);
+                block
+            })
+            .collect()
+    }
+
+    fn finalization(block: &Block, signers: impl Iterator<Item = u32>) -> FinalityCertificate {
+        let block_hash = CryptoHashOf::from(Hashed::crypto_hash(block));
+        FinalityCertificate::Finalization(
+            signers
+                .map(|id| {
+                    Signer::with_derived_key(NodeId(id))
+                        .sign(FinalizationShareContent::new(block.height, block_hash.clone()))
+                        .signature
+                })
+                .collect(),
+        )
+    }
+
+    // proof that the last block of `blocks` is final, certified by a finalization of the replicas `signers`
+    fn finalization_proof(blocks: &[Block], signers: impl Iterator<Item = u32>) -> FinalityProof {
+        let last = blocks.last().expect("at least one block");
+        FinalityProof {
+            height: last.height,
+            blocks: vec![CertifiedBlock { block: last.clone(), certificate: Some(finalization(last, signers)) }],
+        }
+    }
+
+    // n = 4, f = 1, p = 0
+    fn verify_proof(proof: &FinalityProof) -> Result<CryptoHashOf<Block>, InvalidFinalityProof> {
+        verify_finality_proof(proof, &Committee::with_size(4), 1, 0, EPOCH_LENGTH)
+    }
+
+    #[test]
+    fn finalized_block_is_proven_final() {
+        let blocks = chain(3);
+        let proof = finalization_proof(&blocks, 1..=3);
+        assert_eq!(verify_proof(&proof), Ok(CryptoHashOf::from(Hashed::crypto_hash(&blocks[2]))));
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let blocks = chain(3);
+        let mut proof = finalization_proof(&blocks, 1..=3);
+        // same height and parent, but another payload
+        proof.blocks[0].block.payload = Payload::new(ConsensusMode::ICC, None);
+        assert_eq!(
+            verify_proof(&proof),
+            Err(InvalidFinalityProof::InvalidSignature(SignatureError::InvalidSignature {
+                signer: NodeId(1),
+                domain: SigningDomain::FinalizationShare,
+            }))
+        );
+    }
+
+    #[test]
+    fn signature_of_non_member_is_rejected() {
+        let proof = finalization_proof(&chain(3), [1, 2, 5].into_iter());
+        assert_eq!(
+            verify_proof(&proof),
+            Err(InvalidFinalityProof::InvalidSignature(SignatureError::UnknownSigner(NodeId(5))))
+        );
+    }
+
+    #[test]
+    fn proof_with_too_few_signers_is_rejected() {
+        let proof = finalization_proof(&chain(3), 1..=2);
+        assert_eq!(
+            verify_proof(&proof),
+            Err(InvalidFinalityProof::NotEnoughSignatures { height: 3, signatures: 2, threshold: 3 })
+        );
+    }
+
+    #[test]
+    fn proof_crossing_an_epoch_is_rejected() {
+        // the block at height 11, first one of epoch 1, is proven by the finalization of its child
+        let blocks = chain(12);
+        let proof = FinalityProof {
+            height: 10,
+            blocks: vec![
+                CertifiedBlock { block: blocks[9].clone(), certificate: None },
+                CertifiedBlock { block: blocks[10].clone(), certificate: None },
+                CertifiedBlock { block: blocks[11].clone(), certificate: Some(finalization(&blocks[11], 1..=3)) },
+            ],
+        };
+        assert_eq!(verify_proof(&proof), Err(InvalidFinalityProof::WrongEpoch(11)));
+    }
+}
@@ -7,6 +7,7 @@ use super::{
     consensus_subcomponents::{
         aggregator::{Finalization, Notarization},
         block_maker::BlockProposal,
+        equivocation::EquivocationProof,
         finalizer::FinalizationShare,
         goodifier::GoodnessArtifact,
         notary::{NotarizationShare, NotarizationShareContent},
@@ -86,6 +87,7 @@ pub struct Indexes {
     pub finalization_share: HeightIndex<CryptoHashOf<FinalizationShare>>,
     pub finalization: HeightIndex<CryptoHashOf<Finalization>>,
     pub goodness_artifact: HeightIndex<CryptoHashOf<GoodnessArtifact>>,
+    pub equivocation_proof: HeightIndex<CryptoHashOf<EquivocationProof>>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -98,6 +100,7 @@ impl Indexes {
             finalization_share: HeightIndex::new(),
             finalization: HeightIndex::new(),
             goodness_artifact: HeightIndex::new(),
+            equivocation_proof: HeightIndex::new(),
         }
     }
 
@@ -126,6 +129,9 @@ impl Indexes {
             ConsensusMessage::GoodnessArtifact(artifact) => self
                 .goodness_artifact
                 .insert(artifact.children_height, &CryptoHashOf::from(hash)),
+            ConsensusMessage::EquivocationProof(artifact) => self
+                .equivocation_proof
+                .insert(artifact.height(), &CryptoHashOf::from(hash)),
         };
     }
 
@@ -154,6 +160,9 @@ impl Indexes {
             ConsensusMessage::GoodnessArtifact(artifact) => self
                 .goodness_artifact
                 .remove(artifact.children_height, &CryptoHashOf::from(hash)),
+            ConsensusMessage::EquivocationProof(artifact) => self
+                .equivocation_proof
+                .remove(artifact.height(), &CryptoHashOf::from(hash)),
         };
     }
 }
@@ -228,6 +237,12 @@ impl SelectIndex for CryptoHashOf<GoodnessArtifact> {
     }
 }
 
+impl SelectIndex for CryptoHashOf<EquivocationProof> {
+    fn select_index(indexes: &Indexes) -> &HeightIndex<Self> {
+        &indexes.equivocation_proof
+    }
+}
+
 pub struct HeightRange {
     pub min: Height,
     pub max: Height,
@@ -1,8 +1,9 @@
-use std::{collections::BTreeMap, fmt::Debug};
+use std::{collections::{BTreeMap, BTreeSet}, fmt::Debug};
 
 use crate::{
     crypto::{CryptoHash, CryptoHashOf},
     time_source::{Time, TimeSource},
+    SubnetParams,
 };
 
 use super::{
@@ -13,11 +14,13 @@ use super::{
     consensus_subcomponents::{
         aggregator::{Finalization, Notarization},
         block_maker::{Block, BlockProposal},
+        equivocation::EquivocationProof,
         finalizer::FinalizationShare,
         goodifier::GoodnessArtifact,
         notary::NotarizationShare,
     },
     height_index::{Height, HeightIndex, HeightIndexedPool, HeightRange, Indexes, SelectIndex},
+    registry::Registry,
 };
 
 type UnvalidatedConsensusArtifact = UnvalidatedArtifact<ConsensusMessage>;
@@ -60,13 +63,13 @@ impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone + Debug> InMemoryPool
 
     fn insert(&mut self, artifact: T) {
         let msg = artifact.as_ref();
-        let hash = msg.get_cm_hash().digest().clone();
-        self.indexes.insert(msg, hash.clone());
+        let hash = *msg.get_cm_hash().digest();
+        self.indexes.insert(msg, hash);
         self.artifacts.entry(hash).or_insert(artifact);
     }
 
     fn remove(&mut self, msg_id: &ConsensusMessageId) -> Option<T> {
-        self.remove_by_hash(&msg_id.hash.digest())
+        self.remove_by_hash(msg_id.hash.digest())
     }
 
     fn get_by_hashes<S: ConsensusMessageHashable>(&self, hashes: Vec<&CryptoHashOf<S>>) -> Vec<S> {
@@ -93,7 +96,7 @@ impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone + Debug> InMemoryPool
     /// Remove a consensus message by its hash
     pub fn remove_by_hash(&mut self, hash: &CryptoHash) -> Option<T> {
         self.artifacts.remove(hash).map(|artifact| {
-            self.indexes.remove(artifact.as_ref(), hash.to_string());
+            self.indexes.remove(artifact.as_ref(), *hash);
             artifact
         })
     }
@@ -122,12 +125,15 @@ impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone + Debug> InMemoryPool
     pub fn finalization_share(&self) -> &dyn HeightIndexedPool<FinalizationShare> {
         self
     }
-    fn finalization(&self) -> &dyn HeightIndexedPool<Finalization> {
+    pub fn finalization(&self) -> &dyn HeightIndexedPool<Finalization> {
         self
     }
     pub fn goodness_artifact(&self) -> &dyn HeightIndexedPool<GoodnessArtifact> {
         self
     }
+    pub fn equivocation_proof(&self) -> &dyn HeightIndexedPool<EquivocationProof> {
+        self
+    }
 }
 
 impl<
@@ -198,16 +204,23 @@ where
 pub struct ConsensusPoolImpl {
     validated: Box<InMemoryPoolSection<ValidatedConsensusArtifact>>,
     unvalidated: Box<InMemoryPoolSection<UnvalidatedConsensusArtifact>>,
+    registry: Registry,
 }
 
 impl ConsensusPoolImpl {
-    pub fn new() -> Self {
+    pub fn new(subnet_params: SubnetParams) -> Self {
         Self {
             validated: Box::new(InMemoryPoolSection::new()),
             unvalidated: Box::new(InMemoryPoolSection::new()),
+            registry: Registry::new(subnet_params),
         }
     }
 
+    /// Subnet parameters of each height, according to the finalized blocks in the pool.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
     pub fn validated(&self) -> &InMemoryPoolSection<ValidatedConsensusArtifact> {
         self.validated.pool_section()
     }
@@ -250,37 +263,59 @@ impl ConsensusPoolImpl {
                         timestamp,
                     });
                 }
+                ChangeAction::RemoveFromUnvalidated(to_remove) => {
+                    unvalidated_ops.remove(to_remove.get_id());
+                }
             }
         }
         self.apply_changes_unvalidated(unvalidated_ops);
         self.apply_changes_validated(validated_ops);
+        self.update_registry();
+    }
+
+    // Apply the registry updates carried by the blocks finalized since the last call, in order of height.
+    fn update_registry(&mut self) {
+        let finalized_tip = match self.finalized_block() {
+            Some(block) if block.height > self.registry.applied_height() => block,
+            _ => return,
+        };
+        let mut newly_finalized = vec![finalized_tip];
+        while let Some(block) = newly_finalized.last() {
+            if block.height <= self.registry.applied_height() + 1 {
+                break;
+            }
+            let parent = self
+                .validated()
+                .block_proposal()
+                .get_by_height(block.height - 1)
+                .find(|proposal| proposal.content.get_hash() == &block.parent);
+            match parent {
+                Some(parent) => newly_finalized.push(parent.content.value),
+                // retry once the missing ancestor is received
+                None => return,
+            }
+        }
+        for block in newly_finalized.iter().rev() {
+            self.registry.apply_finalized(block.height, block.payload.registry_update.as_deref());
+        }
     }
 
     pub fn finalized_block(&self) -> Option<Block> {
         get_highest_finalized_block(self)
     }
 
-    pub fn finalized_block_hash_at_height(&self, height: Height) -> Option<String> {
-        match self
+    pub fn finalized_block_hash_at_height(&self, height: Height) -> Option<CryptoHash> {
+        // several finalizations of the same block, signed by different replicas, might be validated
+        let finalized_blocks: BTreeSet<CryptoHash> = self
             .validated()
             .finalization()
             .get_by_height(height)
-            .count()
-        {
-            0 => None,
-            1 => Some(
-                self.validated()
-                    .finalization()
-                    .get_by_height(height)
-                    .last()
-                    .unwrap()
-                    .content
-                    .block
-                    .get_ref()
-                    .to_owned(),
-            ),
-            _ => panic!("more than one finalized blocks at the same height"),
+            .map(|finalization| *finalization.content.block.get_ref())
+            .collect();
+        if finalized_blocks.len() > 1 {
+            panic!("more than one finalized blocks at the same height");
         }
+        finalized_blocks.into_iter().next()
     }
 
     fn apply_changes_validated(&mut self, ops: PoolSectionOps<ValidatedConsensusArtifact>) {
@@ -1,15 +1,18 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
 use crate::{
     consensus_layer::pool::ConsensusPoolImpl,
-    crypto::CryptoHashOf,
-    time_source::{system_time_now, Time},
+    committee::NodeId,
+    crypto::{CryptoHash, CryptoHashOf},
+    time_source::{monotonic_time_now, system_time_now, Time},
+    PhaseTimestamps, SubnetParams,
 };
 
 use super::{
     artifacts::ConsensusMessageHashable,
     consensus_subcomponents::{
         block_maker::{Block, BlockProposal},
+        equivocation::{notarization_shares_conflict, proposals_conflict, EquivocationProof},
         finalizer::FinalizationShare,
         goodifier::GoodnessArtifact,
         notary::{NotarizationShare, NotarizationShareContent},
@@ -34,6 +37,17 @@ impl<'a> PoolReader<'a> {
         self.pool
     }
 
+    /// Get the subnet parameters in effect at the given height.
+    pub fn get_subnet_params(&self, h: Height) -> &'a SubnetParams {
+        self.pool.registry().subnet_params(h)
+    }
+
+    /// Return true if the subnet parameters of the given height can no longer change,
+    /// artifacts of later heights must not be created nor validated.
+    pub fn is_registry_known(&self, h: Height) -> bool {
+        self.pool.registry().is_known(h)
+    }
+
     /// Get all valid notarization shares at the given height.
     pub fn get_notarization_shares(
         &self,
@@ -88,10 +102,35 @@ impl<'a> PoolReader<'a> {
         self.pool.finalized_block()
     }
 
-    pub fn get_finalized_block_hash_at_height(&self, height: Height) -> Option<String> {
+    pub fn get_finalized_block_hash_at_height(&self, height: Height) -> Option<CryptoHash> {
         self.pool.finalized_block_hash_at_height(height)
     }
 
+    /// Get the blocks of the finalized chain, from height 1 to the finalized tip.
+    /// Return None if an ancestor of the finalized tip has not been received yet.
+    pub fn get_finalized_chain(&self) -> Option<Vec<Block>> {
+        self.get_finalized_chain_from(1)
+    }
+
+    /// Get the blocks of the finalized chain from height `from` (at least 1) to the finalized tip,
+    /// walking down from the tip only as far as `from`.
+    /// Return None if an ancestor of the finalized tip at or above `from` has not been received yet.
+    pub fn get_finalized_chain_from(&self, from: Height) -> Option<Vec<Block>> {
+        let mut chain = match self.get_finalized_tip() {
+            Some(tip) if tip.height >= from => vec![tip],
+            _ => return Some(vec![]),
+        };
+        while let Some(block) = chain.last() {
+            if block.height <= std::cmp::max(from, 1) {
+                break;
+            }
+            let parent = self.get_block(&CryptoHashOf::from(block.parent), block.height - 1).ok()?;
+            chain.push(parent);
+        }
+        chain.reverse();
+        Some(chain)
+    }
+
     /// Return a valid block with the matching hash and height if it exists.
     pub fn get_block(&self, hash: &CryptoHashOf<Block>, h: Height) -> Result<Block, ()> {
         let mut blocks: Vec<BlockProposal> = self
@@ -108,16 +147,56 @@ impl<'a> PoolReader<'a> {
     }
 
     /// Return all valid notarized blocks of a given height.
+    /// A notarized block which is not validated yet (ex. if its notarization was received before the block)
+    /// is skipped until it is validated.
     pub fn get_notarized_blocks(&'a self, h: Height) -> Box<dyn Iterator<Item = Block> + 'a> {
         Box::new(
             self.pool
                 .validated()
                 .notarization()
                 .get_by_height(h)
-                .map(move |x| self.get_block(&x.content.block, h).unwrap()),
+                .filter_map(move |x| self.get_block(&x.content.block, h).ok()),
         )
     }
 
+    /// Get all valid equivocation proofs of misbehavior at the given height.
+    pub fn get_equivocation_proofs(&self, h: Height) -> Box<dyn Iterator<Item = EquivocationProof>> {
+        self.pool.validated().equivocation_proof().get_by_height(h)
+    }
+
+    /// Find the replicas which signed conflicting block proposals or notarization shares at the given height,
+    /// with one proof for each pair of conflicting artifacts in the validated section of the pool.
+    pub fn find_equivocations(&self, h: Height) -> Vec<EquivocationProof> {
+        let proposals: Vec<BlockProposal> = self.pool.validated().block_proposal().get_by_height(h).collect();
+        let shares: Vec<NotarizationShare> = self.get_notarization_shares(h).collect();
+        let mut proofs = Vec::new();
+        for (i, first) in proposals.iter().enumerate() {
+            for second in proposals[i + 1..].iter().filter(|second| proposals_conflict(first, second)) {
+                proofs.push(EquivocationProof::block_proposals(first.clone(), second.clone()));
+            }
+        }
+        for (i, first) in shares.iter().enumerate() {
+            for second in shares[i + 1..].iter().filter(|second| notarization_shares_conflict(first, second)) {
+                proofs.push(EquivocationProof::notarization_shares(first.clone(), second.clone()));
+            }
+        }
+        proofs
+    }
+
+    /// Return true if the shares of the replica at the given height must be ignored, as it equivocated
+    /// at most `equivocation_exclusion` - 1 heights before.
+    pub fn is_excluded(&self, node_id: NodeId, h: Height) -> bool {
+        let equivocation_exclusion = self.get_subnet_params(h).equivocation_exclusion;
+        if equivocation_exclusion == 0 {
+            return false;
+        }
+        self.pool
+            .validated()
+            .equivocation_proof()
+            .get_by_height_range(HeightRange::new(h.saturating_sub(equivocation_exclusion - 1), h))
+            .any(|proof| proof.offender() == node_id)
+    }
+
     pub fn print_goodness_artifacts_at_height(&self, height: Height) {
         for good in self
             .pool
@@ -138,7 +217,7 @@ impl<'a> PoolReader<'a> {
 
     pub fn get_latest_goodness_artifact_for_parent(
         &self,
-        parent_hash: &String,
+        parent_hash: &CryptoHash,
         children_height: Height,
     ) -> Option<GoodnessArtifact> {
         self.pool
@@ -151,7 +230,7 @@ impl<'a> PoolReader<'a> {
 
     pub fn exists_goodness_artifact_for_parent(
         &self,
-        parent_hash: &String,
+        parent_hash: &CryptoHash,
         height: Height,
     ) -> bool {
         match self.get_latest_goodness_artifact_for_parent(parent_hash, height) {
@@ -177,9 +256,85 @@ impl<'a> PoolReader<'a> {
         get_notarization_time(prev_height).map(|notarization_time| notarization_time)
     }
 
+    /// Get the time elapsed between the start of the round of the given height and its first notarization.
+    /// Return None if the height is not notarized yet or its round start is not known.
+    pub fn get_round_duration(&self, height: Height) -> Option<Duration> {
+        let validated = self.pool.validated();
+        let round_start_time = self.get_round_start_time(height)?;
+        let notarization_time = validated
+            .notarization()
+            .get_by_height(height)
+            .flat_map(|x| validated.get_timestamp(&x.get_id()))
+            .min()?;
+        notarization_time.checked_duration_since(round_start_time)
+    }
+
+    /// Get the time at which each phase of the given height was observed locally,
+    /// assuming that the height is finalized now.
+    pub fn get_phase_timestamps(
+        &self,
+        height: Height,
+        node_id: NodeId,
+        subnet_params: &SubnetParams,
+    ) -> PhaseTimestamps {
+        let validated = self.pool.validated();
+        let notarization_shares: Vec<(NotarizationShare, Time)> = validated
+            .notarization_share()
+            .get_by_height(height)
+            .filter_map(|x| validated.get_timestamp(&x.get_id()).map(|t| (x, t)))
+            .collect();
+        let acks = notarization_shares.iter().filter_map(|(share, t)| match &share.content {
+            NotarizationShareContent::COD(content) if content.is_ack => Some((*content.block.get_ref(), *t)),
+            _ => None,
+        });
+        let finalization_shares = validated
+            .finalization_share()
+            .get_by_height(height)
+            .filter_map(|x| {
+                validated
+                    .get_timestamp(&x.get_id())
+                    .map(|t| (*x.content.block.get_ref(), t))
+            });
+        PhaseTimestamps {
+            round_start: if height > 1 { self.get_round_start_time(height) } else { None },
+            first_proposal: validated
+                .block_proposal()
+                .get_by_height(height)
+                .flat_map(|x| validated.get_timestamp(&x.get_id()))
+                .min(),
+            own_notarization_share: notarization_shares
+                .iter()
+                .filter(|(share, _)| share.signature.signer == node_id)
+                .map(|(_, t)| *t)
+                .min(),
+            notarization: validated
+                .notarization()
+                .get_by_height(height)
+                .flat_map(|x| validated.get_timestamp(&x.get_id()))
+                .min(),
+            acks_quorum: quorum_time(
+                acks,
+                (subnet_params.total_nodes_number - subnet_params.disagreeing_nodes_number) as usize,
+            ),
+            finalization_shares_quorum: quorum_time(
+                finalization_shares,
+                (subnet_params.total_nodes_number - subnet_params.byzantine_nodes_number) as usize,
+            ),
+            finalization: monotonic_time_now(),
+        }
+    }
+
+    /// Get the time elapsed since the given block was created by its proposer, according to the
+    /// system time of the local replica. Return None if the block does not carry the timestamp
+    /// of its proposer or if the timestamp is in the future due to clock skew.
+    pub fn get_end_to_end_latency(&self, hash: &CryptoHashOf<Block>, h: Height) -> Option<Duration> {
+        let proposer_timestamp = self.get_block(hash, h).ok()?.proposer_timestamp?;
+        system_time_now().checked_duration_since(proposer_timestamp)
+    }
+
     pub fn get_finalization_time(&self, height: Height) -> Option<Duration> {
         if let Some(round_start_time) = self.get_round_start_time(height) {
-            let current_time = system_time_now();
+            let current_time = monotonic_time_now();
             let finalization_time = current_time - round_start_time;
             // println!("Time to finalize block: {:?}", finalization_time);
             return Some(finalization_time);
@@ -187,3 +342,19 @@ impl<'a> PoolReader<'a> {
         None
     }
 }
+
+// Return the earliest time at which `quorum` of the given timestamped artifacts
+// were received for the same block, None if no block reached the quorum.
+pub(crate) fn quorum_time(artifacts: impl Iterator<Item = (CryptoHash, Time)>, quorum: usize) -> Option<Time> {
+    let mut times_by_block = BTreeMap::<CryptoHash, Vec<Time>>::new();
+    for (block, time) in artifacts {
+        times_by_block.entry(block).or_default().push(time);
+    }
+    times_by_block
+        .into_values()
+        .filter_map(|mut times| {
+            times.sort();
+            times.get(quorum.checked_sub(1)?).copied()
+        })
+        .min()
+}
@@ -0,0 +1,336 @@
+//! Committee reconfiguration at epoch boundaries.
+//!
+//! Heights are grouped in epochs of `epoch_length` heights, the first epoch starting at height 1. The payload of a
+//! block can carry a [RegistryUpdate] which adds or removes replicas and changes f and p from the first height of a
+//! later epoch. An update carried by a block of epoch `e` takes effect at the earliest from the first height of epoch
+//! `e + 2`, so that the subnet parameters of a height only depend on blocks which are at least one epoch older.
+//! Updates are applied once their block is finalized and the parameters of a height are known as soon as all the
+//! blocks which might change them are finalized (see [Registry::is_known]).
+//!
+//! An update is only valid if it is signed by at least n - f members of the committee of the height of the block
+//! carrying it, so that a single block maker cannot change the committee.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    committee::{Committee, Member, NodeId},
+    crypto::{verify, CanonicalEncode, CryptoHashOf, Signable, Signature, SignatureError, Signed, SigningDomain},
+    SubnetParams,
+};
+
+use super::{
+    consensus_subcomponents::block_maker::Block,
+    height_index::Height,
+    pool_reader::PoolReader,
+};
+
+/// Change of the subnet parameters, from `effective_height` onwards.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RegistryUpdate {
+    // first height of the epoch from which the update takes effect
+    pub effective_height: Height,
+    #[serde(default)]
+    pub added: BTreeMap<NodeId, Member>,
+    #[serde(default)]
+    pub removed: BTreeSet<NodeId>,
+    #[serde(default)]
+    pub byzantine_nodes_number: Option<u32>,
+    #[serde(default)]
+    pub disagreeing_nodes_number: Option<u32>,
+}
+
+impl CanonicalEncode for RegistryUpdate {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.effective_height.encode_canonical(bytes);
+        self.added.encode_canonical(bytes);
+        self.removed.encode_canonical(bytes);
+        self.byzantine_nodes_number.encode_canonical(bytes);
+        self.disagreeing_nodes_number.encode_canonical(bytes);
+    }
+}
+
+impl Signable for RegistryUpdate {
+    fn signing_domain(&self) -> SigningDomain {
+        SigningDomain::RegistryUpdate
+    }
+}
+
+/// Registry update with the signatures of the members of the committee which approved it.
+pub type AuthorizedRegistryUpdate = Signed<RegistryUpdate, BTreeSet<Signature>>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidRegistryUpdate {
+    NotAtEpochStart(Height),
+    TooEarly { effective_height: Height, min_effective_height: Height },
+    InvalidSignature(SignatureError),
+    NotEnoughSignatures { signatures: usize, threshold: usize },
+}
+
+impl RegistryUpdate {
+    /// Check that the update can be carried by a block at `height`.
+    pub fn check(&self, height: Height, epoch_length: u64) -> Result<(), InvalidRegistryUpdate> {
+        if epoch_start(epoch(self.effective_height, epoch_length), epoch_length) != self.effective_height {
+            return Err(InvalidRegistryUpdate::NotAtEpochStart(self.effective_height));
+        }
+        let min_effective_height = min_effective_height(height, epoch_length);
+        if self.effective_height < min_effective_height {
+            return Err(InvalidRegistryUpdate::TooEarly { effective_height: self.effective_height, min_effective_height });
+        }
+        Ok(())
+    }
+}
+
+/// Check that the update carried by a block at `height` can take effect where it says and that it is signed
+/// by at least n - f different members of the committee of `height`, whose parameters are `subnet_params`.
+pub fn check_authorized_update(
+    update: &AuthorizedRegistryUpdate,
+    height: Height,
+    subnet_params: &SubnetParams,
+) -> Result<(), InvalidRegistryUpdate> {
+    update.content.check(height, subnet_params.epoch_length)?;
+    for signature in &update.signature {
+        let signed = Signed { content: update.content.clone(), signature: signature.clone() };
+        verify(&subnet_params.committee, &signed).map_err(InvalidRegistryUpdate::InvalidSignature)?;
+    }
+    // the signatures are ordered by signer, so that a signer with several signatures is counted once
+    let signers: BTreeSet<NodeId> = update.signature.iter().map(|signature| signature.signer).collect();
+    let threshold = (subnet_params.total_nodes_number - subnet_params.byzantine_nodes_number) as usize;
+    if signers.len() < threshold {
+        return Err(InvalidRegistryUpdate::NotEnoughSignatures { signatures: signers.len(), threshold });
+    }
+    Ok(())
+}
+
+/// Epoch of a height, the genesis block belongs to the first epoch.
+pub fn epoch(height: Height, epoch_length: u64) -> u64 {
+    height.saturating_sub(1) / epoch_length
+}
+
+/// First height of an epoch.
+pub fn epoch_start(epoch: u64, epoch_length: u64) -> Height {
+    epoch * epoch_length + 1
+}
+
+/// First height from which an update carried by a block at `height` can take effect.
+pub fn min_effective_height(height: Height, epoch_length: u64) -> Height {
+    epoch_start(epoch(height, epoch_length) + 2, epoch_length)
+}
+
+/// Subnet parameters of each height, derived from the updates carried by the finalized blocks.
+pub struct Registry {
+    // parameters in effect from each height onwards, the first version is the one the replica was started with
+    versions: BTreeMap<Height, SubnetParams>,
+    // updates applied so far, each update is applied at most once even if it is carried by several blocks
+    applied_updates: BTreeSet<RegistryUpdate>,
+    // height of the last finalized block whose update has been applied
+    applied_height: Height,
+}
+
+impl Registry {
+    pub fn new(subnet_params: SubnetParams) -> Self {
+        Self {
+            versions: BTreeMap::from([(0, subnet_params)]),
+            applied_updates: BTreeSet::new(),
+            applied_height: 0,
+        }
+    }
+
+    /// Parameters in effect at the given height, according to the blocks finalized so far.
+    pub fn subnet_params(&self, height: Height) -> &SubnetParams {
+        self.versions
+            .range(..=height)
+            .next_back()
+            .map(|(_, subnet_params)| subnet_params)
+            .expect("initial subnet parameters")
+    }
+
+    pub fn applied_height(&self) -> Height {
+        self.applied_height
+    }
+
+    /// Return true if all the blocks which might change the parameters of the given height are finalized,
+    /// so that all replicas agree on them.
+    pub fn is_known(&self, height: Height) -> bool {
+        let epoch_length = self.versions[&0].epoch_length;
+        match epoch(height, epoch_length) {
+            0 | 1 => true,
+            e => self.applied_height >= epoch_start(e - 1, epoch_length) - 1,
+        }
+    }
+
+    pub fn is_applied(&self, update: &RegistryUpdate) -> bool {
+        self.applied_updates.contains(update)
+    }
+
+    /// Apply the update carried by the finalized block at `height`, if any.
+    /// Must be called for each finalized height in increasing order.
+    pub fn apply_finalized(&mut self, height: Height, update: Option<&AuthorizedRegistryUpdate>) {
+        self.applied_height = height;
+        let authorized_update = match update {
+            Some(update) if !self.is_applied(&update.content) => update,
+            _ => return,
+        };
+        let update = &authorized_update.content;
+        if let Err(e) = check_authorized_update(authorized_update, height, self.subnet_params(height)) {
+            println!("\nIgnoring registry update: {:?} finalized at height: {}, reason: {:?}", update, height, e);
+            return;
+        }
+        // the update also changes the versions which take effect after it
+        let mut updated_versions = BTreeMap::new();
+        updated_versions.insert(update.effective_height, self.subnet_params(update.effective_height).clone());
+        updated_versions.extend(
+            self.versions
+                .range(update.effective_height + 1..)
+                .map(|(h, subnet_params)| (*h, subnet_params.clone())),
+        );
+        for (h, subnet_params) in updated_versions.iter_mut() {
+            match apply_update(subnet_params, update) {
+                Some(updated) => *subnet_params = updated,
+                None => {
+                    println!("\nIgnoring registry update: {:?} finalized at height: {}, as the subnet parameters from height: {} would be invalid", update, height, h);
+                    return;
+                }
+            }
+        }
+        println!(
+            "\nApplying registry update finalized at height: {}, from height: {} the committee has {} replicas",
+            height,
+            update.effective_height,
+            updated_versions[&update.effective_height].total_nodes_number
+        );
+        self.versions.extend(updated_versions);
+        self.applied_updates.insert(update.clone());
+    }
+}
+
+// Return None if the resulting committee would be empty or would not tolerate f byzantine and p disagreeing replicas.
+fn apply_update(subnet_params: &SubnetParams, update: &RegistryUpdate) -> Option<SubnetParams> {
+    let mut members: BTreeMap<NodeId, Member> = subnet_params
+        .committee
+        .node_ids()
+        .filter(|node_id| !update.removed.contains(node_id))
+        .map(|node_id| (node_id, subnet_params.committee.member(node_id).cloned().expect("member of the committee")))
+        .collect();
+    members.extend(update.added.clone());
+    let committee = Committee::new(members);
+    let f = update.byzantine_nodes_number.unwrap_or(subnet_params.byzantine_nodes_number);
+    let p = update.disagreeing_nodes_number.unwrap_or(subnet_params.disagreeing_nodes_number);
+    if committee.size() == 0 || committee.size() < 3 * f + 2 * p + 1 {
+        return None;
+    }
+    let mut updated = subnet_params.clone();
+    updated.total_nodes_number = committee.size();
+    updated.committee = committee;
+    updated.byzantine_nodes_number = f;
+    updated.disagreeing_nodes_number = p;
+    Some(updated)
+}
+
+/// Return true if `update` is neither applied nor carried by `parent` or one of its ancestors
+/// which are not finalized yet, so that it can be carried by a child of `parent`.
+pub fn is_update_pending(pool: &PoolReader<'_>, parent: &Block, update: &RegistryUpdate) -> bool {
+    if pool.pool().registry().is_applied(update) {
+        return false;
+    }
+    let finalized_height = pool.get_finalized_height();
+    let mut block = parent.clone();
+    while block.height > finalized_height {
+        if block.payload.registry_update.as_ref().map(|carried| &carried.content) == Some(update) {
+            return false;
+        }
+        match pool.get_block(&CryptoHashOf::from(block.parent), block.height - 1) {
+            Ok(ancestor) => block = ancestor,
+            // the parent is the genesis block or has not been received yet
+            Err(()) => break,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{PublicKey, Signer};
+
+    const EPOCH_LENGTH: u64 = 10;
+
+    // n = 4, f = 1, p = 0
+    fn subnet_params() -> SubnetParams {
+        SubnetParams::builder(Committee::with_size(4))
+            .byzantine_nodes_number(1)
+            .disagreeing_nodes_number(0)
+            .epoch_length(EPOCH_LENGTH)
+            .build()
+            .expect("valid subnet parameters")
+    }
+
+    fn update(effective_height: Height, added: &[u32], removed: &[u32]) -> RegistryUpdate {
+        RegistryUpdate {
+            effective_height,
+            added: added
+                .iter()
+                .map(|id| (NodeId(*id), Member { public_key: PublicKey::derived(NodeId(*id)), network_id: None }))
+                .collect(),
+            removed: removed.iter().map(|id| NodeId(*id)).collect(),
+            byzantine_nodes_number: None,
+            disagreeing_nodes_number: None,
+        }
+    }
+
+    fn authorize(update: RegistryUpdate, signers: &[u32]) -> AuthorizedRegistryUpdate {
+        let signature = signers
+            .iter()
+            .map(|id| Signer::with_derived_key(NodeId(*id)).sign(update.clone()).signature)
+            .collect();
+        Signed { content: update, signature }
+    }
+
+    #[test]
+    fn update_needs_signatures_of_n_minus_f_members() {
+        let subnet_params = subnet_params();
+        assert_eq!(check_authorized_update(&authorize(update(21, &[5], &[]), &[1, 2, 3]), 5, &subnet_params), Ok(()));
+        assert_eq!(
+            check_authorized_update(&authorize(update(21, &[5], &[]), &[1, 2]), 5, &subnet_params),
+            Err(InvalidRegistryUpdate::NotEnoughSignatures { signatures: 2, threshold: 3 })
+        );
+        // the replica being added is not a member yet
+        assert_eq!(
+            check_authorized_update(&authorize(update(21, &[5], &[]), &[1, 2, 5]), 5, &subnet_params),
+            Err(InvalidRegistryUpdate::InvalidSignature(SignatureError::UnknownSigner(NodeId(5))))
+        );
+    }
+
+    #[test]
+    fn update_takes_effect_from_the_second_next_epoch() {
+        let subnet_params = subnet_params();
+        // carried by a block of epoch 0, the update can take effect from epoch 2 which starts at height 21
+        assert_eq!(
+            check_authorized_update(&authorize(update(11, &[5], &[]), &[1, 2, 3]), 5, &subnet_params),
+            Err(InvalidRegistryUpdate::TooEarly { effective_height: 11, min_effective_height: 21 })
+        );
+        assert_eq!(
+            check_authorized_update(&authorize(update(22, &[5], &[]), &[1, 2, 3]), 5, &subnet_params),
+            Err(InvalidRegistryUpdate::NotAtEpochStart(22))
+        );
+        let mut registry = Registry::new(subnet_params);
+        registry.apply_finalized(5, Some(&authorize(update(21, &[5], &[]), &[1, 2, 3])));
+        assert_eq!(registry.subnet_params(20).total_nodes_number, 4);
+        assert_eq!(registry.subnet_params(21).total_nodes_number, 5);
+        assert!(registry.subnet_params(21).committee.contains(NodeId(5)));
+        // the parameters of epoch 2 are only known once the blocks of epoch 0 are finalized
+        assert!(!registry.is_known(21));
+        registry.apply_finalized(10, None);
+        assert!(registry.is_known(21));
+    }
+
+    #[test]
+    fn update_leaving_too_few_replicas_is_ignored() {
+        let mut registry = Registry::new(subnet_params());
+        // 3 replicas cannot tolerate f = 1 byzantine replica, as 3f + 2p + 1 = 4
+        let shrinking = authorize(update(21, &[], &[4]), &[1, 2, 3]);
+        registry.apply_finalized(5, Some(&shrinking));
+        assert!(!registry.is_applied(&shrinking.content));
+        assert_eq!(registry.subnet_params(21).total_nodes_number, 4);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Counters of the calls to a subcomponent made by the [PriorityScheduler].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SubcomponentMetrics {
+    // number of times the subcomponent was called
+    calls: u64,
+    // number of calls which returned a non-empty change set
+    non_empty: u64,
+    // number of times the subcomponent was not called as a subcomponent before it returned a change set or exceeded its time budget
+    starved: u64,
+    // number of times the subcomponent was called before the ones with higher priority because it was starved for too long
+    boosted: u64,
+    // number of calls which took longer than the time budget of the subcomponent
+    budget_overruns: u64,
+    // total time spent in the subcomponent
+    total_time: Duration,
+}
+
+/// Metrics of each subcomponent, by name of the subcomponent.
+pub type SchedulerMetrics = BTreeMap<String, SubcomponentMetrics>;
+
+/// A subcomponent, as seen by the [PriorityScheduler]: its name, its time budget
+/// and the closure invoking its `on_state_change`.
+pub struct Subcomponent<'a, T> {
+    pub name: &'static str,
+    pub time_budget: Duration,
+    pub call: &'a dyn Fn() -> (Vec<T>, bool),
+}
+
+// Call on_state_change of the subcomponents in order of priority.
+//
+// Only the first non-empty change set is returned, therefore lower priority
+// subcomponents are not called as long as higher priority ones make progress.
+// To guarantee that every subcomponent is eventually called, a subcomponent
+// which has not been called for `max_starvation` consecutive invocations is
+// called before all the others. A subcomponent exceeding its time budget ends
+// the invocation, so that a slow subcomponent does not delay the processing
+// of incoming artifacts further; the subcomponents which were not called are
+// then boosted in the following invocations.
+pub struct PriorityScheduler {
+    // for each subcomponent, number of consecutive invocations in which it was not called
+    starvation: RefCell<Vec<u64>>,
+    max_starvation: u64,
+    metrics: Arc<RwLock<SchedulerMetrics>>,
+}
+
+impl PriorityScheduler {
+    pub fn new(max_starvation: u64, metrics: Arc<RwLock<SchedulerMetrics>>) -> Self {
+        Self {
+            starvation: RefCell::new(Vec::new()),
+            max_starvation,
+            metrics,
+        }
+    }
+
+    // Call the subcomponents, given in order of decreasing priority, until one of
+    // them returns a non-empty change set, which is returned. Return an empty
+    // change set if none of them does.
+    pub fn call_next<T>(&self, subcomponents: &[Subcomponent<'_, T>]) -> (Vec<T>, bool) {
+        let mut starvation = self.starvation.borrow_mut();
+        starvation.resize(subcomponents.len(), 0);
+        let mut metrics = self.metrics.write().unwrap();
+
+        // starved subcomponents first (stable sort keeps the priority order within the two groups)
+        let mut order: Vec<usize> = (0..subcomponents.len()).collect();
+        order.sort_by_key(|index| starvation[*index] < self.max_starvation);
+
+        let mut called = vec![false; subcomponents.len()];
+        let mut result = (vec![], false);
+        for index in order {
+            let subcomponent = &subcomponents[index];
+            let subcomponent_metrics = metrics.entry(subcomponent.name.to_string()).or_default();
+            if starvation[index] >= self.max_starvation {
+                subcomponent_metrics.boosted += 1;
+            }
+
+            let start = Instant::now();
+            let (change_set, to_broadcast) = (subcomponent.call)();
+            let elapsed = start.elapsed();
+
+            called[index] = true;
+            starvation[index] = 0;
+            subcomponent_metrics.calls += 1;
+            subcomponent_metrics.total_time += elapsed;
+            let over_budget = elapsed > subcomponent.time_budget;
+            if over_budget {
+                subcomponent_metrics.budget_overruns += 1;
+                println!(
+                    "\nSubcomponent {} exceeded its time budget of {:?}, took {:?}",
+                    subcomponent.name, subcomponent.time_budget, elapsed
+                );
+            }
+            if !change_set.is_empty() {
+                subcomponent_metrics.non_empty += 1;
+                result = (change_set, to_broadcast);
+                break;
+            }
+            if over_budget {
+                break;
+            }
+        }
+
+        for (index, subcomponent) in subcomponents.iter().enumerate() {
+            if !called[index] {
+                starvation[index] += 1;
+                metrics.entry(subcomponent.name.to_string()).or_default().starved += 1;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // subcomponents which record the order in which they are called, the ones in `progressing` return a change set
+    fn call_next(scheduler: &PriorityScheduler, progressing: &[&'static str], time_budget: Duration) -> Vec<&'static str> {
+        let calls = RefCell::new(Vec::new());
+        let call = |name: &'static str| {
+            calls.borrow_mut().push(name);
+            if time_budget.is_zero() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            if progressing.contains(&name) { (vec![name], false) } else { (vec![], false) }
+        };
+        let (first, second, third) = (|| call("first"), || call("second"), || call("third"));
+        let subcomponents = [
+            Subcomponent { name: "first", time_budget, call: &first },
+            Subcomponent { name: "second", time_budget, call: &second },
+            Subcomponent { name: "third", time_budget, call: &third },
+        ];
+        scheduler.call_next(&subcomponents);
+        calls.into_inner()
+    }
+
+    fn scheduler(max_starvation: u64) -> PriorityScheduler {
+        PriorityScheduler::new(max_starvation, Arc::new(RwLock::new(SchedulerMetrics::new())))
+    }
+
+    #[test]
+    fn subcomponents_are_called_in_priority_order_until_one_progresses() {
+        let scheduler = scheduler(8);
+        let budget = Duration::from_secs(10);
+        assert_eq!(call_next(&scheduler, &[], budget), vec!["first", "second", "third"]);
+        assert_eq!(call_next(&scheduler, &["second"], budget), vec!["first", "second"]);
+        assert_eq!(call_next(&scheduler, &["first", "second"], budget), vec!["first"]);
+    }
+
+    #[test]
+    fn starved_subcomponent_is_boosted() {
+        let scheduler = scheduler(2);
+        let budget = Duration::from_secs(10);
+        // the first subcomponent always progresses, so that the others starve
+        assert_eq!(call_next(&scheduler, &["first"], budget), vec!["first"]);
+        assert_eq!(call_next(&scheduler, &["first"], budget), vec!["first"]);
+        // both starved subcomponents are called first, in priority order, until one of them progresses
+        assert_eq!(call_next(&scheduler, &["first", "third"], budget), vec!["second", "third"]);
+        // the starvation of the boosted subcomponents is reset, so that the priority order applies again
+        assert_eq!(call_next(&scheduler, &["first"], budget), vec!["first"]);
+        let metrics = scheduler.metrics.read().unwrap();
+        assert_eq!(metrics["second"].boosted, 1);
+        assert_eq!(metrics["third"].boosted, 1);
+        assert_eq!(metrics["first"].starved, 1);
+    }
+
+    #[test]
+    fn subcomponent_exceeding_its_budget_ends_the_invocation() {
+        let scheduler = scheduler(8);
+        assert_eq!(call_next(&scheduler, &[], Duration::ZERO), vec!["first"]);
+        let metrics = scheduler.metrics.read().unwrap();
+        assert_eq!(metrics["first"].budget_overruns, 1);
+        assert_eq!(metrics["second"].starved, 1);
+        assert_eq!(metrics["third"].starved, 1);
+    }
+}
@@ -1,9 +1,15 @@
-use serde::{Serialize, Deserialize};
+use libp2p::identity::ed25519;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
-use std::{marker::PhantomData, hash::Hash};
+use std::{collections::{BTreeMap, BTreeSet}, fmt, marker::PhantomData, hash::Hash};
+
+use crate::{
+    committee::{Committee, NodeId},
+    time_source::Time,
+};
 
 // Signed contains the signed content and its signature.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Signed<T, S> {
     pub content: T,
     pub signature: S,
@@ -18,10 +24,8 @@ pub struct Hashed<T> {
     pub(crate) value: T,
 }
 
-impl<T> Hashed<T> {
-    pub fn new(artifact: T) -> Self 
-    where T: Serialize 
-    {
+impl<T: CryptoHashDomain> Hashed<T> {
+    pub fn new(artifact: T) -> Self {
         Self {
             hash: Hashed::crypto_hash(&artifact),
             value: artifact
@@ -33,17 +37,199 @@ impl<T> Hashed<T> {
         &self.hash
     }
 
-    pub fn crypto_hash(artifact: &T) -> CryptoHash 
-    where T: Serialize
-    {
-        let payload = serde_json::json!(artifact);
+    /// Compute the SHA-256 hash of the domain separator of `T` followed by the
+    /// canonical encoding of `artifact`. The domain separator is the length of
+    /// the domain (one byte) followed by its ASCII bytes.
+    pub fn crypto_hash(artifact: &T) -> CryptoHash {
+        let mut bytes = Vec::new();
+        bytes.push(T::DOMAIN.len() as u8);
+        bytes.extend_from_slice(T::DOMAIN.as_bytes());
+        artifact.encode_canonical(&mut bytes);
         let mut hasher = Sha256::new();
-        hasher.update(payload.to_string().as_bytes());
-        hex::encode(hasher.finalize().as_slice().to_owned())
+        hasher.update(&bytes);
+        CryptoHash(hasher.finalize().into())
+    }
+}
+
+/// A SHA-256 digest. Serialized as a hex string in human readable formats (ex. JSON)
+/// and as 32 raw bytes otherwise.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CryptoHash(pub [u8; 32]);
+
+impl fmt::Display for CryptoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for CryptoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Serialize for CryptoHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.0))
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CryptoHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex_hash = String::deserialize(deserializer)?;
+            let bytes = hex::decode(&hex_hash).map_err(de::Error::custom)?;
+            let digest = <[u8; 32]>::try_from(bytes.as_slice())
+                .map_err(|_| de::Error::custom("hash must be 32 bytes long"))?;
+            Ok(CryptoHash(digest))
+        } else {
+            <[u8; 32]>::deserialize(deserializer).map(CryptoHash)
+        }
+    }
+}
+
+/// Canonical byte encoding of a value, independent of any serialization format.
+/// Integers are encoded as fixed width big-endian values, booleans as one byte
+/// (0 or 1), strings and byte strings as their length (u64) followed by their bytes,
+/// sets and maps as their number of elements (u64) followed by their elements (keys
+/// followed by values) in increasing order, enums
+/// (including [Option], where `None` is the first variant) as the index of the
+/// variant (one byte) followed by the encoding of its fields,
+/// and structs as the concatenation of the encoding of their fields, in the order
+/// in which they are declared.
+pub trait CanonicalEncode {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>);
+}
+
+/// Types which can be hashed. The domain is prepended to the canonical encoding
+/// before hashing so that values of different types never have the same hash,
+/// even if their encoding is the same.
+///
+/// # Test vectors
+///
+/// Alternative implementations must produce the following hashes (all values hex encoded), which are checked
+/// by the tests of this module:
+///
+/// | artifact | domain | canonical encoding | hash |
+/// |---|---|---|---|
+/// | genesis block (parent all zeros, FICC for the children, no registry update, height 0, rank 0, no proposer timestamp) | `ficc-block` | `00` (x 32) `01` `00` `0000000000000000` `00000000` `00` | `3489d211e88000be368da6de70cdd148b219caf64cca0ebf44c10087bf2b27ad` |
+/// | block with genesis as parent, FICC for the children, no registry update, height 1, rank 0, no proposer timestamp | `ficc-block` | `3489d211...bf2b27ad` `01` `00` `0000000000000001` `00000000` `00` | `52e7d5a20ada282622784f8c69fefc7f59ec5f2515706ff62ec3ec3fb995d5d5` |
+/// | proposal of the block at height 1 signed by node 1 (keypair derived from its node id) | `ficc-block-proposal` | `52e7d5a2...b995d5d5` `00000001` `0000000000000040` `d2f56850...23e1510d` | `a0a1dd064426a499ef7d132766844f9c8200bc6b4eb9ef978fa2c5176079b565` |
+/// | COD ack of the block at height 1 signed by node 2 (keypair derived from its node id) | `ficc-notarization-share` | `00` `0000000000000001` `52e7d5a2...b995d5d5` `3489d211...bf2b27ad` `01` `00000002` `0000000000000040` `880e790c...ec959804` | `005b18c0137352c54f7a2aab027015a910c1f6628188b17f61c0d7b6b3fbabd2` |
+pub trait CryptoHashDomain: CanonicalEncode {
+    const DOMAIN: &'static str;
+}
+
+impl CanonicalEncode for u8 {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self);
+    }
+}
+
+impl CanonicalEncode for u32 {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl CanonicalEncode for u64 {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl CanonicalEncode for usize {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        (*self as u64).encode_canonical(bytes);
+    }
+}
+
+impl CanonicalEncode for bool {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self as u8);
     }
 }
 
-pub type CryptoHash = String;
+impl CanonicalEncode for String {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        (self.len() as u64).encode_canonical(bytes);
+        bytes.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl CanonicalEncode for CryptoHash {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.0);
+    }
+}
+
+impl CanonicalEncode for Time {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.as_nanos_since_unix_epoch().encode_canonical(bytes);
+    }
+}
+
+impl<Entity> CanonicalEncode for Id<Entity, CryptoHash> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.get_ref().encode_canonical(bytes);
+    }
+}
+
+impl<T: CanonicalEncode> CanonicalEncode for Option<T> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode_canonical(bytes),
+            Some(value) => {
+                1u8.encode_canonical(bytes);
+                value.encode_canonical(bytes);
+            }
+        }
+    }
+}
+
+impl<T: CanonicalEncode> CanonicalEncode for Box<T> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.as_ref().encode_canonical(bytes);
+    }
+}
+
+impl<T: CanonicalEncode> CanonicalEncode for BTreeSet<T> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.len().encode_canonical(bytes);
+        for value in self {
+            value.encode_canonical(bytes);
+        }
+    }
+}
+
+impl<K: CanonicalEncode, V: CanonicalEncode> CanonicalEncode for BTreeMap<K, V> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.len().encode_canonical(bytes);
+        for (key, value) in self {
+            key.encode_canonical(bytes);
+            value.encode_canonical(bytes);
+        }
+    }
+}
+
+/// The hash of a [Hashed] value already commits to the value, therefore only the
+/// hash is encoded.
+impl<T> CanonicalEncode for Hashed<T> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.hash.encode_canonical(bytes);
+    }
+}
+
+impl<T: CanonicalEncode, S: CanonicalEncode> CanonicalEncode for Signed<T, S> {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.content.encode_canonical(bytes);
+        self.signature.encode_canonical(bytes);
+    }
+}
 
 /// A cryptographic hash for content of type `T`
 pub type CryptoHashOf<T> = Id<T, CryptoHash>;
@@ -77,6 +263,7 @@ pub enum ConsensusMessageHash {
     FinalizationShare(CryptoHash),
     Finalization(CryptoHash),
     GoodnessArtifact(CryptoHash),
+    EquivocationProof(CryptoHash),
 }
 
 impl ConsensusMessageHash {
@@ -88,6 +275,290 @@ impl ConsensusMessageHash {
             ConsensusMessageHash::Finalization(hash) => hash,
             ConsensusMessageHash::FinalizationShare(hash) => hash,
             ConsensusMessageHash::GoodnessArtifact(hash) => hash,
+            ConsensusMessageHash::EquivocationProof(hash) => hash,
         }
     }
-}
\ No newline at end of file
+}
+/// Domain in which a signature is created. The tag of the domain is prepended to
+/// the canonical encoding of the signed content, so that a signature created in
+/// one domain is never valid in another one, even if the signed contents have the
+/// same encoding (ex. a notarization share and a finalization share on the same
+/// height and block).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SigningDomain {
+    BlockProposal,
+    NotarizationShareICC,
+    NotarizationShareAck,
+    NotarizationShareNonAck,
+    FinalizationShare,
+    RandomBeaconShare, // random beacon shares are not created yet, the domain is reserved
+    RegistryUpdate,
+}
+
+impl SigningDomain {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            SigningDomain::BlockProposal => "ficc-sign-block-proposal",
+            SigningDomain::NotarizationShareICC => "ficc-sign-notarization-share-icc",
+            SigningDomain::NotarizationShareAck => "ficc-sign-notarization-share-ack",
+            SigningDomain::NotarizationShareNonAck => "ficc-sign-notarization-share-non-ack",
+            SigningDomain::FinalizationShare => "ficc-sign-finalization-share",
+            SigningDomain::RandomBeaconShare => "ficc-sign-random-beacon-share",
+            SigningDomain::RegistryUpdate => "ficc-sign-registry-update",
+        }
+    }
+}
+
+/// Contents which can be signed. The signing domain may depend on the value of the
+/// content (ex. acks and non-acks are both notarization share contents).
+pub trait Signable: CanonicalEncode {
+    fn signing_domain(&self) -> SigningDomain;
+}
+
+/// Return the bytes which are signed for `content`: the length of the tag of its
+/// signing domain (one byte), the tag and the canonical encoding of the content.
+pub fn signed_bytes<T: Signable>(content: &T) -> Vec<u8> {
+    let tag = content.signing_domain().tag();
+    let mut bytes = Vec::new();
+    bytes.push(tag.len() as u8);
+    bytes.extend_from_slice(tag.as_bytes());
+    content.encode_canonical(&mut bytes);
+    bytes
+}
+
+/// Ed25519 public key of a replica (32 bytes), serialized as a hex string in human readable formats (ex. JSON).
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublicKey(pub Vec<u8>);
+
+impl PublicKey {
+    /// Public key of the keypair derived from the node id (see [derived_keypair]), used by the replicas
+    /// of a committee created without a committee file.
+    pub fn derived(node_id: NodeId) -> Self {
+        PublicKey::from(&derived_keypair(node_id).public())
+    }
+}
+
+impl From<&ed25519::PublicKey> for PublicKey {
+    fn from(public_key: &ed25519::PublicKey) -> Self {
+        PublicKey(public_key.encode().to_vec())
+    }
+}
+
+/// Keypair of the replica `node_id` whose secret key is the digest of its node id, used by the replicas of
+/// a committee created without a committee file (see [Committee::with_size]). Anyone can derive the secret
+/// key and sign on behalf of the replica, so derived keypairs are only meant for tests and local benchmarks.
+pub fn derived_keypair(node_id: NodeId) -> ed25519::Keypair {
+    let mut hasher = Sha256::new();
+    hasher.update(b"ficc-derived-secret-key");
+    hasher.update(node_id.get().to_be_bytes());
+    let secret_key = ed25519::SecretKey::from_bytes(hasher.finalize().to_vec()).expect("32 bytes secret key");
+    ed25519::Keypair::from(secret_key)
+}
+
+impl CanonicalEncode for PublicKey {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        (self.0.len() as u64).encode_canonical(bytes);
+        bytes.extend_from_slice(&self.0);
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(&self.0))
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex_key = String::deserialize(deserializer)?;
+            hex::decode(&hex_key).map(PublicKey).map_err(de::Error::custom)
+        } else {
+            Vec::<u8>::deserialize(deserializer).map(PublicKey)
+        }
+    }
+}
+
+/// Signature of a single replica.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Signature {
+    pub signer: NodeId,
+    pub bytes: Vec<u8>,
+}
+
+impl CanonicalEncode for Signature {
+    fn encode_canonical(&self, bytes: &mut Vec<u8>) {
+        self.signer.encode_canonical(bytes);
+        (self.bytes.len() as u64).encode_canonical(bytes);
+        bytes.extend_from_slice(&self.bytes);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    UnknownSigner(NodeId),
+    InvalidSignature { signer: NodeId, domain: SigningDomain },
+}
+
+/// Creates the ed25519 signatures of the local replica.
+#[derive(Clone)]
+pub struct Signer {
+    node_id: NodeId,
+    keypair: ed25519::Keypair,
+}
+
+impl Signer {
+    /// Signer of the replica `node_id` with its keypair, whose public key is the one of the replica in the committee
+    /// (or the one it must be added with by a registry update if it is not a member of the committee yet).
+    pub fn new(node_id: NodeId, keypair: ed25519::Keypair) -> Self {
+        Self { node_id, keypair }
+    }
+
+    /// Signer of the replica `node_id` with its derived keypair (see [derived_keypair]).
+    pub fn with_derived_key(node_id: NodeId) -> Self {
+        Self::new(node_id, derived_keypair(node_id))
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.keypair.public())
+    }
+
+    /// Sign `content` in its signing domain.
+    pub fn sign<T: Signable>(&self, content: T) -> Signed<T, Signature> {
+        let signature = Signature {
+            signer: self.node_id,
+            bytes: self.keypair.sign(&signed_bytes(&content)),
+        };
+        Signed { content, signature }
+    }
+}
+
+/// Verify that the signature of `signed` was created by its signer, a member of the committee,
+/// on its content, in the signing domain of the content.
+pub fn verify<T: Signable>(committee: &Committee, signed: &Signed<T, Signature>) -> Result<(), SignatureError> {
+    verify_signature(committee, &signed.content, &signed.signature)
+}
+
+/// Verify that `signature` was created by its signer, a member of the committee, on `content`,
+/// in the signing domain of the content.
+pub fn verify_signature<T: Signable>(committee: &Committee, content: &T, signature: &Signature) -> Result<(), SignatureError> {
+    let signer = signature.signer;
+    let public_key = committee.public_key(signer).ok_or(SignatureError::UnknownSigner(signer))?;
+    let valid = ed25519::PublicKey::decode(&public_key.0)
+        .map(|public_key| public_key.verify(&signed_bytes(content), &signature.bytes))
+        .unwrap_or(false);
+    if valid {
+        Ok(())
+    } else {
+        Err(SignatureError::InvalidSignature {
+            signer,
+            domain: content.signing_domain(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_layer::consensus_subcomponents::{
+        block_maker::{genesis_block, Block, Payload},
+        notary::{NotarizationShareContent, NotarizationShareContentCOD},
+    };
+    use crate::consensus_layer::consensus_mode::ConsensusMode;
+
+    // test vectors of the documentation of [CryptoHashDomain]
+
+    fn encoding<T: CanonicalEncode>(value: &T) -> String {
+        let mut bytes = Vec::new();
+        value.encode_canonical(&mut bytes);
+        hex::encode(bytes)
+    }
+
+    fn first_block() -> Block {
+        Block::new(Hashed::crypto_hash(&genesis_block()), Payload::new(ConsensusMode::FICC, None), 1, 0, None)
+    }
+
+    #[test]
+    fn genesis_block_vector() {
+        let block = genesis_block();
+        assert_eq!(encoding(&block), format!("{}{}{}{}{}{}", "00".repeat(32), "01", "00", "0000000000000000", "00000000", "00"));
+        assert_eq!(
+            Hashed::crypto_hash(&block).to_string(),
+            "3489d211e88000be368da6de70cdd148b219caf64cca0ebf44c10087bf2b27ad"
+        );
+    }
+
+    #[test]
+    fn first_block_vector() {
+        let block = first_block();
+        assert_eq!(
+            encoding(&block),
+            "3489d211e88000be368da6de70cdd148b219caf64cca0ebf44c10087bf2b27ad\
+             01\
+             00\
+             0000000000000001\
+             00000000\
+             00"
+        );
+        assert_eq!(
+            Hashed::crypto_hash(&block).to_string(),
+            "52e7d5a20ada282622784f8c69fefc7f59ec5f2515706ff62ec3ec3fb995d5d5"
+        );
+    }
+
+    #[test]
+    fn block_proposal_vector() {
+        let proposal = Signer::with_derived_key(NodeId(1)).sign(Hashed::new(first_block()));
+        let encoded = encoding(&proposal);
+        assert!(encoded.starts_with("52e7d5a20ada282622784f8c69fefc7f59ec5f2515706ff62ec3ec3fb995d5d5000000010000000000000040"));
+        // ed25519 signatures are deterministic
+        assert!(encoded.ends_with(
+            "d2f56850d43b9f38fcc78c2ff40da1adf919e71d34de4fcb10f9bdad824fcda7\
+             07c9aa029fc0f58f8645371b7cadebd248fa6d5aaecfea8300427e2323e1510d"
+        ));
+        assert_eq!(
+            Hashed::crypto_hash(&proposal).to_string(),
+            "a0a1dd064426a499ef7d132766844f9c8200bc6b4eb9ef978fa2c5176079b565"
+        );
+    }
+
+    #[test]
+    fn ack_vector() {
+        let block = first_block();
+        let ack = Signer::with_derived_key(NodeId(2)).sign(NotarizationShareContent::COD(NotarizationShareContentCOD::new(
+            1,
+            CryptoHashOf::from(Hashed::crypto_hash(&block)),
+            block.parent,
+            Some(true),
+        )));
+        let encoded = encoding(&ack);
+        assert!(encoded.starts_with(
+            "000000000000000001\
+             52e7d5a20ada282622784f8c69fefc7f59ec5f2515706ff62ec3ec3fb995d5d5\
+             3489d211e88000be368da6de70cdd148b219caf64cca0ebf44c10087bf2b27ad\
+             01000000020000000000000040"
+        ));
+        assert!(encoded.ends_with(
+            "880e790cf3cc48342dd9b483b77db930306cd30085523d5042897b9c1f2098fb\
+             19ab2a82bf4f82e7378fadcd122dfe0a2ff2614e44d5958f366bda7fec959804"
+        ));
+        assert_eq!(
+            Hashed::crypto_hash(&ack).to_string(),
+            "005b18c0137352c54f7a2aab027015a910c1f6628188b17f61c0d7b6b3fbabd2"
+        );
+    }
+}
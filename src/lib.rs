@@ -0,0 +1,496 @@
+//! Fast Internet Computer Consensus (FICC) and Internet Computer Consensus (ICC).
+//!
+//! The replica binary is a thin wrapper around this library: [network_layer::Peer] connects to the
+//! other replicas and starts the [artifact_manager::ArtifactProcessorManager], which runs the
+//! subcomponents of the [consensus_layer] on the artifacts received by the peer.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{committee::Committee, consensus_layer::registry::AuthorizedRegistryUpdate, time_source::Time};
+
+pub mod artifact_manager;
+pub mod committee;
+pub mod consensus_layer;
+pub mod crypto;
+pub mod network_layer;
+pub mod results;
+pub mod time_source;
+pub mod transport;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FinalizationType {
+    IC,
+    FP,
+    DK,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeightMetrics {
+    pub latency: Duration,
+    pub fp_finalization: FinalizationType,
+    pub phases: PhaseTimestamps,
+    // from the creation of the block by its proposer to the local finalization, only if proposals carry the proposer timestamp
+    // as the clocks of the two replicas are compared, the error is bounded by the max clock skew
+    pub end_to_end_latency: Option<Duration>,
+}
+
+/// Time at which the local replica observed each phase of a height,
+/// None if the phase was skipped or its artifacts were not received.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PhaseTimestamps {
+    pub round_start: Option<Time>,
+    pub first_proposal: Option<Time>,
+    pub own_notarization_share: Option<Time>,
+    pub notarization: Option<Time>,
+    pub acks_quorum: Option<Time>,                  // n-p acks for the same block
+    pub finalization_shares_quorum: Option<Time>,   // n-f finalization shares for the same block
+    pub finalization: Time,
+}
+
+/// Distribution of the one-way delays of the artifacts of one type received from one peer,
+/// aggregated so that its size does not grow with the duration of the run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ArtifactDelayStats {
+    pub count: u64,
+    // artifacts received before they were sent according to the local clock, due to clock skew between replicas,
+    // which are not counted in the delays below
+    pub skewed: u64,
+    pub sum: Duration,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    // number of delays in each bucket: bucket 0 counts the delays below 1 ms and bucket k the ones in [2^(k-1), 2^k) ms
+    pub histogram: BTreeMap<u32, u64>,
+}
+
+impl ArtifactDelayStats {
+    pub fn record(&mut self, sent: Time, received: Time) {
+        self.count += 1;
+        let delay = match received.checked_duration_since(sent) {
+            Some(delay) => delay,
+            None => {
+                self.skewed += 1;
+                return;
+            }
+        };
+        self.sum += delay;
+        self.min = Some(self.min.map_or(delay, |min| min.min(delay)));
+        self.max = Some(self.max.map_or(delay, |max| max.max(delay)));
+        let bucket = u64::BITS - (delay.as_millis() as u64).leading_zeros();
+        *self.histogram.entry(bucket).or_insert(0) += 1;
+    }
+
+    /// Mean of the delays, None if all the artifacts were affected by clock skew.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count - self.skewed {
+            0 => None,
+            measured => Some(self.sum / measured as u32),
+        }
+    }
+}
+
+/// Delays of the artifacts received from each peer, grouped by type of artifact
+pub type ArtifactDelays = BTreeMap<String, BTreeMap<String, ArtifactDelayStats>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubnetParams {
+    committee: Committee,
+    total_nodes_number: u32,
+    byzantine_nodes_number: u32,
+    disagreeing_nodes_number: u32,
+    consensus_on_demand: bool,
+    adaptive_consensus: bool,
+    mode_switch_window: u64,
+    mode_switch_threshold: u64,
+    artifact_delay: u64,
+    adaptive_delay: bool, // estimate the artifact delay from the observed rounds instead of using `artifact_delay`
+    adaptive_delay_min: u64,
+    adaptive_delay_max: u64,
+    delay_backoff_base: f32, // base of the exponential backoff of the notary delay, the exponent being the gap between notarized and finalized heights
+    artifact_manager_polling_interval: u64,
+    queue_capacity: usize,
+    batch_changes: bool,
+    max_starvation: u64,
+    subcomponent_time_budget: u64,
+    max_clock_skew: Option<u64>,
+    epoch_length: u64,
+    registry_updates: Vec<AuthorizedRegistryUpdate>, // updates proposed by the local replica, until they are finalized or too late
+    equivocation_exclusion: u64, // number of heights, from the one of an equivocation, during which the shares of the offender are ignored, 0 to disable exclusion
+    stall_timeout: u64, // time (in milliseconds) after which a round without notarization is considered stalled, 0 to disable stall detection
+}
+
+/// Invalid combination of subnet parameters, rejected by [SubnetParamsBuilder::build].
+#[derive(Debug, PartialEq)]
+pub enum InvalidSubnetParams {
+    EmptyCommittee,
+    // n < 3f + 2p + 1, which includes f >= n
+    NotEnoughReplicas { total: u32, byzantine: u32, disagreeing: u32 },
+    QueueCapacity,
+    ModeSwitchWindow,
+    ModeSwitchThreshold { threshold: u64, window: u64 },
+    EpochLength,
+    AdaptiveDelayBounds { min: u64, max: u64 },
+    DelayBackoffBase(f32),
+}
+
+impl SubnetParams {
+    /// Builder of the parameters of a subnet formed by `committee`, with the same defaults as the replica binary.
+    pub fn builder(committee: Committee) -> SubnetParamsBuilder {
+        SubnetParamsBuilder {
+            committee,
+            byzantine_nodes_number: 1,
+            disagreeing_nodes_number: 1,
+            consensus_on_demand: false,
+            adaptive_consensus: false,
+            mode_switch_window: 10,
+            mode_switch_threshold: 3,
+            artifact_delay: 500,
+            adaptive_delay: false,
+            adaptive_delay_min: 50,
+            adaptive_delay_max: 2000,
+            delay_backoff_base: 1.5,
+            artifact_manager_polling_interval: 200,
+            queue_capacity: 1000,
+            batch_changes: false,
+            max_starvation: 8,
+            subcomponent_time_budget: 50,
+            max_clock_skew: None,
+            epoch_length: 100,
+            registry_updates: vec![],
+            equivocation_exclusion: 100,
+            stall_timeout: 5000,
+        }
+    }
+
+    pub fn committee(&self) -> &Committee {
+        &self.committee
+    }
+
+    pub fn total_nodes_number(&self) -> u32 {
+        self.total_nodes_number
+    }
+
+    pub fn byzantine_nodes_number(&self) -> u32 {
+        self.byzantine_nodes_number
+    }
+
+    pub fn disagreeing_nodes_number(&self) -> u32 {
+        self.disagreeing_nodes_number
+    }
+
+    pub fn consensus_on_demand(&self) -> bool {
+        self.consensus_on_demand
+    }
+
+    pub fn adaptive_consensus(&self) -> bool {
+        self.adaptive_consensus
+    }
+
+    pub fn mode_switch_window(&self) -> u64 {
+        self.mode_switch_window
+    }
+
+    pub fn mode_switch_threshold(&self) -> u64 {
+        self.mode_switch_threshold
+    }
+
+    pub fn artifact_delay(&self) -> u64 {
+        self.artifact_delay
+    }
+
+    pub fn adaptive_delay(&self) -> bool {
+        self.adaptive_delay
+    }
+
+    pub fn adaptive_delay_min(&self) -> u64 {
+        self.adaptive_delay_min
+    }
+
+    pub fn adaptive_delay_max(&self) -> u64 {
+        self.adaptive_delay_max
+    }
+
+    pub fn delay_backoff_base(&self) -> f32 {
+        self.delay_backoff_base
+    }
+
+    pub fn artifact_manager_polling_interval(&self) -> u64 {
+        self.artifact_manager_polling_interval
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    pub fn batch_changes(&self) -> bool {
+        self.batch_changes
+    }
+
+    pub fn max_starvation(&self) -> u64 {
+        self.max_starvation
+    }
+
+    pub fn subcomponent_time_budget(&self) -> u64 {
+        self.subcomponent_time_budget
+    }
+
+    pub fn max_clock_skew(&self) -> Option<u64> {
+        self.max_clock_skew
+    }
+
+    pub fn epoch_length(&self) -> u64 {
+        self.epoch_length
+    }
+
+    pub fn registry_updates(&self) -> &[AuthorizedRegistryUpdate] {
+        &self.registry_updates
+    }
+
+    pub fn equivocation_exclusion(&self) -> u64 {
+        self.equivocation_exclusion
+    }
+
+    pub fn stall_timeout(&self) -> u64 {
+        self.stall_timeout
+    }
+}
+
+/// Builder of [SubnetParams], created with [SubnetParams::builder].
+#[derive(Debug, Clone)]
+pub struct SubnetParamsBuilder {
+    committee: Committee,
+    byzantine_nodes_number: u32,
+    disagreeing_nodes_number: u32,
+    consensus_on_demand: bool,
+    adaptive_consensus: bool,
+    mode_switch_window: u64,
+    mode_switch_threshold: u64,
+    artifact_delay: u64,
+    adaptive_delay: bool,
+    adaptive_delay_min: u64,
+    adaptive_delay_max: u64,
+    delay_backoff_base: f32,
+    artifact_manager_polling_interval: u64,
+    queue_capacity: usize,
+    batch_changes: bool,
+    max_starvation: u64,
+    subcomponent_time_budget: u64,
+    max_clock_skew: Option<u64>,
+    epoch_length: u64,
+    registry_updates: Vec<AuthorizedRegistryUpdate>,
+    equivocation_exclusion: u64,
+    stall_timeout: u64,
+}
+
+impl SubnetParamsBuilder {
+    /// Set the number of byzantine replicas (f).
+    pub fn byzantine_nodes_number(mut self, byzantine_nodes_number: u32) -> Self {
+        self.byzantine_nodes_number = byzantine_nodes_number;
+        self
+    }
+
+    /// Set the number of disagreeing replicas (p).
+    pub fn disagreeing_nodes_number(mut self, disagreeing_nodes_number: u32) -> Self {
+        self.disagreeing_nodes_number = disagreeing_nodes_number;
+        self
+    }
+
+    /// Set whether the subnet runs FICC instead of ICC.
+    pub fn consensus_on_demand(mut self, consensus_on_demand: bool) -> Self {
+        self.consensus_on_demand = consensus_on_demand;
+        self
+    }
+
+    /// Set whether the subnet switches between FICC and ICC at runtime.
+    pub fn adaptive_consensus(mut self, adaptive_consensus: bool) -> Self {
+        self.adaptive_consensus = adaptive_consensus;
+        self
+    }
+
+    /// Set the number of recent heights considered when choosing the consensus mode.
+    pub fn mode_switch_window(mut self, mode_switch_window: u64) -> Self {
+        self.mode_switch_window = mode_switch_window;
+        self
+    }
+
+    /// Set the number of heights in the window whose fast path failed after which ICC is used.
+    pub fn mode_switch_threshold(mut self, mode_switch_threshold: u64) -> Self {
+        self.mode_switch_threshold = mode_switch_threshold;
+        self
+    }
+
+    /// Set the delay (in milliseconds) of the block makers and notaries for each rank.
+    pub fn artifact_delay(mut self, artifact_delay: u64) -> Self {
+        self.artifact_delay = artifact_delay;
+        self
+    }
+
+    /// Set whether the artifact delay is estimated from the observed rounds instead of using the artifact delay.
+    pub fn adaptive_delay(mut self, adaptive_delay: bool) -> Self {
+        self.adaptive_delay = adaptive_delay;
+        self
+    }
+
+    /// Set the lower bound (in milliseconds) of the adaptive delay.
+    pub fn adaptive_delay_min(mut self, adaptive_delay_min: u64) -> Self {
+        self.adaptive_delay_min = adaptive_delay_min;
+        self
+    }
+
+    /// Set the upper bound (in milliseconds) of the adaptive delay.
+    pub fn adaptive_delay_max(mut self, adaptive_delay_max: u64) -> Self {
+        self.adaptive_delay_max = adaptive_delay_max;
+        self
+    }
+
+    /// Set the base of the exponential backoff of the notary delay.
+    pub fn delay_backoff_base(mut self, delay_backoff_base: f32) -> Self {
+        self.delay_backoff_base = delay_backoff_base;
+        self
+    }
+
+    /// Set the periodic duration (in milliseconds) of the polls of the artifact manager.
+    pub fn artifact_manager_polling_interval(mut self, artifact_manager_polling_interval: u64) -> Self {
+        self.artifact_manager_polling_interval = artifact_manager_polling_interval;
+        self
+    }
+
+    /// Set the max number of artifacts waiting in each queue between the network and the consensus layers.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Set whether the changes of all subcomponents are applied within a single poll of the artifact manager.
+    pub fn batch_changes(mut self, batch_changes: bool) -> Self {
+        self.batch_changes = batch_changes;
+        self
+    }
+
+    /// Set the number of consecutive polls without calling a subcomponent after which it is called first.
+    pub fn max_starvation(mut self, max_starvation: u64) -> Self {
+        self.max_starvation = max_starvation;
+        self
+    }
+
+    /// Set the time (in milliseconds) a subcomponent can take before lower priority ones are postponed.
+    pub fn subcomponent_time_budget(mut self, subcomponent_time_budget: u64) -> Self {
+        self.subcomponent_time_budget = subcomponent_time_budget;
+        self
+    }
+
+    /// Set the max time (in milliseconds) by which a proposal can be ahead of the local clock, None to not timestamp proposals.
+    pub fn max_clock_skew(mut self, max_clock_skew: Option<u64>) -> Self {
+        self.max_clock_skew = max_clock_skew;
+        self
+    }
+
+    /// Set the number of heights in an epoch, the committee can only change at the first height of an epoch.
+    pub fn epoch_length(mut self, epoch_length: u64) -> Self {
+        self.epoch_length = epoch_length;
+        self
+    }
+
+    /// Set the registry updates proposed by the local replica, each one signed by at least n - f members of the committee.
+    pub fn registry_updates(mut self, registry_updates: Vec<AuthorizedRegistryUpdate>) -> Self {
+        self.registry_updates = registry_updates;
+        self
+    }
+
+    /// Set the number of heights during which the shares of a replica which equivocated are ignored (100 by default), 0 to keep counting them.
+    pub fn equivocation_exclusion(mut self, equivocation_exclusion: u64) -> Self {
+        self.equivocation_exclusion = equivocation_exclusion;
+        self
+    }
+
+    /// Set the time (in milliseconds) after which a round without notarization is stalled, 0 to disable.
+    pub fn stall_timeout(mut self, stall_timeout: u64) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Check the parameters which depend on each other and build the parameters of the subnet.
+    pub fn build(self) -> Result<SubnetParams, InvalidSubnetParams> {
+        if self.committee.size() == 0 {
+            return Err(InvalidSubnetParams::EmptyCommittee);
+        }
+        let (total, byzantine, disagreeing) = (self.committee.size(), self.byzantine_nodes_number, self.disagreeing_nodes_number);
+        if (total as u64) < 3 * byzantine as u64 + 2 * disagreeing as u64 + 1 {
+            return Err(InvalidSubnetParams::NotEnoughReplicas { total, byzantine, disagreeing });
+        }
+        if self.queue_capacity == 0 {
+            return Err(InvalidSubnetParams::QueueCapacity);
+        }
+        if self.mode_switch_window == 0 {
+            return Err(InvalidSubnetParams::ModeSwitchWindow);
+        }
+        if self.epoch_length == 0 {
+            return Err(InvalidSubnetParams::EpochLength);
+        }
+        if self.adaptive_delay_min > self.adaptive_delay_max {
+            return Err(InvalidSubnetParams::AdaptiveDelayBounds { min: self.adaptive_delay_min, max: self.adaptive_delay_max });
+        }
+        // also rejects NaN, which would make the notary delay undefined
+        if self.delay_backoff_base.is_nan() || self.delay_backoff_base < 1.0 {
+            return Err(InvalidSubnetParams::DelayBackoffBase(self.delay_backoff_base));
+        }
+        if self.mode_switch_threshold == 0 || self.mode_switch_threshold > self.mode_switch_window {
+            return Err(InvalidSubnetParams::ModeSwitchThreshold {
+                threshold: self.mode_switch_threshold,
+                window: self.mode_switch_window,
+            });
+        }
+        Ok(SubnetParams {
+            total_nodes_number: self.committee.size(),
+            committee: self.committee,
+            byzantine_nodes_number: self.byzantine_nodes_number,
+            disagreeing_nodes_number: self.disagreeing_nodes_number,
+            consensus_on_demand: self.consensus_on_demand,
+            adaptive_consensus: self.adaptive_consensus,
+            mode_switch_window: self.mode_switch_window,
+            mode_switch_threshold: self.mode_switch_threshold,
+            artifact_delay: self.artifact_delay,
+            adaptive_delay: self.adaptive_delay,
+            adaptive_delay_min: self.adaptive_delay_min,
+            adaptive_delay_max: self.adaptive_delay_max,
+            delay_backoff_base: self.delay_backoff_base,
+            artifact_manager_polling_interval: self.artifact_manager_polling_interval,
+            queue_capacity: self.queue_capacity,
+            batch_changes: self.batch_changes,
+            max_starvation: self.max_starvation,
+            subcomponent_time_budget: self.subcomponent_time_budget,
+            max_clock_skew: self.max_clock_skew,
+            epoch_length: self.epoch_length,
+            registry_updates: self.registry_updates,
+            equivocation_exclusion: self.equivocation_exclusion,
+            stall_timeout: self.stall_timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(n: u32, f: u32, p: u32) -> SubnetParamsBuilder {
+        SubnetParams::builder(Committee::with_size(n)).byzantine_nodes_number(f).disagreeing_nodes_number(p)
+    }
+
+    #[test]
+    fn subnet_must_tolerate_f_and_p() {
+        assert!(builder(6, 1, 1).build().is_ok());
+        assert_eq!(
+            builder(5, 1, 1).build().err(),
+            Some(InvalidSubnetParams::NotEnoughReplicas { total: 5, byzantine: 1, disagreeing: 1 })
+        );
+        assert_eq!(
+            builder(4, 4, 0).build().err(),
+            Some(InvalidSubnetParams::NotEnoughReplicas { total: 4, byzantine: 4, disagreeing: 0 })
+        );
+    }
+
+    #[test]
+    fn queue_capacity_must_not_be_zero() {
+        assert_eq!(builder(4, 1, 0).queue_capacity(0).build().err(), Some(InvalidSubnetParams::QueueCapacity));
+    }
+}
@@ -1,101 +1,115 @@
-use async_std::{fs::File, io, task, stream};
+use async_std::{task, stream};
 use futures::{
     future::FutureExt,
-    prelude::{stream::StreamExt, *},
+    prelude::stream::StreamExt,
     select,
 };
-use serde::{Deserialize, Serialize};
-use serde_json::to_string;
-use time_source::Time;
 use std::{
     collections::BTreeMap,
-    sync::{Arc, RwLock},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock},
     time::Duration, thread,
 };
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use libp2p::{identity::Keypair, PeerId};
+use serde::Serialize;
 use structopt::StructOpt;
 use tide::{Body, Request, Response, Result};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-enum FinalizationType {
-    IC,
-    FP,
-    DK,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct HeightMetrics {
-    latency: Duration,
-    fp_finalization: FinalizationType,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct BenchmarkResult {
-    finalization_times: BTreeMap<Height, Option<HeightMetrics>>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ArtifactDelayInfo {
-    sent: Option<Time>,
-    received: Option<Time>,
-}
-
-pub mod network_layer;
-use crate::{
-    consensus_layer::height_index::Height,
-    network_layer::Peer,
-    time_source::{get_absolute_end_time, system_time_now}
+use consensus_on_demand::{
+    committee::{Committee, NodeId},
+    consensus_layer::{
+        fast_path_report::{get_fast_path_report, get_fast_path_reports, FastPathReport, FastPathStatistics, FastPathTracker},
+        finality_proof::build_finality_proof, height_index::Height, pool::ConsensusPoolImpl, pool_reader::PoolReader,
+        consensus_subcomponents::stall_detector::{StalledRound, StalledRounds},
+        registry::AuthorizedRegistryUpdate, scheduler::SchedulerMetrics,
+    },
+    crypto::Signer,
+    network_layer::{load_or_generate_keypair, read_peers_file, Peer},
+    results::{output_path, write_results, BenchmarkResult, OutputFormat, RunMetadata},
+    time_source::{get_absolute_end_time, monotonic_time_now, system_time_now},
+    ArtifactDelays, HeightMetrics, SubnetParams,
 };
 
-pub mod artifact_manager;
-pub mod consensus_layer;
-pub mod crypto;
-pub mod time_source;
-
 #[derive(StructOpt, Debug)]
 struct Opt {
     #[structopt(long)]
-    r: u8, // replica number
+    r: NodeId, // replica number
     #[structopt(long, default_value = "6")]
-    n: u8, // total number of nodes
+    n: u32, // total number of nodes, ignored if a committee file is given
     #[structopt(long, default_value = "1")]
-    f: u8, // number of byzantine nodes
+    f: u32, // number of byzantine nodes
     #[structopt(long, default_value = "1")]
-    p: u8, // number of disagreeing nodes
+    p: u32, // number of disagreeing nodes
     #[structopt(long)]
     cod: bool, // enable Fast IC Consensus
+    #[structopt(long)]
+    auto: bool, // switch between Fast IC Consensus and IC Consensus at runtime
+    #[structopt(name="mode_switch_window", long, default_value = "10")]
+    mode_switch_window: u64, // number of recent heights considered when choosing the consensus mode
+    #[structopt(name="mode_switch_threshold", long, default_value = "3")]
+    mode_switch_threshold: u64, // number of heights in the window whose fast path failed after which IC Consensus is used
     #[structopt(long, default_value = "300")]
     t: u64, // time to run replica
     #[structopt(long, default_value = "500")]
     d: u64, // notary delay
+    #[structopt(name="adaptive_delay", long)]
+    adaptive_delay: bool, // estimate the notary and block maker delay from the observed network round trip instead of using `d`
+    #[structopt(name="adaptive_delay_min", long, default_value = "50")]
+    adaptive_delay_min: u64, // lower bound (in milliseconds) of the adaptive delay
+    #[structopt(name="adaptive_delay_max", long, default_value = "2000")]
+    adaptive_delay_max: u64, // upper bound (in milliseconds) of the adaptive delay
+    #[structopt(name="delay_backoff_base", long, default_value = "1.5")]
+    delay_backoff_base: f32, // the notary delay is multiplied by this base to the power of the gap between notarized and finalized heights
     #[structopt(long, default_value = "56789")]
     port: u64,    // port which the peers listen for connections
     #[structopt(name="broadcast_interval", long, default_value = "100")]
-    broadcast_interval: u64, // interval after which artifacts are broadcasted
+    broadcast_interval: u64, // interval after which keep alive messages are broadcasted and disconnected peers are redialed
     #[structopt(name="artifact_manager_polling_interval", long, default_value = "200")]
     artifact_manager_polling_interval: u64, // periodic duration of `PollEvent` in milliseconds
+    #[structopt(name="queue_capacity", long, default_value = "1000")]
+    queue_capacity: usize, // max number of incoming artifacts waiting to be processed for each peer and of outgoing artifacts waiting to be broadcasted
+    #[structopt(name="batch_changes", long)]
+    batch_changes: bool, // apply the changes of a subcomponent and call the next one within the same poll of the artifact manager
+    #[structopt(name="max_starvation", long, default_value = "8")]
+    max_starvation: u64, // number of consecutive polls without calling a subcomponent after which it is called first
+    #[structopt(name="subcomponent_time_budget", long, default_value = "50")]
+    subcomponent_time_budget: u64, // time (in milliseconds) a subcomponent can take before lower priority subcomponents are postponed
+    #[structopt(name="max_clock_skew", long)]
+    max_clock_skew: Option<u64>, // add the time of the block maker to block proposals and reject those more than the given milliseconds in the future
+    #[structopt(name="key_file", long)]
+    key_file: Option<String>, // file containing the protobuf encoded ed25519 keypair of the replica, generated if it does not exist
+    #[structopt(name="committee_file", long)]
+    committee_file: Option<String>, // JSON file mapping the node id of each replica to its public key and PeerId, by default the committee contains the replicas from 1 to n
+    #[structopt(name="epoch_length", long, default_value = "100", parse(try_from_str = parse_epoch_length))]
+    epoch_length: u64, // number of heights in an epoch, the committee can only change at the first height of an epoch
+    #[structopt(name="registry_updates", long)]
+    registry_updates: Option<String>, // JSON file containing the list of registry updates proposed by the replica, each one signed by n - f members of the committee
+    #[structopt(name="sign_registry_updates", long)]
+    sign_registry_updates: bool, // add the signature of the replica to each update of the registry updates file and exit
+    #[structopt(name="equivocation_exclusion", long, default_value = "100")]
+    equivocation_exclusion: u64, // number of heights during which the shares of a replica which equivocated are ignored, 0 to keep counting them
+    #[structopt(name="stall_timeout", long, default_value = "5000")]
+    stall_timeout: u64, // time (in milliseconds) after which a round without notarization is stalled and its artifacts are gossiped again, 0 to disable
+    #[structopt(name="peers_file", long)]
+    peers_file: Option<String>, // file containing the multiaddresses of the remote peers (one per line), dialed at startup
+    #[structopt(name="output", long, default_value = "./benchmark/benchmark_results.json")]
+    output: String, // file the results are written to, `{replica}` and `{run_id}` are replaced by the replica number and the run id
+    #[structopt(name="output_format", long, default_value = "json")]
+    output_format: OutputFormat, // format of the results: json, jsonl (one line per height) or csv (one row per height)
+    #[structopt(name="run_id", long)]
+    run_id: Option<String>, // identifier of the run recorded in the results, defaults to the time at which the replica is started (in seconds since UNIX epoch)
+    #[structopt(name="print_peer_id", long)]
+    print_peer_id: bool, // print the PeerId derived from the node identity and exit
+    #[structopt(name="print_public_key", long)]
+    print_public_key: bool, // print the public key (hex encoded) of the node identity, to be written in the committee file, and exit
 }
 
-#[derive(Clone)]
-pub struct SubnetParams {
-    total_nodes_number: u8,
-    byzantine_nodes_number: u8,
-    disagreeing_nodes_number: u8,
-    consensus_on_demand: bool,
-    artifact_delay: u64,
-    artifact_manager_polling_interval: u64,
-}
-
-impl SubnetParams {
-    fn new(n: u8, f: u8, p: u8, cod: bool, d: u64, artifact_manager_polling_interval: u64) -> Self {
-        Self {
-            total_nodes_number: n,
-            byzantine_nodes_number: f,
-            disagreeing_nodes_number: p,
-            consensus_on_demand: cod,
-            artifact_delay: d,
-            artifact_manager_polling_interval,
-        }
+fn parse_epoch_length(s: &str) -> std::result::Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("the epoch length must be at least 1".to_string()),
+        Ok(epoch_length) => Ok(epoch_length),
+        Err(e) => Err(e.to_string()),
     }
 }
 
@@ -117,77 +131,306 @@ async fn post_remote_peers_addresses(mut req: Request<String>, sender: Arc<RwLoc
     Ok(res)
 }
 
+async fn get_finality_proof(req: Request<String>, consensus_pool: Arc<RwLock<ConsensusPoolImpl>>) -> Result {
+    let height: Height = match req.param("height").map(|height| height.parse()) {
+        Ok(Ok(height)) => height,
+        _ => return Ok(Response::builder(400).build()),
+    };
+    let finality_proof = {
+        let pool = consensus_pool.read().unwrap();
+        build_finality_proof(&PoolReader::new(&pool), height)
+    };
+    let res = match finality_proof {
+        Some(finality_proof) => Response::builder(200)
+            .header("Content-Type", "application/json")
+            .body(Body::from_json(&finality_proof)?)
+            .build(),
+        // the height is not finalized yet or the pool does not contain the shares proving it
+        None => Response::builder(404).build(),
+    };
+    Ok(res)
+}
+
+// number of the last finalized heights whose fast path report is returned by the status API
+const STATUS_REPORTED_HEIGHTS: usize = 10;
+
+#[derive(Serialize)]
+struct ReplicaStatus {
+    notarized_height: Height,
+    finalized_height: Height,
+    fast_path_statistics: FastPathStatistics,
+    last_finalized_heights: Vec<FastPathReport>,
+    // last rounds which did not progress within the stall timeout
+    stalled_rounds: Vec<StalledRound>,
+}
+
+async fn get_status(
+    _req: Request<String>,
+    consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
+    fast_path_tracker: Arc<RwLock<FastPathTracker>>,
+    stalled_rounds: Arc<RwLock<StalledRounds>>,
+) -> Result {
+    let status = {
+        // only the reports of the heights finalized since the previous request and of the last heights are computed
+        let mut fast_path_tracker = fast_path_tracker.write().unwrap();
+        let pool = consensus_pool.read().unwrap();
+        let pool_reader = PoolReader::new(&pool);
+        let (fast_path_statistics, last_finalized_heights) = fast_path_tracker.update(&pool_reader);
+        ReplicaStatus {
+            notarized_height: pool_reader.get_notarized_height(),
+            finalized_height: pool_reader.get_finalized_height(),
+            fast_path_statistics,
+            last_finalized_heights,
+            stalled_rounds: stalled_rounds.read().unwrap().values().rev().take(STATUS_REPORTED_HEIGHTS).rev().cloned().collect(),
+        }
+    };
+    let res = Response::builder(200)
+        .header("Content-Type", "application/json")
+        .body(Body::from_json(&status)?)
+        .build();
+    Ok(res)
+}
+
+async fn get_fast_path(req: Request<String>, consensus_pool: Arc<RwLock<ConsensusPoolImpl>>) -> Result {
+    let height: Height = match req.param("height").map(|height| height.parse()) {
+        Ok(Ok(height)) => height,
+        _ => return Ok(Response::builder(400).build()),
+    };
+    let fast_path_report = {
+        let pool = consensus_pool.read().unwrap();
+        get_fast_path_report(&PoolReader::new(&pool), height)
+    };
+    let res = match fast_path_report {
+        Some(fast_path_report) => Response::builder(200)
+            .header("Content-Type", "application/json")
+            .body(Body::from_json(&fast_path_report)?)
+            .build(),
+        // the height is not finalized yet
+        None => Response::builder(404).build(),
+    };
+    Ok(res)
+}
+
 #[async_std::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
-    println!("Replica number: {} running FICC: {}, with F: {}, P: {}, notarization delay: {}, broadcast_interval: {}, and artifact manager polling interval: {}", opt.r, opt.cod, opt.f, opt.p, opt.d, opt. broadcast_interval, opt.artifact_manager_polling_interval);
+    println!("Replica number: {} running FICC: {}, switching mode at runtime: {}, with F: {}, P: {}, notarization delay: {}, broadcast_interval: {}, and artifact manager polling interval: {}", opt.r, opt.cod, opt.auto, opt.f, opt.p, opt.d, opt. broadcast_interval, opt.artifact_manager_polling_interval);
+
+    // use a persistent node identity if a key file is given, otherwise the PeerId changes at every run
+    let local_key = match &opt.key_file {
+        Some(key_file) => load_or_generate_keypair(key_file),
+        None => Keypair::generate_ed25519(),
+    };
+    if opt.print_peer_id {
+        println!("{}", PeerId::from(local_key.public()));
+        return Ok(());
+    }
+    let identity_keypair = match &local_key {
+        Keypair::Ed25519(keypair) => keypair.clone(),
+        _ => panic!("the node identity must be an ed25519 keypair"),
+    };
+    if opt.print_public_key {
+        println!("{}", hex::encode(identity_keypair.public().encode()));
+        return Ok(());
+    }
 
     let finalizations_times = Arc::new(RwLock::new(BTreeMap::<Height, Option<HeightMetrics>>::new()));
     let cloned_finalization_times = Arc::clone(&finalizations_times);
 
+    let artifact_delays = Arc::new(RwLock::new(ArtifactDelays::new()));
+    let cloned_artifact_delays = Arc::clone(&artifact_delays);
+
+    let scheduler_metrics = Arc::new(RwLock::new(SchedulerMetrics::new()));
+    let cloned_scheduler_metrics = Arc::clone(&scheduler_metrics);
+
+    let stalled_rounds = Arc::new(RwLock::new(StalledRounds::new()));
+    let cloned_stalled_rounds = Arc::clone(&stalled_rounds);
+
+    let run_id = opt.run_id.clone().unwrap_or_else(|| (system_time_now().as_nanos_since_unix_epoch() / 1_000_000_000).to_string());
+    let output_path = output_path(&opt.output, opt.r, &run_id);
+
+    let committee = match &opt.committee_file {
+        Some(committee_file) => Committee::load(committee_file),
+        None => Committee::with_size(opt.n),
+    };
+    if !committee.contains(opt.r) {
+        // the replica only takes part in consensus once a registry update adds it to the committee
+        println!("Replica {} is not a member of the initial committee", opt.r);
+    }
+    // with a committee file, the replica signs with the keypair of its node identity, otherwise all the replicas
+    // sign with the keypairs derived from their node ids, which anyone can derive (see `crypto::derived_keypair`)
+    let signer = match &opt.committee_file {
+        Some(_) => Signer::new(opt.r, identity_keypair),
+        None => Signer::with_derived_key(opt.r),
+    };
+    if let Some(public_key) = committee.public_key(opt.r) {
+        if *public_key != signer.public_key() {
+            println!("Public key: {:?} of replica: {} differs from its public key in the committee: {:?}", signer.public_key(), opt.r, public_key);
+        }
+    }
+    let mut registry_updates: Vec<AuthorizedRegistryUpdate> = match &opt.registry_updates {
+        Some(registry_updates_file) => serde_json::from_str(
+            &std::fs::read_to_string(registry_updates_file).expect("can read registry updates file"),
+        ).expect("valid registry updates file"),
+        None => vec![],
+    };
+    if opt.sign_registry_updates {
+        let registry_updates_file = opt.registry_updates.as_ref().expect("registry updates file to sign");
+        for update in registry_updates.iter_mut() {
+            update.signature.insert(signer.sign(update.content.clone()).signature);
+        }
+        std::fs::write(registry_updates_file, serde_json::to_string(&registry_updates).expect("can serialize registry updates"))
+            .expect("can write registry updates file");
+        println!("Replica {} signed {} registry updates", opt.r, registry_updates.len());
+        return Ok(());
+    }
+
+    // invalid combinations of options stop the replica at startup instead of panicking or being silently ignored while running consensus
+    let subnet_params = match SubnetParams::builder(committee)
+        .byzantine_nodes_number(opt.f)
+        .disagreeing_nodes_number(opt.p)
+        .consensus_on_demand(opt.cod)
+        .adaptive_consensus(opt.auto)
+        .mode_switch_window(opt.mode_switch_window)
+        .mode_switch_threshold(opt.mode_switch_threshold)
+        .artifact_delay(opt.d)
+        .adaptive_delay(opt.adaptive_delay)
+        .adaptive_delay_min(opt.adaptive_delay_min)
+        .adaptive_delay_max(opt.adaptive_delay_max)
+        .delay_backoff_base(opt.delay_backoff_base)
+        .artifact_manager_polling_interval(opt.artifact_manager_polling_interval)
+        .queue_capacity(opt.queue_capacity)
+        .batch_changes(opt.batch_changes)
+        .max_starvation(opt.max_starvation)
+        .subcomponent_time_budget(opt.subcomponent_time_budget)
+        .max_clock_skew(opt.max_clock_skew)
+        .epoch_length(opt.epoch_length)
+        .registry_updates(registry_updates)
+        .equivocation_exclusion(opt.equivocation_exclusion)
+        .stall_timeout(opt.stall_timeout)
+        .build()
+    {
+        Ok(subnet_params) => subnet_params,
+        Err(e) => structopt::clap::Error::with_description(
+            &format!("invalid subnet parameters: {:?}", e),
+            structopt::clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+    };
+
+    let consensus_pool = Arc::new(RwLock::new(ConsensusPoolImpl::new(subnet_params.clone())));
+    let cloned_consensus_pool = Arc::clone(&consensus_pool);
+    let results_consensus_pool = Arc::clone(&consensus_pool);
+
     let mut my_peer = Peer::new(
         opt.r,
         opt.port,
-        SubnetParams::new(
-            opt.n,
-            opt.f,
-            opt.p,
-            opt.cod,
-            opt.d,
-            opt.artifact_manager_polling_interval
-        ),
+        subnet_params.clone(),
         "gossip_blocks",
+        local_key,
+        signer,
         cloned_finalization_times,
+        cloned_artifact_delays,
+        cloned_scheduler_metrics,
+        cloned_stalled_rounds,
+        cloned_consensus_pool,
     ).await;
 
     // Listen on all available interfaces at port specified in opt.port
     my_peer.listen_for_dialing();
     let local_peer_id = my_peer.id.to_string();
 
+    // on SIGINT or SIGTERM the replica stops the consensus layer and writes the results collected so far,
+    // a second signal terminates it immediately
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_default(signal, Arc::clone(&shutdown)).expect("can register signal handler");
+        signal_hook::flag::register(signal, Arc::clone(&shutdown)).expect("can register signal handler");
+    }
+
     let (sender_peers_addresses, receiver_peers_addresses) = 
     crossbeam_channel::unbounded::<String>();
 
+    if let Some(peers_file) = &opt.peers_file {
+        // with a static peer list, remote peers can be dialed immediately without waiting for their addresses to be posted
+        sender_peers_addresses.send(read_peers_file(peers_file)).unwrap();
+    }
+
     thread::spawn(move || {
         let mut peers_addresses = String::new();
         println!("Waiting to receive peers addresses...");
-        match receiver_peers_addresses.recv() {
-            Ok(addresses) => {
-                peers_addresses.push_str(&addresses);
-            },
-            Err(_) => (),
+        loop {
+            match receiver_peers_addresses.recv_timeout(Duration::from_millis(100)) {
+                Ok(addresses) => {
+                    peers_addresses.push_str(&addresses);
+                    break;
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    if shutdown.load(Ordering::SeqCst) {
+                        // no result to write yet
+                        println!("\nStopped replica before receiving peers addresses");
+                        std::process::exit(0);
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
         }
         println!("Received peers addresses: {}", peers_addresses);
 
         task::block_on(async {
             my_peer.dial_peers(peers_addresses);
 
-            let starting_time = system_time_now();
+            let metadata = RunMetadata {
+                run_id,
+                replica_number: opt.r,
+                subnet_params,
+                git_revision: env!("GIT_REVISION").to_string(),
+                start_time: system_time_now(),
+            };
+            let starting_time = monotonic_time_now();
             let relative_duration = Duration::from_millis(opt.t * 1000);
             let absolute_end_time = get_absolute_end_time(starting_time, relative_duration);
+            // locally generated artifacts are broadcasted as soon as they are received from the consensus layer,
+            // independently of the timer used for keep alive messages
+            let outgoing_artifacts = my_peer.outgoing_artifacts();
+            let mut broadcast_interval = stream::interval(Duration::from_millis(opt.broadcast_interval));
             loop {
-                if system_time_now() < absolute_end_time {
-                    let mut broadcast_interval = stream::interval(Duration::from_millis(opt.broadcast_interval));
+                if monotonic_time_now() < absolute_end_time && !shutdown.load(Ordering::SeqCst) {
                     select! {
                         _ = broadcast_interval.next().fuse() => {
-                            // prevent Mdns expiration event by periodically broadcasting keep alive messages to peers
-                            // if any locally generated artifact, broadcast it
+                            my_peer.redial_disconnected_peers();
                             if my_peer.artifact_manager_started() {
-                                my_peer.broadcast_message();
+                                my_peer.send_keepalive();
+                            }
+                        },
+                        outgoing_artifact = outgoing_artifacts.recv().fuse() => {
+                            if let Ok(outgoing_artifact) = outgoing_artifact {
+                                my_peer.broadcast_artifact(outgoing_artifact);
                             }
                         },
                         event = my_peer.get_next_event() => my_peer.match_event(event),
                     }
                 } else {
                     // println!("\nStopped replica");
+                    let interrupted = shutdown.load(Ordering::SeqCst);
+                    if interrupted {
+                        println!("\nReceived shutdown signal, writing results collected so far");
+                    }
+                    // wait for the consensus layer to stop so that the metrics are not updated while being written
+                    let pool_state = my_peer.shutdown();
+                    let fast_path = get_fast_path_reports(&PoolReader::new(&results_consensus_pool.read().unwrap()));
                     let benchmark_result = BenchmarkResult {
+                        metadata,
                         finalization_times: finalizations_times.read().unwrap().clone(),
+                        artifact_delays: artifact_delays.read().unwrap().clone(),
+                        scheduler_metrics: scheduler_metrics.read().unwrap().clone(),
+                        queue_metrics: my_peer.queue_metrics(),
+                        pool_state,
+                        fast_path_statistics: FastPathStatistics::new(fast_path.values()),
+                        fast_path,
+                        interrupted,
                     };
-
-                    let encoded = to_string(&benchmark_result).unwrap();
-                    let mut file = File::create(format!("./benchmark/benchmark_results.json"))
-                        .await
-                        .unwrap();
-                    file.write_all(encoded.as_bytes()).await.unwrap();
+                    write_results(&output_path, opt.output_format, &benchmark_result).await;
 
                     break;
                 }
@@ -206,6 +449,18 @@ async fn main() -> Result<()> {
     app.at("/remote_peers_addresses")
         .post(move |req| post_remote_peers_addresses(req, Arc::clone(&cloned_arc_sender_peers_addresses)));
 
+    let cloned_consensus_pool = Arc::clone(&consensus_pool);
+    app.at("/finality_proof/:height")
+        .get(move |req| get_finality_proof(req, Arc::clone(&cloned_consensus_pool)));
+
+    let cloned_consensus_pool = Arc::clone(&consensus_pool);
+    app.at("/fast_path/:height")
+        .get(move |req| get_fast_path(req, Arc::clone(&cloned_consensus_pool)));
+
+    let fast_path_tracker = Arc::new(RwLock::new(FastPathTracker::new(STATUS_REPORTED_HEIGHTS)));
+    app.at("/status")
+        .get(move |req| get_status(req, Arc::clone(&consensus_pool), Arc::clone(&fast_path_tracker), Arc::clone(&stalled_rounds)));
+
     app.listen(format!("0.0.0.0:{}", opt.port+1)).await?;
 
     Ok(())
@@ -1,9 +1,12 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    sync::{Arc, RwLock}, time::Duration,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex, RwLock}, time::Duration,
 };
 use std::thread::sleep;
-use crossbeam_channel::{Receiver, Sender};
+use async_std::channel::{self, Receiver, Sender};
+use crossbeam_channel::{RecvTimeoutError, TrySendError};
 use futures::{prelude::stream::StreamExt, stream::SelectNextSome};
 use libp2p::{
     floodsub::{Floodsub, FloodsubEvent, Topic},
@@ -15,14 +18,24 @@ use libp2p::{
 };
 use serde::{Deserialize, Serialize};
 
+pub mod codec;
+
+use codec::Reassembler;
+
 use crate::{
-    artifact_manager::ArtifactProcessorManager,
+    artifact_manager::{ArtifactProcessorManager, QueueMetrics},
+    committee::NodeId,
     consensus_layer::{
-        artifacts::{ConsensusMessage, UnvalidatedArtifact},
-        height_index::Height, consensus_subcomponents::{block_maker::{BlockProposal, Block, Payload}, notary::{NotarizationShareContentICC, NotarizationShareContentCOD, NotarizationShareContent}},
+        artifacts::ConsensusMessage,
+        pool::ConsensusPoolImpl,
+        scheduler::SchedulerMetrics,
+        PoolState,
+        height_index::Height, consensus_subcomponents::{block_maker::BlockProposal, stall_detector::StalledRounds, notary::{NotarizationShareContentICC, NotarizationShareContentCOD, NotarizationShareContent}},
     },
-    time_source::{SysTimeSource, TimeSource, system_time_now},
-    SubnetParams, HeightMetrics, crypto::{CryptoHash, Hashed, Signed}, ArtifactDelayInfo,
+    crypto::Signer,
+    time_source::{SysTimeSource, Time, monotonic_time_now, system_time_now},
+    transport::{ReceivedArtifact, Transport, TransportClosed},
+    SubnetParams, HeightMetrics, ArtifactDelays,
 };
 
 // We create a custom network behaviour that combines floodsub and mDNS.
@@ -45,14 +58,26 @@ impl From<FloodsubEvent> for OutEvent {
     }
 }
 
+// delay before the first attempt to reconnect to a disconnected peer, doubled after each failed attempt
+const INITIAL_RECONNECTION_BACKOFF: Duration = Duration::from_millis(500);
+// upper bound on the delay between two attempts to reconnect to a disconnected peer
+const MAX_RECONNECTION_BACKOFF: Duration = Duration::from_secs(30);
+
+// state of the reconnection attempts to a peer which is currently disconnected
+struct ReconnectionState {
+    backoff: Duration,
+    next_attempt: Time,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    ConsensusMessage(ConsensusMessage),
+    ConsensusMessage(ConsensusMessage, Time), // artifact and time at which it is sent
     KeepAliveMessage,
+    Fragment(codec::Fragment), // part of a message too large to be sent at once, see [codec::encode_fragments]
 }
 
 pub struct Peer {
-    replica_number: u8,
+    replica_number: NodeId,
     pub id: PeerId,
     artifact_manager_started: bool,
     subnet_params: SubnetParams,
@@ -63,25 +88,51 @@ pub struct Peer {
     listening_port: u64,
     subscribed_peers: BTreeSet<PeerId>,
     connected_peers: BTreeSet<PeerId>,
+    peers_addresses: BTreeMap<PeerId, Multiaddr>,
+    reconnections: BTreeMap<PeerId, ReconnectionState>,
+    decoding_errors: BTreeMap<PeerId, u64>,
+    reassemblers: BTreeMap<PeerId, Reassembler>,
+    first_block_proposal: Option<BlockProposal>,
     receiver_outgoing_artifact: Receiver<ConsensusMessage>,
     sender_outgoing_artifact: Sender<ConsensusMessage>,
+    sender_incoming_artifact: crossbeam_channel::Sender<ReceivedArtifact>,
+    receiver_incoming_artifact: crossbeam_channel::Receiver<ReceivedArtifact>,
     finalization_times: Arc<RwLock<BTreeMap<Height, Option<HeightMetrics>>>>,
+    artifact_delays: Arc<RwLock<ArtifactDelays>>,
+    scheduler_metrics: Arc<RwLock<SchedulerMetrics>>,
+    stalled_rounds: Arc<RwLock<StalledRounds>>,
+    // consensus pool of the artifact manager, shared so that it can be read while the replica is running (ex. to build finality proofs)
+    consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
+    queue_metrics: Arc<Mutex<QueueMetrics>>,
     time_source: Arc<SysTimeSource>,
     manager: Option<ArtifactProcessorManager>,
+    // signs the artifacts of the replica, handed over to the artifact manager once it starts
+    signer: Signer,
 }
 
 impl Peer {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        replica_number: u8,
+        replica_number: NodeId,
         listening_port: u64,
         subnet_params: SubnetParams,
         topic: &str,
+        local_key: Keypair,
+        signer: Signer,
         finalization_times: Arc<RwLock<BTreeMap<Height, Option<HeightMetrics>>>>,
+        artifact_delays: Arc<RwLock<ArtifactDelays>>,
+        scheduler_metrics: Arc<RwLock<SchedulerMetrics>>,
+        stalled_rounds: Arc<RwLock<StalledRounds>>,
+        consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
     ) -> Self {
         let starting_round = 1;
-        // Create a random PeerId
-        let local_key = Keypair::generate_ed25519();
+        // PeerId is derived from the node identity, so it is stable across runs if the keypair is loaded from a key file
         let local_peer_id = PeerId::from(local_key.public());
+        if let Some(network_id) = subnet_params.committee.network_id(replica_number) {
+            if network_id != local_peer_id.to_string() {
+                println!("PeerId: {} differs from the network identity of replica: {} in the committee: {}", local_peer_id, replica_number, network_id);
+            }
+        }
 
         // Set up an encrypted DNS-enabled TCP Transport
         let transport = libp2p::development_transport(local_key).await.unwrap();
@@ -90,8 +141,12 @@ impl Peer {
         let floodsub_topic = Topic::new(topic);
 
         // channel used to transmit locally generated artifacts from the consensus layer to the network layer so that they can be broadcasted to other peers
+        // the network layer is woken up as soon as an artifact is sent, when the channel is full the consensus layer waits for it to broadcast the previous artifacts
         let (sender_outgoing_artifact, receiver_outgoing_artifact) =
-            crossbeam_channel::unbounded::<ConsensusMessage>();
+            channel::bounded::<ConsensusMessage>(subnet_params.queue_capacity);
+        // channel used to transmit the artifacts received from other peers from the network layer to the consensus layer
+        let (sender_incoming_artifact, receiver_incoming_artifact) =
+            crossbeam_channel::bounded::<ReceivedArtifact>(subnet_params.queue_capacity);
 
         // Initialize the time source.
         let time_source = Arc::new(SysTimeSource::new());
@@ -116,11 +171,24 @@ impl Peer {
             listening_port,
             subscribed_peers: BTreeSet::new(),
             connected_peers: BTreeSet::new(),
+            peers_addresses: BTreeMap::new(),
+            reconnections: BTreeMap::new(),
+            decoding_errors: BTreeMap::new(),
+            reassemblers: BTreeMap::new(),
+            first_block_proposal: None,
             receiver_outgoing_artifact,
             sender_outgoing_artifact,
+            sender_incoming_artifact,
+            receiver_incoming_artifact,
             finalization_times,
+            artifact_delays,
+            scheduler_metrics,
+            stalled_rounds,
+            consensus_pool,
+            queue_metrics: Arc::new(Mutex::new(QueueMetrics::default())),
             time_source,
             manager: None,
+            signer,
         };
         // println!(
         //     "Local node initialized with number: {} and peer id: {:?}",
@@ -140,9 +208,11 @@ impl Peer {
     }
 
     pub fn dial_peers(&mut self, peers_addresses: String) {
-        for peer_address in peers_addresses.split(',') {
+        for peer_address in peers_addresses.split(',').map(str::trim).filter(|address| !address.is_empty()) {
             let remote_peer_multiaddr: Multiaddr = peer_address.parse().expect("valid address");
             let remote_peer_id = PeerId::try_from_multiaddr(&remote_peer_multiaddr).expect("multiaddress with peer ID");
+            // remember the address in order to be able to reconnect if the connection is closed
+            self.peers_addresses.insert(remote_peer_id, remote_peer_multiaddr.clone());
             if !self.subscribed_peers.contains(&remote_peer_id) {
                 self.swarm.dial(remote_peer_multiaddr.clone()).expect("known peer");
                 self.swarm
@@ -155,79 +225,137 @@ impl Peer {
         }
     }
 
-    pub fn broadcast_message(&mut self) {
-        match self.receiver_outgoing_artifact.try_recv() {
-            Ok(outgoing_artifact) => {
-                if self.replica_number == 1 {
-                    match &outgoing_artifact {
-                        ConsensusMessage::BlockProposal(proposal) => {
-                            if proposal.content.value.height == 1 {
-                                sleep(Duration::from_millis(500));
+    /// Redial the disconnected peers whose backoff delay has expired.
+    pub fn redial_disconnected_peers(&mut self) {
+        let now = monotonic_time_now();
+        let peers_to_redial: Vec<PeerId> = self
+            .reconnections
+            .iter()
+            .filter(|(_, reconnection)| reconnection.next_attempt <= now)
+            .map(|(remote_peer_id, _)| *remote_peer_id)
+            .collect();
+        for remote_peer_id in peers_to_redial {
+            let remote_peer_multiaddr = self.peers_addresses[&remote_peer_id].clone();
+            if let Some(reconnection) = self.reconnections.get_mut(&remote_peer_id) {
+                // do not redial while the current attempt is pending, the backoff is increased only if the attempt fails
+                reconnection.next_attempt = now + reconnection.backoff;
+            }
+            match self.swarm.dial(remote_peer_multiaddr) {
+                Ok(()) => println!("Redialing remote peer: {:?}", remote_peer_id),
+                Err(e) => {
+                    println!("Could not redial remote peer: {:?}, error: {:?}", remote_peer_id, e);
+                    self.schedule_reconnection(remote_peer_id);
+                }
+            }
+        }
+    }
+
+    // Schedule the next attempt to reconnect to a peer, with exponential backoff between consecutive attempts.
+    // Only peers whose address is known (i.e. which have been dialed by the local replica) can be redialed.
+    fn schedule_reconnection(&mut self, remote_peer_id: PeerId) {
+        if !self.peers_addresses.contains_key(&remote_peer_id) || self.connected_peers.contains(&remote_peer_id) {
+            return;
+        }
+        let backoff = match self.reconnections.get(&remote_peer_id) {
+            Some(reconnection) => std::cmp::min(reconnection.backoff * 2, MAX_RECONNECTION_BACKOFF),
+            None => INITIAL_RECONNECTION_BACKOFF,
+        };
+        self.reconnections.insert(
+            remote_peer_id,
+            ReconnectionState {
+                backoff,
+                next_attempt: monotonic_time_now() + backoff,
+            },
+        );
+    }
+
+    /// Receiving end of the channel of the artifacts generated by the consensus layer, to be awaited
+    /// alongside the swarm events so that each artifact is broadcasted as soon as it is generated.
+    pub fn outgoing_artifacts(&self) -> Receiver<ConsensusMessage> {
+        self.receiver_outgoing_artifact.clone()
+    }
+
+    // prevent Mdns expiration event by periodically broadcasting keep alive messages to peers
+    pub fn send_keepalive(&mut self) {
+        // println!("Sending keepalive");
+        self.publish(&Message::KeepAliveMessage);
+    }
+
+    pub fn broadcast_artifact(&mut self, outgoing_artifact: ConsensusMessage) {
+        {
+            // the artifact has already been taken from the queue
+            let outgoing_depth = self.receiver_outgoing_artifact.len() + 1;
+            let mut queue_metrics = self.queue_metrics.lock().unwrap();
+            queue_metrics.max_outgoing_depth = queue_metrics.max_outgoing_depth.max(outgoing_depth);
+        }
+        // the first replica of the committee proposes the block at height 1
+        if self.subnet_params.committee.position(self.replica_number) == Some(0) {
+            match &outgoing_artifact {
+                ConsensusMessage::BlockProposal(proposal) => {
+                    if proposal.content.value.height == 1 {
+                        sleep(Duration::from_millis(500));
+                        self.first_block_proposal = Some(proposal.clone());
+                    }
+                },
+                ConsensusMessage::NotarizationShare(share) => {
+                    match &share.content {
+                        NotarizationShareContent::COD(ack) => {
+                            if ack.height == 1 {
+                                self.rebroadcast_first_block_proposal();
                             }
-                        },
-                        ConsensusMessage::NotarizationShare(share) => {
-                            match &share.content {
-                                NotarizationShareContent::COD(ack) => {
-                                    if ack.height == 1 {
-                                        println!("Rebroadcasting first block proposal");
-                                        self.swarm.behaviour_mut().floodsub.publish(
-                                            self.floodsub_topic.clone(),
-                                            serde_json::to_string::<Message>(&Message::ConsensusMessage(ConsensusMessage::BlockProposal(Signed {
-                                                content: Hashed {
-                                                    hash: String::from("426d3a77ace30d95db82aaaa9c49dbb6718bfbf106968ae0218f0f588871e229"),
-                                                    value: Block {
-                                                        parent: String::from("8c43f94e4759170f3b528ba6ff62171f4d26fd12ca4f4cca1da81a6534746715"),
-                                                        payload: Payload::new(),
-                                                        height: 1,
-                                                        rank: 0,
-                                                    },
-                                                },
-                                                signature: self.replica_number,
-                                            })))
-                                                .unwrap(),
-                                        );
-                                    }
-                                }
-                                NotarizationShareContent::ICC(share) => {
-                                    if share.height == 1 {
-                                        println!("Rebroadcasting first block proposal");
-                                        self.swarm.behaviour_mut().floodsub.publish(
-                                            self.floodsub_topic.clone(),
-                                            serde_json::to_string::<Message>(&Message::ConsensusMessage(ConsensusMessage::BlockProposal(Signed {
-                                                content: Hashed {
-                                                    hash: String::from("426d3a77ace30d95db82aaaa9c49dbb6718bfbf106968ae0218f0f588871e229"),
-                                                    value: Block {
-                                                        parent: String::from("8c43f94e4759170f3b528ba6ff62171f4d26fd12ca4f4cca1da81a6534746715"),
-                                                        payload: Payload::new(),
-                                                        height: 1,
-                                                        rank: 0,
-                                                    },
-                                                },
-                                                signature: self.replica_number,
-                                            })))
-                                                .unwrap(),
-                                        );
-                                    }
-                                }
+                        }
+                        NotarizationShareContent::ICC(share) => {
+                            if share.height == 1 {
+                                self.rebroadcast_first_block_proposal();
                             }
-                        },
-                        _ => (),
+                        }
                     }
-                }
-                // println!("\nBroadcasted locally generated artifact: {:?}", outgoing_artifact);
-                self.swarm.behaviour_mut().floodsub.publish(
-                    self.floodsub_topic.clone(),
-                    serde_json::to_string::<Message>(&Message::ConsensusMessage(outgoing_artifact))
-                        .unwrap(),
-                );
+                },
+                _ => (),
             }
-            Err(_) => {
-                // println!("Sending keepalive");
-                self.swarm.behaviour_mut().floodsub.publish(
-                    self.floodsub_topic.clone(),
-                    serde_json::to_string::<Message>(&Message::KeepAliveMessage).unwrap(),
-                );
+        }
+        // println!("\nBroadcasted locally generated artifact: {:?}", outgoing_artifact);
+        self.publish(&Message::ConsensusMessage(outgoing_artifact, system_time_now()));
+    }
+
+    // block proposal of rank 0 at height 1, rebroadcasted by the first replica of the committee
+    fn rebroadcast_first_block_proposal(&mut self) {
+        if let Some(first_block_proposal) = self.first_block_proposal.clone() {
+            println!("Rebroadcasting first block proposal");
+            self.publish(&Message::ConsensusMessage(ConsensusMessage::BlockProposal(first_block_proposal), system_time_now()));
+        }
+    }
+
+    // Sources of the messages which are decoded: the members of the committee if their network identities are known,
+    // otherwise the connected peers.
+    fn is_known_source(&self, source: &PeerId) -> bool {
+        if self.subnet_params.committee.has_network_ids() {
+            self.is_known_replica(&source.to_string())
+        } else {
+            self.connected_peers.contains(source)
+        }
+    }
+
+    // Return true if the network identity belongs to a member of the initial committee or to a replica added by the
+    // registry updates known locally, the signature of each artifact is then checked against the committee of its height.
+    fn is_known_replica(&self, network_id: &str) -> bool {
+        self.subnet_params.committee.node_id_of(network_id).is_some()
+            || self.subnet_params.registry_updates.iter().any(|update| {
+                update.content.added.values().any(|member| member.network_id.as_deref() == Some(network_id))
+            })
+    }
+
+    fn publish(&mut self, message: &Message) {
+        match codec::encode_fragments(message) {
+            Ok(fragments) => {
+                for bytes in fragments {
+                    self.swarm
+                        .behaviour_mut()
+                        .floodsub
+                        .publish(self.floodsub_topic.clone(), bytes);
+                }
             }
+            Err(e) => println!("Could not encode message: {:?}, error: {:?}", message, e),
         }
     }
 
@@ -247,10 +375,31 @@ impl Peer {
             SwarmEvent::Behaviour(OutEvent::Floodsub(floodsub_event)) => {
                 match floodsub_event {
                     FloodsubEvent::Message(floodsub_message) => {
-                        let floodsub_content = String::from_utf8_lossy(&floodsub_message.data);
-                        let message =
-                            serde_json::from_str::<Message>(&floodsub_content).expect("can parse artifact");
-                        self.handle_incoming_message(message);
+                        let received = system_time_now();
+                        // the source of a floodsub message is not authenticated, so that fragments are only buffered
+                        // for known sources, otherwise a peer could allocate a reassembler for each source it makes up
+                        if !self.is_known_source(&floodsub_message.source) {
+                            println!("Dropped message from unknown source: {}", floodsub_message.source);
+                            return;
+                        }
+                        let reassembler = self.reassemblers.entry(floodsub_message.source).or_default();
+                        match reassembler.decode(&floodsub_message.data) {
+                            Ok(Some(message)) => {
+                                self.record_artifact_delay(&floodsub_message.source, &message, received);
+                                self.handle_incoming_message(&floodsub_message.source, message);
+                            }
+                            // the other fragments of the message have not been received yet
+                            Ok(None) => (),
+                            Err(e) => {
+                                // a malformed message must not stop the replica, it is dropped and accounted to the peer which sent it
+                                let decoding_errors = self.decoding_errors.entry(floodsub_message.source).or_insert(0);
+                                *decoding_errors += 1;
+                                println!(
+                                    "Dropped undecodable message from peer: {}, error: {:?}, total errors from peer: {}",
+                                    floodsub_message.source, e, decoding_errors
+                                );
+                            }
+                        }
                     },
                     FloodsubEvent::Subscribed { peer_id: remote_peer_id, .. } => {
                         if !self.subscribed_peers.contains(&remote_peer_id) {
@@ -270,20 +419,66 @@ impl Peer {
                 if !self.connected_peers.contains(&remote_peer_id) {
                     println!("Connection established with remote peer: {:?}", remote_peer_id);
                     self.connected_peers.insert(remote_peer_id);
-                    if self.connected_peers.len() == (self.subnet_params.total_nodes_number-1) as usize {
+                    self.reconnections.remove(&remote_peer_id);
+                    if !self.subscribed_peers.contains(&remote_peer_id) {
+                        // the peer was removed from the partial view when it disconnected
+                        self.swarm
+                            .behaviour_mut()
+                            .floodsub
+                            .add_node_to_partial_view(remote_peer_id);
+                        self.subscribed_peers.insert(remote_peer_id);
+                        println!("Added peer with ID: {:?} to broadcast list", remote_peer_id);
+                    }
+                    // consensus can start as soon as n-f replicas (including the local one) are connected
+                    // the remaining replicas are added to the broadcast list once they connect
+                    if self.manager.is_none()
+                        && self.connected_peers.len()
+                            >= (self.subnet_params.total_nodes_number
+                                - self.subnet_params.byzantine_nodes_number
+                                - 1) as usize
+                    {
+                        let transport = Libp2pTransport {
+                            sender_outgoing_artifact: self.sender_outgoing_artifact.clone(),
+                            receiver_incoming_artifact: self.receiver_incoming_artifact.clone(),
+                            queue_metrics: Arc::clone(&self.queue_metrics),
+                        };
                         self.manager = Some(ArtifactProcessorManager::new(
-                            self.replica_number,
+                            self.signer.clone(),
                             self.subnet_params.clone(),
-                            Arc::clone(&self.time_source),
-                            self.sender_outgoing_artifact.clone(),
+                            Arc::clone(&self.time_source) as Arc<_>,
+                            Arc::new(transport),
                             Arc::clone(&self.finalization_times),
+                            Arc::clone(&self.scheduler_metrics),
+                            Arc::clone(&self.stalled_rounds),
+                            Arc::clone(&self.queue_metrics),
+                            Arc::clone(&self.consensus_pool),
                         ));
-                        println!("\nArtifact manager started");
+                        println!("\nArtifact manager started with {} connected peers", self.connected_peers.len());
                         self.artifact_manager_started = true;
                     }
                 }
             },
-            SwarmEvent::ConnectionClosed { peer_id, .. } => println!("Peer: {} disconnected", peer_id),
+            SwarmEvent::ConnectionClosed { peer_id: remote_peer_id, num_established, .. } => {
+                // other connections with the same peer might still be open
+                if num_established == 0 {
+                    println!("Peer: {} disconnected", remote_peer_id);
+                    self.connected_peers.remove(&remote_peer_id);
+                    // floodsub would immediately redial peers in its partial view, instead reconnect with backoff
+                    self.swarm
+                        .behaviour_mut()
+                        .floodsub
+                        .remove_node_from_partial_view(&remote_peer_id);
+                    self.subscribed_peers.remove(&remote_peer_id);
+                    if !self.subnet_params.committee.has_network_ids() {
+                        self.reassemblers.remove(&remote_peer_id);
+                    }
+                    self.schedule_reconnection(remote_peer_id);
+                }
+            },
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(remote_peer_id), error } => {
+                println!("Could not connect to remote peer: {}, error: {:?}", remote_peer_id, error);
+                self.schedule_reconnection(remote_peer_id);
+            },
             SwarmEvent::Dialing(peer_id) => println!("Dialed peer {}", peer_id),
             SwarmEvent::ListenerError { listener_id, .. } => println!("Listener with ID: {:?}", listener_id),
             SwarmEvent::IncomingConnection {..} => println!("Incoming connection"),
@@ -293,24 +488,135 @@ impl Peer {
         }
     }
 
-    pub fn handle_incoming_message(&mut self, message_variant: Message) {
+    pub fn handle_incoming_message(&mut self, source: &PeerId, message_variant: Message) {
         match message_variant {
             Message::KeepAliveMessage => (),
-            Message::ConsensusMessage(consensus_message) => {
+            // fragments are put back together by the reassembler of their source
+            Message::Fragment(_) => (),
+            Message::ConsensusMessage(consensus_message, _) => {
                 // println!("\nReceived message: {:?}", consensus_message);
-                match &self.manager {
-                    Some(manager) => {
-                        manager.on_artifact(
-                            UnvalidatedArtifact::new(consensus_message, self.time_source.get_relative_time())
-                        );
-                    },
-                    None => (),
-                };
+                if self.subnet_params.committee.has_network_ids() && !self.is_known_replica(&source.to_string()) {
+                    println!("Dropped artifact from peer: {} which is not a member of the committee", source);
+                    return;
+                }
+                if self.manager.is_some() {
+                    match self.sender_incoming_artifact.try_send((source.to_string(), consensus_message)) {
+                        Ok(()) => (),
+                        Err(TrySendError::Full(_)) => {
+                            *self.queue_metrics.lock().unwrap().incoming_drops.entry(source.to_string()).or_insert(0) += 1;
+                        },
+                        // the artifact manager has been stopped
+                        Err(TrySendError::Disconnected(_)) => (),
+                    }
+                }
             }
         }
     }
 
+    // the delay is measured with the local clock of the sender and of the receiver,
+    // therefore it also includes the clock skew between the two replicas
+    fn record_artifact_delay(&self, source: &PeerId, message: &Message, received: Time) {
+        if let Message::ConsensusMessage(artifact, sent) = message {
+            self.artifact_delays
+                .write()
+                .unwrap()
+                .entry(source.to_string())
+                .or_default()
+                .entry(artifact.type_name().to_string())
+                .or_default()
+                .record(*sent, received);
+        }
+    }
+
+    /// Number of messages received from each peer which could not be decoded.
+    pub fn decoding_errors(&self) -> &BTreeMap<PeerId, u64> {
+        &self.decoding_errors
+    }
+
+    /// Stop broadcasting the artifacts generated by the consensus layer and stop the artifact processing thread.
+    /// Return the state of the consensus pool, or None if the artifact manager has not been started.
+    pub fn shutdown(&mut self) -> Option<PoolState> {
+        // closing the channel also unblocks the processing thread if it is waiting for the channel to have room
+        self.receiver_outgoing_artifact.close();
+        self.manager.as_mut().and_then(|manager| manager.stop())
+    }
+
+    /// Depth and drops of the queues between the network layer and the consensus layer.
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.queue_metrics.lock().unwrap().clone()
+    }
+
     pub fn artifact_manager_started(&self) -> bool {
         self.artifact_manager_started
     }
 }
+
+/// Load the node identity from the protobuf encoded ed25519 keypair stored at `key_file`.
+/// If the file does not exist yet, a new keypair is generated and written to `key_file`
+/// so that the replica keeps the same PeerId in subsequent runs.
+pub fn load_or_generate_keypair(key_file: &str) -> Keypair {
+    let key_path = Path::new(key_file);
+    if key_path.exists() {
+        let encoded_key = fs::read(key_path).expect("can read key file");
+        return Keypair::from_protobuf_encoding(&encoded_key).expect("key file contains a valid ed25519 keypair");
+    }
+    let local_key = Keypair::generate_ed25519();
+    if let Some(keys_dir) = key_path.parent() {
+        fs::create_dir_all(keys_dir).expect("can create keys directory");
+    }
+    fs::write(key_path, local_key.to_protobuf_encoding().expect("ed25519 keypair can be encoded"))
+        .expect("can write key file");
+    println!("Generated new node identity in: {}", key_file);
+    local_key
+}
+
+/// Read the static list of remote peers from `peers_file`.
+/// The file contains one multiaddress (including the `/p2p/<PeerId>` suffix) per line,
+/// empty lines and lines starting with '#' are ignored.
+/// Returns the addresses in the comma separated format expected by [Peer::dial_peers].
+pub fn read_peers_file(peers_file: &str) -> String {
+    fs::read_to_string(peers_file)
+        .expect("can read peers file")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+/// [Transport] over the libp2p swarm of a [Peer]: the artifacts to be sent are handed over to the event loop
+/// of the peer, which publishes them with floodsub, and the artifacts received by the peer are handed over
+/// to the transport.
+pub struct Libp2pTransport {
+    sender_outgoing_artifact: Sender<ConsensusMessage>,
+    receiver_incoming_artifact: crossbeam_channel::Receiver<ReceivedArtifact>,
+    queue_metrics: Arc<Mutex<QueueMetrics>>,
+}
+
+impl Transport for Libp2pTransport {
+    fn broadcast(&self, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        // if the channel is full, wait for the network layer to broadcast the previous artifacts
+        match self.sender_outgoing_artifact.try_send(artifact) {
+            Ok(()) => Ok(()),
+            Err(channel::TrySendError::Full(artifact)) => {
+                self.queue_metrics.lock().unwrap().outgoing_full += 1;
+                self.sender_outgoing_artifact.send_blocking(artifact).map_err(|_| TransportClosed)
+            }
+            // the peer closes the channel when the replica is stopped
+            Err(channel::TrySendError::Closed(_)) => Err(TransportClosed),
+        }
+    }
+
+    // floodsub cannot address a single peer, therefore the artifact is broadcasted to all of them
+    fn send_to(&self, _peer: &str, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        self.broadcast(artifact)
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Option<ReceivedArtifact>, TransportClosed> {
+        match self.receiver_incoming_artifact.recv_timeout(timeout) {
+            Ok(received) => Ok(Some(received)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(TransportClosed),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Binary wire encoding of the messages exchanged between peers.
+//!
+//! Each encoded message starts with a version byte, followed by the bincode
+//! encoding of the [Message]. Messages which cannot be decoded are rejected
+//! with a [DecodeError] instead of stopping the replica.
+//!
+//! Messages larger than [MAX_MESSAGE_SIZE] (ex. the goodness artifacts of large subnets, whose
+//! certificate grows with the number of replicas) are split into [Fragment]s by [encode_fragments]
+//! and put back together by the [Reassembler] of the receiver.
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use super::Message;
+
+/// Version of the wire format, increased whenever the encoding of [Message] changes.
+pub const WIRE_FORMAT_VERSION: u8 = 11;
+
+/// Max size of an encoded message. Floodsub drops RPC packets larger than 2048 bytes,
+/// which also contain the source, sequence number and topic of the message.
+pub const MAX_MESSAGE_SIZE: usize = 1800;
+
+/// Max size of an encoded message split into fragments.
+pub const MAX_FRAGMENTED_MESSAGE_SIZE: usize = 1 << 17;
+
+// bytes of the encoded message carried by each fragment, leaving room for the version and the other fields of the fragment
+const FRAGMENT_SIZE: usize = MAX_MESSAGE_SIZE - 64;
+const MAX_FRAGMENTS: usize = MAX_FRAGMENTED_MESSAGE_SIZE.div_ceil(FRAGMENT_SIZE);
+// messages being reassembled for each source, the oldest one is dropped when a fragment of another message is received
+const MAX_PARTIAL_MESSAGES: usize = 8;
+
+/// Part of the encoding of a message larger than [MAX_MESSAGE_SIZE].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub message_id: u64, // first bytes of the digest of the encoded message
+    pub index: u32,
+    pub count: u32,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    TooLarge(usize),
+    Serialization(bincode::Error),
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Empty,
+    UnsupportedVersion(u8),
+    TooLarge(usize),
+    Malformed(bincode::Error),
+    InvalidFragment,
+    // the reassembled message does not match the digest in its message id
+    DigestMismatch(u64),
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().reject_trailing_bytes()
+}
+
+// Serialize a message without a size limit, prefixed by the version of the wire format.
+fn encode_unchecked(message: &Message) -> Result<Vec<u8>, EncodeError> {
+    let payload = bincode_options()
+        .serialize(message)
+        .map_err(EncodeError::Serialization)?;
+    let mut bytes = Vec::with_capacity(payload.len() + 1);
+    bytes.push(WIRE_FORMAT_VERSION);
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Encode a message prefixed by the version of the wire format. The message is serialized without
+/// a size limit, so that a message larger than [MAX_MESSAGE_SIZE] is reported as [EncodeError::TooLarge].
+pub fn encode(message: &Message) -> Result<Vec<u8>, EncodeError> {
+    let bytes = encode_unchecked(message)?;
+    if bytes.len() > MAX_MESSAGE_SIZE {
+        return Err(EncodeError::TooLarge(bytes.len()));
+    }
+    Ok(bytes)
+}
+
+/// Encode a message in one message if it fits in [MAX_MESSAGE_SIZE], otherwise in fragments which do.
+/// Messages larger than [MAX_FRAGMENTED_MESSAGE_SIZE] are reported as [EncodeError::TooLarge].
+pub fn encode_fragments(message: &Message) -> Result<Vec<Vec<u8>>, EncodeError> {
+    let bytes = encode_unchecked(message)?;
+    if bytes.len() <= MAX_MESSAGE_SIZE {
+        return Ok(vec![bytes]);
+    }
+    if bytes.len() > MAX_FRAGMENTED_MESSAGE_SIZE {
+        return Err(EncodeError::TooLarge(bytes.len()));
+    }
+    let message_id = message_id(&bytes);
+    let chunks: Vec<&[u8]> = bytes.chunks(FRAGMENT_SIZE).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            encode(&Message::Fragment(Fragment {
+                message_id,
+                index: index as u32,
+                count: chunks.len() as u32,
+                bytes: chunk.to_vec(),
+            }))
+        })
+        .collect()
+}
+
+// first bytes of the digest of an encoded message, identifying its fragments
+fn message_id(bytes: &[u8]) -> u64 {
+    let mut message_id = [0u8; 8];
+    message_id.copy_from_slice(&Sha256::digest(bytes)[..8]);
+    u64::from_be_bytes(message_id)
+}
+
+/// Decode a message, rejecting messages encoded with a different version of the wire format,
+/// larger than [MAX_MESSAGE_SIZE] or containing trailing bytes.
+pub fn decode(bytes: &[u8]) -> Result<Message, DecodeError> {
+    decode_with_limit(bytes, MAX_MESSAGE_SIZE)
+}
+
+fn decode_with_limit(bytes: &[u8], limit: usize) -> Result<Message, DecodeError> {
+    if bytes.len() > limit {
+        return Err(DecodeError::TooLarge(bytes.len()));
+    }
+    match bytes.split_first() {
+        None => Err(DecodeError::Empty),
+        Some((&WIRE_FORMAT_VERSION, payload)) => bincode_options()
+            .with_limit(limit as u64)
+            .deserialize(payload)
+            .map_err(DecodeError::Malformed),
+        Some((&version, _)) => Err(DecodeError::UnsupportedVersion(version)),
+    }
+}
+
+struct PartialMessage {
+    count: u32,
+    fragments: BTreeMap<u32, Vec<u8>>,
+    first_received: u64, // number of fragments received from the source before the first fragment of the message
+}
+
+/// Puts back together the messages split into fragments by a single source.
+/// At most [MAX_PARTIAL_MESSAGES] incomplete messages are kept, so that a source cannot exhaust the memory of the replica,
+/// and a reassembled message is dropped if it does not match the digest in its message id.
+#[derive(Default)]
+pub struct Reassembler {
+    partial_messages: BTreeMap<u64, PartialMessage>,
+    received_fragments: u64,
+}
+
+impl Reassembler {
+    /// Decode a message received from the source of the reassembler.
+    /// Return None if it is a fragment of a message whose other fragments have not been received yet.
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<Option<Message>, DecodeError> {
+        match decode(bytes)? {
+            Message::Fragment(fragment) => self.add(fragment),
+            message => Ok(Some(message)),
+        }
+    }
+
+    fn add(&mut self, fragment: Fragment) -> Result<Option<Message>, DecodeError> {
+        if fragment.count < 2
+            || fragment.count as usize > MAX_FRAGMENTS
+            || fragment.index >= fragment.count
+            || fragment.bytes.len() > FRAGMENT_SIZE
+        {
+            return Err(DecodeError::InvalidFragment);
+        }
+        if !self.partial_messages.contains_key(&fragment.message_id) && self.partial_messages.len() >= MAX_PARTIAL_MESSAGES {
+            let oldest = self
+                .partial_messages
+                .iter()
+                .min_by_key(|(_, partial_message)| partial_message.first_received)
+                .map(|(message_id, _)| *message_id)
+                .expect("partial messages");
+            self.partial_messages.remove(&oldest);
+        }
+        let first_received = self.received_fragments;
+        self.received_fragments += 1;
+        let partial_message = self.partial_messages.entry(fragment.message_id).or_insert_with(|| PartialMessage {
+            count: fragment.count,
+            fragments: BTreeMap::new(),
+            first_received,
+        });
+        if partial_message.count != fragment.count {
+            self.partial_messages.remove(&fragment.message_id);
+            return Err(DecodeError::InvalidFragment);
+        }
+        partial_message.fragments.insert(fragment.index, fragment.bytes);
+        if partial_message.fragments.len() < partial_message.count as usize {
+            return Ok(None);
+        }
+        let partial_message = self.partial_messages.remove(&fragment.message_id).expect("complete message");
+        let bytes: Vec<u8> = partial_message.fragments.into_values().flatten().collect();
+        if message_id(&bytes) != fragment.message_id {
+            return Err(DecodeError::DigestMismatch(fragment.message_id));
+        }
+        match decode_with_limit(&bytes, MAX_FRAGMENTED_MESSAGE_SIZE)? {
+            // fragments cannot be nested
+            Message::Fragment(_) => Err(DecodeError::InvalidFragment),
+            message => Ok(Some(message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        committee::{Member, NodeId},
+        consensus_layer::{
+            artifacts::ConsensusMessage,
+            consensus_mode::ConsensusMode,
+            consensus_subcomponents::block_maker::{genesis_block, Block, Payload},
+            registry::RegistryUpdate,
+        },
+        crypto::{Hashed, PublicKey, Signed, Signer},
+        time_source::Time,
+    };
+
+    // block proposal carrying a registry update which adds `added` replicas, about 40 bytes each
+    fn large_message(added: u32) -> Message {
+        let update = RegistryUpdate {
+            effective_height: 201,
+            added: (10..10 + added)
+                .map(|node_id| (NodeId(node_id), Member { public_key: PublicKey::derived(NodeId(node_id)), network_id: None }))
+                .collect(),
+            removed: Default::default(),
+            byzantine_nodes_number: None,
+            disagreeing_nodes_number: None,
+        };
+        let payload = Payload::new(ConsensusMode::ICC, Some(Signed { content: update, signature: Default::default() }));
+        let block = Block::new(Hashed::crypto_hash(&genesis_block()), payload, 1, 0, None);
+        let proposal = Signer::with_derived_key(NodeId(1)).sign(Hashed::new(block));
+        Message::ConsensusMessage(ConsensusMessage::BlockProposal(proposal), Time::from_duration(std::time::Duration::ZERO))
+    }
+
+    #[test]
+    fn small_message_is_not_fragmented() {
+        let fragments = encode_fragments(&Message::KeepAliveMessage).unwrap();
+        assert_eq!(fragments.len(), 1);
+        assert!(matches!(Reassembler::default().decode(&fragments[0]), Ok(Some(Message::KeepAliveMessage))));
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let message = large_message(100);
+        let fragments = encode_fragments(&message).unwrap();
+        assert!(fragments.len() > 1);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= MAX_MESSAGE_SIZE));
+        let mut reassembler = Reassembler::default();
+        for fragment in fragments.iter().skip(1) {
+            assert!(matches!(reassembler.decode(fragment), Ok(None)));
+        }
+        let reassembled = reassembler.decode(&fragments[0]).unwrap().expect("complete message");
+        assert_eq!(encode_unchecked(&reassembled).unwrap(), encode_unchecked(&message).unwrap());
+        assert!(reassembler.partial_messages.is_empty());
+    }
+
+    #[test]
+    fn nested_fragments_are_rejected() {
+        let nested = Message::Fragment(Fragment { message_id: 0, index: 0, count: 2, bytes: vec![7; 5000] });
+        let mut reassembler = Reassembler::default();
+        let mut decoded = encode_fragments(&nested).unwrap().iter().map(|fragment| reassembler.decode(fragment)).collect::<Vec<_>>();
+        assert!(matches!(decoded.pop(), Some(Err(DecodeError::InvalidFragment))));
+    }
+
+    #[test]
+    fn too_large_message_is_rejected() {
+        assert!(matches!(encode_fragments(&large_message(4000)), Err(EncodeError::TooLarge(_))));
+    }
+
+    #[test]
+    fn oldest_partial_message_is_dropped() {
+        let mut reassembler = Reassembler::default();
+        for message_id in 0..=MAX_PARTIAL_MESSAGES as u64 {
+            let fragment = Fragment { message_id, index: 0, count: 2, bytes: vec![] };
+            assert!(matches!(reassembler.add(fragment), Ok(None)));
+        }
+        assert_eq!(reassembler.partial_messages.len(), MAX_PARTIAL_MESSAGES);
+        assert!(!reassembler.partial_messages.contains_key(&0));
+    }
+
+    #[test]
+    fn tampered_message_is_rejected() {
+        let fragments = encode_fragments(&large_message(100)).unwrap();
+        let mut reassembler = Reassembler::default();
+        let mut decoded = Vec::new();
+        for (index, bytes) in fragments.iter().enumerate() {
+            let mut fragment = match decode(bytes).unwrap() {
+                Message::Fragment(fragment) => fragment,
+                _ => panic!("fragment expected"),
+            };
+            if index == 0 {
+                fragment.bytes[FRAGMENT_SIZE - 1] ^= 1;
+            }
+            decoded.push(reassembler.add(fragment));
+        }
+        assert!(matches!(decoded.pop(), Some(Err(DecodeError::DigestMismatch(_)))));
+        assert!(reassembler.partial_messages.is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::BTreeMap, fmt::Write as _, path::Path, str::FromStr, time::Duration};
+
+use async_std::{fs, io::WriteExt};
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+
+use crate::{
+    artifact_manager::QueueMetrics,
+    committee::NodeId,
+    consensus_layer::{
+        fast_path_report::{FastPathReport, FastPathStatistics},
+        height_index::Height,
+        scheduler::SchedulerMetrics,
+        PoolState,
+    },
+    time_source::Time,
+    ArtifactDelays, HeightMetrics, SubnetParams,
+};
+
+/// Format of the file the benchmark results are written to.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    // a single JSON object
+    Json,
+    // one JSON object per line: the metadata of the run, then one line per height, then the other metrics
+    Jsonl,
+    // one row per height, preceded by the metadata of the run as a comment line
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format: {}, expected one of: json, jsonl, csv", s)),
+        }
+    }
+}
+
+/// Description of the run, so that results can be compared without knowing how the replica was started.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunMetadata {
+    pub run_id: String,
+    pub replica_number: NodeId,
+    pub subnet_params: SubnetParams,
+    // git revision the replica was built from, with a "-dirty" suffix if it had uncommitted changes
+    pub git_revision: String,
+    // system time at which the replica started running consensus
+    pub start_time: Time,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BenchmarkResult {
+    pub metadata: RunMetadata,
+    pub finalization_times: BTreeMap<Height, Option<HeightMetrics>>,
+    pub artifact_delays: ArtifactDelays,
+    pub scheduler_metrics: SchedulerMetrics,
+    pub queue_metrics: QueueMetrics,
+    pub pool_state: Option<PoolState>,  // None if the artifact manager was not started
+    pub fast_path: BTreeMap<Height, FastPathReport>,  // path through which each height of the finalized chain was finalized
+    pub fast_path_statistics: FastPathStatistics,
+    pub interrupted: bool,  // true if the replica was stopped by a signal before the end of the run
+}
+
+// Lines of the JSON lines format, distinguished by the "record" field.
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum ResultRecord<'a> {
+    Metadata(&'a RunMetadata),
+    Height {
+        height: Height,
+        metrics: &'a Option<HeightMetrics>,
+        fast_path: Option<&'a FastPathReport>,
+    },
+    Summary {
+        artifact_delays: &'a ArtifactDelays,
+        scheduler_metrics: &'a SchedulerMetrics,
+        queue_metrics: &'a QueueMetrics,
+        pool_state: &'a Option<PoolState>,
+        fast_path_statistics: &'a FastPathStatistics,
+        interrupted: bool,
+    },
+}
+
+/// Path of the output file: `{replica}` and `{run_id}` in the template are replaced by
+/// the replica number and the run id, so that replicas sharing a filesystem do not overwrite each other's results.
+pub fn output_path(template: &str, replica_number: NodeId, run_id: &str) -> String {
+    template
+        .replace("{replica}", &replica_number.to_string())
+        .replace("{run_id}", run_id)
+}
+
+/// Write the results to `path` in the given format, creating the missing directories.
+pub async fn write_results(path: &str, format: OutputFormat, result: &BenchmarkResult) {
+    let encoded = match format {
+        OutputFormat::Json => to_string(result).unwrap(),
+        OutputFormat::Jsonl => encode_jsonl(result),
+        OutputFormat::Csv => encode_csv(result),
+    };
+    if let Some(directory) = Path::new(path).parent() {
+        fs::create_dir_all(directory).await.expect("can create output directory");
+    }
+    let mut file = fs::File::create(path).await.expect("can create output file");
+    file.write_all(encoded.as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+    println!("\nResults written to: {}", path);
+}
+
+fn encode_jsonl(result: &BenchmarkResult) -> String {
+    let mut records = vec![ResultRecord::Metadata(&result.metadata)];
+    records.extend(
+        result
+            .finalization_times
+            .iter()
+            .map(|(height, metrics)| ResultRecord::Height {
+                height: *height,
+                metrics,
+                fast_path: result.fast_path.get(height),
+            }),
+    );
+    records.push(ResultRecord::Summary {
+        artifact_delays: &result.artifact_delays,
+        scheduler_metrics: &result.scheduler_metrics,
+        queue_metrics: &result.queue_metrics,
+        pool_state: &result.pool_state,
+        fast_path_statistics: &result.fast_path_statistics,
+        interrupted: result.interrupted,
+    });
+    records
+        .iter()
+        .map(|record| to_string(record).unwrap() + "\n")
+        .collect()
+}
+
+// Only the metrics of each height are written, latencies are in milliseconds and
+// phase timestamps in nanoseconds since UNIX epoch. Empty cells stand for missing values,
+// the fast path columns are empty for the heights which are not in the finalized chain.
+fn encode_csv(result: &BenchmarkResult) -> String {
+    let mut csv = format!("# {}\n", to_string(&result.metadata).unwrap());
+    csv.push_str("run_id,replica,height,finalization_type,latency_ms,end_to_end_latency_ms,round_start,first_proposal,own_notarization_share,notarization,acks_quorum,finalization_shares_quorum,finalization,finalization_path,fast_path_failure,acks,acks_threshold,finalization_shares,finalization_shares_threshold\n");
+    let millis = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+    let nanos = |time: Option<Time>| time.map_or(String::new(), |time| time.as_nanos_since_unix_epoch().to_string());
+    for (height, metrics) in &result.finalization_times {
+        write!(csv, "{},{},{}", result.metadata.run_id, result.metadata.replica_number, height).unwrap();
+        match metrics {
+            Some(metrics) => write!(
+                csv,
+                ",{:?},{},{},{},{},{},{},{},{},{}",
+                metrics.fp_finalization,
+                millis(metrics.latency),
+                metrics.end_to_end_latency.map_or(String::new(), millis),
+                nanos(metrics.phases.round_start),
+                nanos(metrics.phases.first_proposal),
+                nanos(metrics.phases.own_notarization_share),
+                nanos(metrics.phases.notarization),
+                nanos(metrics.phases.acks_quorum),
+                nanos(metrics.phases.finalization_shares_quorum),
+                nanos(Some(metrics.phases.finalization)),
+            )
+            .unwrap(),
+            None => csv.push_str(",,,,,,,,,,"),
+        }
+        match result.fast_path.get(height) {
+            Some(report) => writeln!(
+                csv,
+                ",{:?},{},{},{},{},{}",
+                report.finalization_path,
+                report.failure.map_or(String::new(), |failure| format!("{:?}", failure)),
+                report.acks_per_child.get(&report.block).copied().unwrap_or(0),
+                report.acks_threshold,
+                report.finalization_shares,
+                report.finalization_shares_threshold,
+            )
+            .unwrap(),
+            None => csv.push_str(",,,,,,\n"),
+        }
+    }
+    csv
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
-use std::sync::RwLock;
-use std::time::SystemTime;
+use std::sync::{OnceLock, RwLock};
+use std::time::{Instant, SystemTime};
 
 /// Time since UNIX_EPOCH (in nanoseconds). Just like 'std::time::Instant' or
 /// 'std::time::SystemTime', [Time] does not implement the [Default] trait.
@@ -13,6 +13,16 @@ impl Time {
     pub fn from_duration(t: Duration) -> Self {
         Time(t.as_nanos() as u64)
     }
+
+    /// Number of nanoseconds since the unix epoch.
+    pub fn as_nanos_since_unix_epoch(&self) -> u64 {
+        self.0
+    }
+
+    /// Return the duration since `earlier`, None if `earlier` is later than `self`.
+    pub fn checked_duration_since(&self, earlier: Time) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
 }
 impl std::ops::Add<Duration> for Time {
     type Output = Time;
@@ -21,13 +31,13 @@ impl std::ops::Add<Duration> for Time {
     }
 }
 
+/// Just like for [std::time::Instant], the difference saturates to zero if `other`
+/// is later than `self`.
 impl std::ops::Sub<Time> for Time {
     type Output = std::time::Duration;
 
     fn sub(self, other: Time) -> std::time::Duration {
-        let lhs = Duration::from_nanos(self.0);
-        let rhs = Duration::from_nanos(other.0);
-        lhs - rhs
+        Duration::from_nanos(self.0.saturating_sub(other.0))
     }
 }
 
@@ -40,9 +50,15 @@ pub trait TimeSource: Send + Sync {
     /// on the actual implementation. For [SysTimeSource] it is the UNIX
     /// epoch.
     fn get_relative_time(&self) -> Time;
+
+    /// Advance the time returned by [TimeSource::get_relative_time], called by the artifact manager
+    /// before each round of processing. Return an error if the time could not be updated.
+    fn update_time(&self) -> Result<(), ()> {
+        Ok(())
+    }
 }
 
-/// Time source using the system time.
+/// Time source using the monotonic clock of the replica (see [monotonic_time_now]).
 pub struct SysTimeSource {
     current_time: RwLock<Time>,
 }
@@ -52,17 +68,23 @@ impl SysTimeSource {
     /// Create a new [SysTimeSource].
     pub fn new() -> Self {
         SysTimeSource {
-            current_time: RwLock::new(system_time_now()),
+            current_time: RwLock::new(monotonic_time_now()),
         }
     }
+}
 
-    /// Update time to the new system time value.
+impl TimeSource for SysTimeSource {
+    fn get_relative_time(&self) -> Time {
+        *self.current_time.read().unwrap()
+    }
+
+    /// Update time to the new value of the monotonic clock.
     ///
-    /// It will skip the update and return an error if the new system time is
+    /// It will skip the update and return an error if the new time is
     /// less than the previous value.
-    pub fn update_time(&self) -> Result<(), ()> {
+    fn update_time(&self) -> Result<(), ()> {
         let mut current_time = self.current_time.write().unwrap();
-        let t = system_time_now();
+        let t = monotonic_time_now();
         if *current_time > t {
             Err(())
         } else {
@@ -72,12 +94,6 @@ impl SysTimeSource {
     }
 }
 
-impl TimeSource for SysTimeSource {
-    fn get_relative_time(&self) -> Time {
-        *self.current_time.read().unwrap()
-    }
-}
-
 /// Return the current system time. Note that the value returned is not
 /// guaranteed to be monotonic.
 pub fn system_time_now() -> Time {
@@ -87,6 +103,21 @@ pub fn system_time_now() -> Time {
             .expect("SystemTime is before UNIX EPOCH!")
 }
 
+// system time and instant of the first reading of the monotonic clock
+static MONOTONIC_CLOCK_ORIGIN: OnceLock<(Time, Instant)> = OnceLock::new();
+
+/// Return the current time of the monotonic clock of the replica, which starts at
+/// the system time of its first reading and then advances as [Instant] does.
+/// Unlike [system_time_now], it never goes backwards, therefore it is used for
+/// all the durations measured locally. Timestamps compared across replicas must
+/// use [system_time_now] instead, as the two clocks drift apart if the system
+/// time is adjusted.
+pub fn monotonic_time_now() -> Time {
+    let (origin, origin_instant) =
+        MONOTONIC_CLOCK_ORIGIN.get_or_init(|| (system_time_now(), Instant::now()));
+    *origin + origin_instant.elapsed()
+}
+
 pub fn get_absolute_end_time(starting_time: Time, relative_duration: Duration) -> Time {
     starting_time + relative_duration
 }
\ No newline at end of file
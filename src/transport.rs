@@ -0,0 +1,38 @@
+//! Network used by the consensus layer to exchange artifacts with the other replicas.
+//!
+//! The [ArtifactProcessorManager](crate::artifact_manager::ArtifactProcessorManager) only depends on the
+//! [Transport] trait, so consensus can run over the libp2p swarm of a [Peer](crate::network_layer::Peer),
+//! over in-memory channels (ex. to run several replicas within the same process) or over plain TCP connections.
+
+use std::time::Duration;
+
+use crate::consensus_layer::artifacts::ConsensusMessage;
+
+pub mod in_memory;
+pub mod tcp;
+
+/// Returned when the transport can no longer send or receive artifacts, ex. because the replica is stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportClosed;
+
+/// Artifact received from another replica, along with the id of the replica which sent it.
+pub type ReceivedArtifact = (String, ConsensusMessage);
+
+/// Exchange artifacts with the other replicas of the subnet, identified by strings
+/// (ex. the [PeerId](libp2p::PeerId) of libp2p peers).
+///
+/// The methods are called concurrently by the threads of the artifact manager, which
+/// receive and send artifacts independently.
+pub trait Transport: Send + Sync {
+    /// Send an artifact to all the other replicas.
+    /// Might block while the outgoing queue of the transport is full.
+    fn broadcast(&self, artifact: ConsensusMessage) -> Result<(), TransportClosed>;
+
+    /// Send an artifact to the replica with id `peer` only.
+    /// Might block while the outgoing queue of the transport is full.
+    fn send_to(&self, peer: &str, artifact: ConsensusMessage) -> Result<(), TransportClosed>;
+
+    /// Wait at most `timeout` for an artifact sent by another replica.
+    /// Return None if no artifact is received in time.
+    fn receive(&self, timeout: Duration) -> Result<Option<ReceivedArtifact>, TransportClosed>;
+}
@@ -0,0 +1,66 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use crate::{
+    consensus_layer::artifacts::ConsensusMessage,
+    transport::{ReceivedArtifact, Transport, TransportClosed},
+};
+
+/// [Transport] delivering artifacts through channels to the other replicas running in the same process.
+/// Artifacts are delivered reliably, in order and without delay.
+pub struct InMemoryTransport {
+    id: String,
+    peers: BTreeMap<String, Sender<ReceivedArtifact>>,
+    receiver: Receiver<ReceivedArtifact>,
+}
+
+impl InMemoryTransport {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Create the transports of the replicas with the given ids, each one connected to all the others.
+pub fn in_memory_network(ids: &[String]) -> Vec<InMemoryTransport> {
+    let channels: Vec<(Sender<ReceivedArtifact>, Receiver<ReceivedArtifact>)> =
+        ids.iter().map(|_| crossbeam_channel::unbounded()).collect();
+    ids.iter()
+        .zip(channels.iter())
+        .map(|(id, (_, receiver))| InMemoryTransport {
+            id: id.clone(),
+            peers: ids
+                .iter()
+                .zip(channels.iter())
+                .filter(|(peer, _)| *peer != id)
+                .map(|(peer, (sender, _))| (peer.clone(), sender.clone()))
+                .collect(),
+            receiver: receiver.clone(),
+        })
+        .collect()
+}
+
+impl Transport for InMemoryTransport {
+    fn broadcast(&self, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        for sender in self.peers.values() {
+            // a replica which has been stopped no longer receives artifacts, like a crashed one
+            sender.send((self.id.clone(), artifact.clone())).ok();
+        }
+        Ok(())
+    }
+
+    fn send_to(&self, peer: &str, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        if let Some(sender) = self.peers.get(peer) {
+            sender.send((self.id.clone(), artifact)).ok();
+        }
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Option<ReceivedArtifact>, TransportClosed> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(received) => Ok(Some(received)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(TransportClosed),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+
+use crate::{
+    artifact_manager::QueueMetrics,
+    consensus_layer::artifacts::ConsensusMessage,
+    network_layer::{codec::{self, Reassembler}, Message},
+    time_source::system_time_now,
+    transport::{ReceivedArtifact, Transport, TransportClosed},
+};
+
+// delay before the first attempt to reconnect to a peer, doubled after each failed attempt
+const INITIAL_RECONNECTION_BACKOFF: Duration = Duration::from_millis(100);
+// upper bound on the delay between two attempts to reconnect to a peer
+const MAX_RECONNECTION_BACKOFF: Duration = Duration::from_secs(5);
+// max length of the id sent by a replica when it opens a connection
+const MAX_ID_LENGTH: usize = 256;
+// interval at which the listener checks whether the transport has been dropped while no connection is accepted
+const ACCEPT_POLLING_INTERVAL: Duration = Duration::from_millis(50);
+
+/// [Transport] over a full mesh of TCP connections, one for each direction between every pair of replicas.
+///
+/// When a replica opens a connection, it sends its id followed by the artifacts, each one encoded with
+/// the wire format of the network layer (see [codec]) and prefixed by its length (u32, big endian).
+/// Lost connections are reopened with exponential backoff. While a peer is not reachable, the artifacts
+/// to be sent to it are queued, and dropped once its queue is full, as a disconnected libp2p peer misses
+/// the artifacts broadcasted meanwhile. Sending an artifact therefore never blocks, unlike with the other transports,
+/// and the dropped artifacts are counted in the [QueueMetrics] of the transport. An artifact split into fragments is
+/// queued as a whole, so that either all its fragments are sent to the peer or none.
+///
+/// Connections are only accepted from the configured peers: the id sent by the remote replica must be the
+/// one of a peer and the connection must come from the IP address of that peer. As the connections are not
+/// encrypted nor authenticated with the keys of the replicas, this does not protect against an attacker
+/// which can spoof the IP address of a peer.
+pub struct TcpTransport {
+    id: String,
+    // queue of the encoded artifacts (one or more fragments each) to be sent to each peer, consumed by the thread writing to the connection
+    peers: BTreeMap<String, Sender<Vec<Vec<u8>>>>,
+    receiver: Receiver<ReceivedArtifact>,
+    queue_metrics: Arc<Mutex<QueueMetrics>>,
+    // set when the transport is dropped, so that the listener and the threads reconnecting to the peers stop
+    closed: Arc<AtomicBool>,
+}
+
+impl TcpTransport {
+    /// Listen for connections on `listen_address` and connect to the `peers`, given by id.
+    /// At most `queue_capacity` artifacts are queued for each peer.
+    pub fn new(
+        id: String,
+        listen_address: SocketAddr,
+        peers: BTreeMap<String, SocketAddr>,
+        queue_capacity: usize,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen_address)?;
+        // the listener is polled so that it can stop once the transport is dropped
+        listener.set_nonblocking(true)?;
+        let (sender_incoming_artifact, receiver) = crossbeam_channel::unbounded();
+        let closed = Arc::new(AtomicBool::new(false));
+        // the threads stop once the transport is dropped, the threads reading from a connection
+        // stop once it is closed by the peer as they cannot receive more artifacts
+        let peer_addresses = Arc::new(peers.clone());
+        let listener_closed = Arc::clone(&closed);
+        thread::spawn(move || {
+            while !listener_closed.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, remote_address)) => {
+                        if let Err(e) = stream.set_nonblocking(false) {
+                            println!("Could not accept TCP connection, error: {:?}", e);
+                            continue;
+                        }
+                        let sender_incoming_artifact = sender_incoming_artifact.clone();
+                        let peer_addresses = Arc::clone(&peer_addresses);
+                        thread::spawn(move || read_artifacts(stream, remote_address, &peer_addresses, sender_incoming_artifact));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => sleep(ACCEPT_POLLING_INTERVAL),
+                    Err(e) => println!("Could not accept TCP connection, error: {:?}", e),
+                }
+            }
+        });
+
+        let peers = peers
+            .into_iter()
+            .map(|(peer, address)| {
+                let (sender, receiver) = crossbeam_channel::bounded(queue_capacity);
+                let local_id = id.clone();
+                let closed = Arc::clone(&closed);
+                thread::spawn(move || write_artifacts(local_id, address, receiver, closed));
+                (peer, sender)
+            })
+            .collect();
+
+        Ok(Self { id, peers, receiver, closed, queue_metrics: Arc::new(Mutex::new(QueueMetrics::default())) })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Metrics of the outgoing queues, to be shared with the artifact manager.
+    pub fn queue_metrics(&self) -> Arc<Mutex<QueueMetrics>> {
+        Arc::clone(&self.queue_metrics)
+    }
+
+    fn enqueue(&self, peer: &str, sender: &Sender<Vec<Vec<u8>>>, fragments: Vec<Vec<u8>>) {
+        match sender.try_send(fragments) {
+            Ok(()) => (),
+            // the peer is too slow or unreachable, the artifact is lost for it
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                *self.queue_metrics.lock().unwrap().outgoing_drops.entry(peer.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // encode the artifact in one or more messages, see [codec::encode_fragments]
+    fn encode(artifact: ConsensusMessage) -> Option<Vec<Vec<u8>>> {
+        let message = Message::ConsensusMessage(artifact, system_time_now());
+        match codec::encode_fragments(&message) {
+            Ok(fragments) => Some(fragments),
+            Err(e) => {
+                println!("Could not encode message: {:?}, error: {:?}", message, e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Transport for TcpTransport {
+    fn broadcast(&self, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        if let Some(fragments) = Self::encode(artifact) {
+            for (peer, sender) in &self.peers {
+                self.enqueue(peer, sender, fragments.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn send_to(&self, peer: &str, artifact: ConsensusMessage) -> Result<(), TransportClosed> {
+        if let (Some(sender), Some(fragments)) = (self.peers.get(peer), Self::encode(artifact)) {
+            self.enqueue(peer, sender, fragments);
+        }
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Option<ReceivedArtifact>, TransportClosed> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(received) => Ok(Some(received)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(TransportClosed),
+        }
+    }
+}
+
+fn write_frame(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    writer.flush()
+}
+
+fn read_frame(reader: &mut impl Read, max_length: usize) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > max_length {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes", length)));
+    }
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Read the id of the remote replica and then the artifacts it sends, until the connection is closed.
+// The connection is closed if the id is not the one of a peer or if the connection does not come from its address.
+fn read_artifacts(
+    stream: TcpStream,
+    remote_address: SocketAddr,
+    peer_addresses: &BTreeMap<String, SocketAddr>,
+    sender_incoming_artifact: Sender<ReceivedArtifact>,
+) {
+    let mut reader = BufReader::new(stream);
+    let peer = match read_frame(&mut reader, MAX_ID_LENGTH).map(String::from_utf8) {
+        Ok(Ok(peer)) => peer,
+        _ => {
+            println!("Closed TCP connection from replica which did not send its id");
+            return;
+        }
+    };
+    match peer_addresses.get(&peer) {
+        Some(address) if address.ip() == remote_address.ip() => (),
+        Some(address) => {
+            println!("Closed TCP connection from: {} claiming to be replica: {} with address: {}", remote_address, peer, address);
+            return;
+        }
+        None => {
+            println!("Closed TCP connection from: {} claiming to be unknown replica: {}", remote_address, peer);
+            return;
+        }
+    }
+    let mut reassembler = Reassembler::default();
+    loop {
+        let bytes = match read_frame(&mut reader, codec::MAX_MESSAGE_SIZE) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("TCP connection from replica: {} closed, error: {:?}", peer, e);
+                return;
+            }
+        };
+        match reassembler.decode(&bytes) {
+            Ok(Some(Message::ConsensusMessage(artifact, _))) => {
+                if sender_incoming_artifact.send((peer.clone(), artifact)).is_err() {
+                    // the transport has been dropped
+                    return;
+                }
+            }
+            // keep alive messages, and fragments of a message whose other fragments have not been received yet
+            Ok(_) => (),
+            // a malformed message must not stop the replica, it is dropped
+            Err(e) => println!("Dropped undecodable message from replica: {}, error: {:?}", peer, e),
+        }
+    }
+}
+
+// Open a connection to the peer and write the queued artifacts to it, reconnecting whenever the connection is lost.
+// While the peer is unreachable, the artifacts stay in the queue until the connection is reopened.
+// An artifact whose fragments could not all be written is written again as a whole on the new connection,
+// as the peer drops the fragments received on the lost one.
+fn write_artifacts(local_id: String, address: SocketAddr, receiver: Receiver<Vec<Vec<u8>>>, closed: Arc<AtomicBool>) {
+    let mut backoff = INITIAL_RECONNECTION_BACKOFF;
+    // artifact which could not be written because the connection was lost
+    let mut unsent: Option<Vec<Vec<u8>>> = None;
+    loop {
+        let connection = TcpStream::connect(address).and_then(|stream| {
+            stream.set_nodelay(true)?;
+            let mut writer = BufWriter::new(stream);
+            write_frame(&mut writer, local_id.as_bytes())?;
+            Ok(writer)
+        });
+        let mut writer = match connection {
+            Ok(writer) => {
+                backoff = INITIAL_RECONNECTION_BACKOFF;
+                writer
+            }
+            Err(_) => {
+                if closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                sleep(backoff);
+                backoff = std::cmp::min(backoff * 2, MAX_RECONNECTION_BACKOFF);
+                continue;
+            }
+        };
+        loop {
+            let fragments = match unsent.take() {
+                Some(fragments) => fragments,
+                None => match receiver.recv() {
+                    Ok(fragments) => fragments,
+                    Err(_) => return,
+                },
+            };
+            if let Err(e) = fragments.iter().try_for_each(|bytes| write_frame(&mut writer, bytes)) {
+                println!("TCP connection to: {} lost, error: {:?}", address, e);
+                unsent = Some(fragments);
+                break;
+            }
+        }
+    }
+}
@@ -154,8 +154,9 @@ P = 3
 T = 300
 D = 3000
 FICC = True
+BATCH = False   # apply the changes of all subcomponents within a single poll of the artifact manager
 
-print("\nStarting subnet running " + ("FICC" if FICC else "ICC") + f" with n={N}, f={F} and p={P}")
+print("\nStarting subnet running " + ("FICC" if FICC else "ICC") + f" with n={N}, f={F} and p={P}" + (" in batched mode" if BATCH else ""))
 
 for peer in peers:
     with open(".env.example", "r") as file:
@@ -176,10 +177,11 @@ for peer in peers:
 
 with open("docker-compose.yml", "r") as file:
     contents = file.readlines()
+    batch_flag = '"--batch_changes", ' if BATCH else ''
     if FICC:
-        contents[8] = '    command: ["--cod", "--r", $REPLICA_NUMBER, "--n", $TOTAL_REPLICA_NUMBER, "--f", $FAULTY_REPLICAS, "--p", $DISAGREEING_REPLICA, "--t", $EXECUTION_TIME, "--d", $NOTARIZATION_DELAY, "--broadcast_interval", "$BROADCAST_INTERVAL", "--port", $PORT]\n'
+        contents[8] = '    command: [' + batch_flag + '"--cod", "--r", $REPLICA_NUMBER, "--n", $TOTAL_REPLICA_NUMBER, "--f", $FAULTY_REPLICAS, "--p", $DISAGREEING_REPLICA, "--t", $EXECUTION_TIME, "--d", $NOTARIZATION_DELAY, "--broadcast_interval", "$BROADCAST_INTERVAL", "--port", $PORT]\n'
     else:
-        contents[8] = '    command: ["--r", $REPLICA_NUMBER, "--n", $TOTAL_REPLICA_NUMBER, "--f", $FAULTY_REPLICAS, "--p", $DISAGREEING_REPLICA, "--t", $EXECUTION_TIME, "--d", $NOTARIZATION_DELAY, "--broadcast_interval", "$BROADCAST_INTERVAL", "--port", $PORT]\n'
+        contents[8] = '    command: [' + batch_flag + '"--r", $REPLICA_NUMBER, "--n", $TOTAL_REPLICA_NUMBER, "--f", $FAULTY_REPLICAS, "--p", $DISAGREEING_REPLICA, "--t", $EXECUTION_TIME, "--d", $NOTARIZATION_DELAY, "--broadcast_interval", "$BROADCAST_INTERVAL", "--port", $PORT]\n'
 
 with open("docker-compose.yml", "w") as file:
     file.writelines(contents)
@@ -227,11 +229,11 @@ for p in processes:
 print("\nReplicas stopped")
 
 now = int(time.time())
-folder = f'{("FICC" if FICC else "ICC")}_{N}_{F}_{P}_{D}_{T}_{now}'
+folder = f'{("FICC" if FICC else "ICC")}{("_batch" if BATCH else "")}_{N}_{F}_{P}_{D}_{T}_{now}'
 subprocess.run(f'cd benchmark && mkdir {folder}', shell=True, stdout=subprocess.DEVNULL)
 
 for peer in peers:
-    get_benchmark_results_cmd = f'scp -i ./keys/{peer["key_file"]} ubuntu@{peer["ip"]}:consensus_on_demand/benchmark/benchmark_results.json benchmark/{("FICC" if FICC else "ICC")}_{N}_{F}_{P}_{D}_{T}_{now}/benchmark_results_{peer["number"]}.json'
+    get_benchmark_results_cmd = f'scp -i ./keys/{peer["key_file"]} ubuntu@{peer["ip"]}:consensus_on_demand/benchmark/benchmark_results.json benchmark/{folder}/benchmark_results_{peer["number"]}.json'
     subprocess.run(get_benchmark_results_cmd, shell=True, stdout=subprocess.DEVNULL)
 
 print(f'\nResults written in folder benchmark/{folder}')
@@ -0,0 +1,70 @@
+//! Replicas running in the same process and exchanging artifacts through in-memory transports, without libp2p.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, RwLock},
+    thread::sleep,
+    time::Duration,
+};
+
+use consensus_on_demand::{
+    artifact_manager::ArtifactProcessorManager,
+    committee::{Committee, NodeId},
+    crypto::Signer,
+    consensus_layer::{pool::ConsensusPoolImpl, scheduler::SchedulerMetrics},
+    time_source::SysTimeSource,
+    transport::in_memory::in_memory_network,
+    SubnetParams, SubnetParamsBuilder,
+};
+
+const N: u32 = 4;
+
+// Run the replicas for a few seconds and return the finalized height of each of them.
+fn run(configure: impl Fn(SubnetParamsBuilder) -> SubnetParamsBuilder) -> Vec<u64> {
+    let subnet_params = configure(
+        SubnetParams::builder(Committee::with_size(N))
+            .byzantine_nodes_number(1)
+            .disagreeing_nodes_number(0)
+            .artifact_delay(100),
+    )
+    .build()
+    .expect("valid subnet parameters");
+    let ids: Vec<String> = (1..=N).map(|id| id.to_string()).collect();
+    let mut managers: Vec<ArtifactProcessorManager> = in_memory_network(&ids)
+        .into_iter()
+        .zip(1..=N)
+        .map(|(transport, id)| {
+            ArtifactProcessorManager::new(
+                Signer::with_derived_key(NodeId(id)),
+                subnet_params.clone(),
+                Arc::new(SysTimeSource::new()),
+                Arc::new(transport),
+                Arc::new(RwLock::new(BTreeMap::new())),
+                Arc::new(RwLock::new(SchedulerMetrics::new())),
+                Arc::new(RwLock::new(BTreeMap::new())),
+                Arc::new(Mutex::new(Default::default())),
+                Arc::new(RwLock::new(ConsensusPoolImpl::new(subnet_params.clone()))),
+            )
+        })
+        .collect();
+    sleep(Duration::from_secs(5));
+    managers
+        .iter_mut()
+        .map(|manager| manager.stop().expect("manager is running").finalized_height)
+        .collect()
+}
+
+#[test]
+fn icc_finalizes() {
+    assert!(run(|builder| builder).iter().all(|finalized_height| *finalized_height >= 5));
+}
+
+#[test]
+fn ficc_finalizes() {
+    assert!(run(|builder| builder.consensus_on_demand(true)).iter().all(|finalized_height| *finalized_height >= 5));
+}
+
+#[test]
+fn adaptive_consensus_finalizes() {
+    assert!(run(|builder| builder.adaptive_consensus(true)).iter().all(|finalized_height| *finalized_height >= 5));
+}
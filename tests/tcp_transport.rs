@@ -0,0 +1,105 @@
+//! Artifacts exchanged between replicas through TCP transports on the loopback interface.
+
+use std::{collections::BTreeMap, net::SocketAddr, thread::sleep, time::Duration};
+
+use consensus_on_demand::{
+    committee::{Member, NodeId},
+    consensus_layer::{
+        artifacts::ConsensusMessage,
+        consensus_mode::ConsensusMode,
+        consensus_subcomponents::block_maker::{genesis_block, Block, Payload},
+        registry::RegistryUpdate,
+    },
+    crypto::{Hashed, PublicKey, Signed, Signer},
+    transport::{tcp::TcpTransport, Transport},
+};
+
+fn address(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn proposal_with_payload(height: u64, payload: Payload) -> ConsensusMessage {
+    let block = Block::new(Hashed::crypto_hash(&genesis_block()), payload, height, 0, None);
+    ConsensusMessage::BlockProposal(Signer::with_derived_key(NodeId(1)).sign(Hashed::new(block)))
+}
+
+fn proposal(height: u64) -> ConsensusMessage {
+    proposal_with_payload(height, Payload::new(ConsensusMode::FICC, None))
+}
+
+// proposal carrying a registry update which adds 100 replicas, too large to be sent in a single message
+fn large_proposal() -> ConsensusMessage {
+    let update = RegistryUpdate {
+        effective_height: 201,
+        added: (10..110)
+            .map(|node_id| (NodeId(node_id), Member { public_key: PublicKey::derived(NodeId(node_id)), network_id: None }))
+            .collect(),
+        removed: Default::default(),
+        byzantine_nodes_number: None,
+        disagreeing_nodes_number: None,
+    };
+    proposal_with_payload(1, Payload::new(ConsensusMode::ICC, Some(Signed { content: update, signature: Default::default() })))
+}
+
+fn receive_all(transport: &TcpTransport) -> Vec<ConsensusMessage> {
+    let mut artifacts = Vec::new();
+    while let Some((_, artifact)) = transport.receive(Duration::from_secs(2)).expect("transport is open") {
+        artifacts.push(artifact);
+    }
+    artifacts
+}
+
+#[test]
+fn queued_artifacts_are_sent_once_peer_is_reachable() {
+    let sender = TcpTransport::new("1".to_string(), address(47011), BTreeMap::from([("2".to_string(), address(47021))]), 10)
+        .expect("listening");
+    for height in 1..=3 {
+        sender.broadcast(proposal(height)).expect("transport is open");
+    }
+    // the peer starts after a few failed connection attempts
+    sleep(Duration::from_millis(500));
+    let receiver = TcpTransport::new("2".to_string(), address(47021), BTreeMap::from([("1".to_string(), address(47011))]), 10)
+        .expect("listening");
+    assert_eq!(receive_all(&receiver), (1..=3).map(proposal).collect::<Vec<_>>());
+}
+
+#[test]
+fn connections_from_unknown_replicas_are_closed() {
+    let receiver = TcpTransport::new("2".to_string(), address(47121), BTreeMap::from([("1".to_string(), address(47111))]), 10)
+        .expect("listening");
+    // replica 3 is not a peer of the receiver
+    let unknown = TcpTransport::new("3".to_string(), address(47131), BTreeMap::from([("2".to_string(), address(47121))]), 10)
+        .expect("listening");
+    unknown.broadcast(proposal(1)).expect("transport is open");
+    assert!(receive_all(&receiver).is_empty());
+}
+
+#[test]
+fn listener_stops_when_transport_is_dropped() {
+    let transport = TcpTransport::new("1".to_string(), address(47211), BTreeMap::new(), 10).expect("listening");
+    drop(transport);
+    sleep(Duration::from_millis(200));
+    TcpTransport::new("1".to_string(), address(47211), BTreeMap::new(), 10).expect("listening again on the same address");
+}
+
+#[test]
+fn large_artifacts_are_sent_in_fragments() {
+    let sender = TcpTransport::new("1".to_string(), address(47311), BTreeMap::from([("2".to_string(), address(47321))]), 10)
+        .expect("listening");
+    let receiver = TcpTransport::new("2".to_string(), address(47321), BTreeMap::from([("1".to_string(), address(47311))]), 10)
+        .expect("listening");
+    sender.broadcast(large_proposal()).expect("transport is open");
+    assert_eq!(receive_all(&receiver), vec![large_proposal()]);
+}
+
+#[test]
+fn artifacts_dropped_for_unreachable_peer_are_counted() {
+    let sender = TcpTransport::new("1".to_string(), address(47411), BTreeMap::from([("2".to_string(), address(47421))]), 2)
+        .expect("listening");
+    for height in 1..=4 {
+        sender.broadcast(proposal(height)).expect("transport is open");
+    }
+    sender.broadcast(large_proposal()).expect("transport is open");
+    // the fragments of the large proposal count as a single dropped artifact
+    assert_eq!(sender.queue_metrics().lock().unwrap().outgoing_drops, BTreeMap::from([("2".to_string(), 3)]));
+}